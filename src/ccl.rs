@@ -0,0 +1,1156 @@
+//! CCL, the Common Command Language of ISO 8777 as used by YAZ,
+//! converted to and from Type-1 queries with qualifiers in the format of
+//! YAZ's `ccl.bib`:
+//!
+//! ```text
+//! # qualifier  attributes
+//! ti    u=4 s=pw t=l,r
+//! au    u=1003 s=pw
+//! date  u=30 r=o
+//! kw    u=1016 s=al
+//! term  s=pw
+//! any   ti au
+//! ```
+//!
+//! Attributes are `type=value`, with types given by number or as `u`
+//! (use), `r` (relation), `p` (position), `s` (structure), `t`
+//! (truncation) and `c` (completeness). Some values are symbolic: `r=o`
+//! allows the ordered relations `<`, `<=`, `>`, `>=` and `<>`, which are
+//! rejected otherwise; `s=pw` searches several words as a phrase and one
+//! as a word, `s=w` and `s=p` always as a word or phrase, and `s=al` and
+//! `s=ol` search each word, combined with `and` or `or`; `t=l,r,b,z`
+//! lists the truncation allowed, left, right, both or Z39.58, all of it
+//! if not given. A line naming other qualifiers, such as `any`, searches
+//! all of them with `or`, as does `ti,au=x`. Unqualified terms use the
+//! qualifier `term` if there is one.
+//!
+//! Queries are search terms, `qualifier=term` with the relations above,
+//! `qualifier=(query)`, and combinations with `and`, `or`, `not`, `%n`
+//! and `!n` (proximity within `n` words, unordered or ordered), which
+//! all bind equally from left to right. Adjacent terms are combined with
+//! `and`. A `?` at the start or end of a term truncates it; `?` and `#`
+//! elsewhere use Z39.58 truncation.
+//!
+//! [`CclTransform::ccl_to_cql`] and [`CclTransform::cql_to_ccl`] bridge
+//! to CQL through Type-1 queries and a [`PqfTransform`].
+
+use crate::node::CqlNode;
+use crate::pqf::PqfError;
+use crate::pqf::PqfTransform;
+use crate::rpn::AttributeElement;
+use crate::rpn::AttributeValue;
+use crate::rpn::AttributesPlusTerm;
+use crate::rpn::Operand;
+use crate::rpn::Operator;
+use crate::rpn::ProximityOperator;
+use crate::rpn::ProximityUnit;
+use crate::rpn::RpnQuery;
+use crate::rpn::RpnStructure;
+use crate::rpn::Term as RpnTerm;
+use crate::rpn::BIB1;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error from reading qualifiers or converting a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CclError {
+    /// A qualifier line with an attribute that cannot be read.
+    InvalidQualifier {
+        line: usize,
+        text: String,
+    },
+    /// CCL text that cannot be parsed.
+    Syntax {
+        offset: usize,
+        message: String,
+    },
+    UnknownQualifier(String),
+    /// The relation is not allowed for the qualifier.
+    UnsupportedRelation(String),
+    /// The truncation is not allowed for the qualifier.
+    UnsupportedMasking(String),
+    /// An attribute with no matching qualifier when writing CCL.
+    UnsupportedAttribute(String),
+    /// An operator with no CCL equivalent.
+    UnsupportedOperator(String),
+    /// A term with no CCL equivalent.
+    UnsupportedTerm(String),
+    /// Conversion between CQL and Type-1 queries failed.
+    Pqf(PqfError),
+}
+
+impl fmt::Display for CclError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CclError::InvalidQualifier { line, text } => {
+                write!(f, "invalid qualifier at line {}: {}", line, text)
+            }
+            CclError::Syntax { offset, message } => write!(f, "{} at offset {}", message, offset),
+            CclError::UnknownQualifier(s) => write!(f, "unknown qualifier {}", s),
+            CclError::UnsupportedRelation(s) => write!(f, "unsupported relation {}", s),
+            CclError::UnsupportedMasking(s) => write!(f, "unsupported truncation in {}", s),
+            CclError::UnsupportedAttribute(s) => write!(f, "no qualifier for attribute {}", s),
+            CclError::UnsupportedOperator(s) => write!(f, "unsupported operator {}", s),
+            CclError::UnsupportedTerm(s) => write!(f, "unsupported term {}", s),
+            CclError::Pqf(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CclError {}
+
+/// Bib-1 relation attribute values of the CCL relations.
+const RELATIONS: [(&str, i64); 6] = [
+    ("<", 1),
+    ("<=", 2),
+    ("=", 3),
+    (">=", 4),
+    (">", 5),
+    ("<>", 6),
+];
+
+/// Truncation attribute values: right, left, both and Z39.58.
+const RIGHT: i64 = 1;
+const LEFT: i64 = 2;
+const BOTH: i64 = 3;
+const Z3958: i64 = 104;
+
+/// Handling of several words, from the symbolic values of `s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Words {
+    /// As given by the structure attributes of the qualifier, if any.
+    Default,
+    PhraseOrWord,
+    Word,
+    Phrase,
+    AndList,
+    OrList,
+}
+
+#[derive(Debug, Clone)]
+struct Qualifier {
+    name: String,
+    attributes: Vec<AttributeElement>,
+    words: Words,
+    ordered: bool,
+    /// Truncation values allowed, or `None` for all.
+    truncation: Option<Vec<i64>>,
+    /// Qualifiers searched instead of this one.
+    aliases: Vec<String>,
+}
+
+impl Qualifier {
+    fn parse(no: usize, line: &str) -> Result<Qualifier, CclError> {
+        let invalid = || CclError::InvalidQualifier {
+            line: no,
+            text: String::from(line),
+        };
+        let mut specs = line.split_whitespace();
+        let name = specs.next().ok_or_else(invalid)?;
+        let mut res = Qualifier {
+            name: String::from(name),
+            attributes: Vec::new(),
+            words: Words::Default,
+            ordered: false,
+            truncation: None,
+            aliases: Vec::new(),
+        };
+        for spec in specs {
+            let Some((kind, value)) = spec.split_once('=') else {
+                res.aliases.push(String::from(spec));
+                continue;
+            };
+            let attribute_type = match kind.to_ascii_lowercase().as_str() {
+                "u" => 1,
+                "r" => 2,
+                "p" => 3,
+                "s" => 4,
+                "t" => 5,
+                "c" => 6,
+                _ => kind.parse().map_err(|_| invalid())?,
+            };
+            if let Ok(value) = value.parse() {
+                res.attributes
+                    .push(AttributeElement::numeric(attribute_type, value));
+                continue;
+            }
+            match (attribute_type, value.to_ascii_lowercase().as_str()) {
+                (2, "o" | "r") => res.ordered = true,
+                (4, "pw") => res.words = Words::PhraseOrWord,
+                (4, "w") => res.words = Words::Word,
+                (4, "p") => res.words = Words::Phrase,
+                (4, "al") => res.words = Words::AndList,
+                (4, "ol") => res.words = Words::OrList,
+                (5, values) => {
+                    let allowed: Result<Vec<i64>, CclError> = values
+                        .split(',')
+                        .map(|v| match v {
+                            "l" => Ok(LEFT),
+                            "r" => Ok(RIGHT),
+                            "b" => Ok(BOTH),
+                            "z" => Ok(Z3958),
+                            "n" => Ok(100),
+                            _ => Err(invalid()),
+                        })
+                        .collect();
+                    res.truncation = Some(allowed?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        let specs = line.split_whitespace().skip(1).filter(|s| s.contains('='));
+        if !res.aliases.is_empty() && specs.count() > 0 {
+            return Err(invalid());
+        }
+        Ok(res)
+    }
+}
+
+/// Replace the attribute of type `attribute_type`, or add it.
+fn set_attribute(attributes: &mut Vec<AttributeElement>, attribute_type: i64, value: i64) {
+    attributes.retain(|a| a.attribute_type != attribute_type);
+    attributes.push(AttributeElement::numeric(attribute_type, value));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Word(String),
+    Quoted(String),
+    LParen,
+    RParen,
+    Comma,
+    Relation(&'static str),
+    Op(Operator),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    offset: usize,
+}
+
+fn syntax(offset: usize, message: &str) -> CclError {
+    CclError::Syntax {
+        offset,
+        message: String::from(message),
+    }
+}
+
+/// Characters that end a word.
+const SPECIAL: &str = "()=<>,\"%!";
+
+fn lex(text: &str) -> Result<Vec<Token>, CclError> {
+    let mut res = Vec::new();
+    let mut it = text.char_indices().peekable();
+    while let Some((offset, ch)) = it.next() {
+        let tok = match ch {
+            _ if ch.is_whitespace() => continue,
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            ',' => Tok::Comma,
+            '=' => Tok::Relation("="),
+            '<' => match it.peek() {
+                Some((_, '=')) => {
+                    it.next();
+                    Tok::Relation("<=")
+                }
+                Some((_, '>')) => {
+                    it.next();
+                    Tok::Relation("<>")
+                }
+                _ => Tok::Relation("<"),
+            },
+            '>' => match it.peek() {
+                Some((_, '=')) => {
+                    it.next();
+                    Tok::Relation(">=")
+                }
+                _ => Tok::Relation(">"),
+            },
+            '%' | '!' => {
+                let mut digits = String::new();
+                while let Some((_, d)) = it.peek().filter(|(_, d)| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    it.next();
+                }
+                let distance = match digits.as_str() {
+                    "" => 1,
+                    _ => digits
+                        .parse()
+                        .map_err(|_| syntax(offset, "invalid distance"))?,
+                };
+                Tok::Op(Operator::Prox(ProximityOperator {
+                    exclusion: Some(false),
+                    distance,
+                    ordered: ch == '!',
+                    relation_type: 2,
+                    unit: ProximityUnit::Known(2),
+                }))
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match it.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            if let Some((_, ch)) = it.next() {
+                                s.push(ch);
+                            }
+                        }
+                        Some((_, ch)) => s.push(ch),
+                        None => return Err(syntax(offset, "unterminated string")),
+                    }
+                }
+                Tok::Quoted(s)
+            }
+            _ => {
+                let mut s = String::from(ch);
+                while let Some((_, ch)) = it
+                    .peek()
+                    .filter(|(_, ch)| !ch.is_whitespace() && !SPECIAL.contains(*ch))
+                {
+                    s.push(*ch);
+                    it.next();
+                }
+                match s.to_ascii_lowercase().as_str() {
+                    "and" => Tok::Op(Operator::And),
+                    "or" => Tok::Op(Operator::Or),
+                    "not" => Tok::Op(Operator::AndNot),
+                    _ => Tok::Word(s),
+                }
+            }
+        };
+        res.push(Token { tok, offset });
+    }
+    res.push(Token {
+        tok: Tok::End,
+        offset: text.len(),
+    });
+    Ok(res)
+}
+
+/// Character of a term, and whether it is a truncation mark.
+type TermChar = (char, bool);
+
+/// Parser of CCL text into a Type-1 query.
+struct CclReader<'a> {
+    transform: &'a CclTransform,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> CclReader<'a> {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].offset
+    }
+
+    /// Whether the word at `pos` is a qualifier, followed by `,` or a
+    /// relation.
+    fn is_qualifier(&self, pos: usize) -> bool {
+        matches!(self.tokens[pos].tok, Tok::Word(_))
+            && matches!(self.tokens[pos + 1].tok, Tok::Comma | Tok::Relation(_))
+    }
+
+    /// The qualifiers to search for `name`, with aliases expanded.
+    fn lookup(&self, name: &str, depth: usize) -> Result<Vec<&'a Qualifier>, CclError> {
+        let unknown = || CclError::UnknownQualifier(String::from(name));
+        let qualifier = self.transform.get(name).ok_or_else(unknown)?;
+        if qualifier.aliases.is_empty() {
+            return Ok(vec![qualifier]);
+        }
+        if depth > 8 {
+            return Err(unknown());
+        }
+        let mut res = Vec::new();
+        for alias in &qualifier.aliases {
+            res.extend(self.lookup(alias, depth + 1)?);
+        }
+        Ok(res)
+    }
+
+    fn query(&mut self, qualifiers: &[&'a Qualifier]) -> Result<RpnStructure, CclError> {
+        let mut left = self.elements(qualifiers)?;
+        while let Tok::Op(op) = self.peek() {
+            let op = op.clone();
+            self.pos += 1;
+            let right = self.elements(qualifiers)?;
+            left = RpnStructure::RpnOp {
+                left: Box::new(left),
+                right: Box::new(right),
+                op,
+            };
+        }
+        Ok(left)
+    }
+
+    /// Adjacent elements, combined with `and`.
+    fn elements(&mut self, qualifiers: &[&'a Qualifier]) -> Result<RpnStructure, CclError> {
+        let mut left = self.element(qualifiers)?;
+        while let Tok::Word(_) | Tok::Quoted(_) | Tok::LParen = self.peek() {
+            let right = self.element(qualifiers)?;
+            left = RpnStructure::RpnOp {
+                left: Box::new(left),
+                right: Box::new(right),
+                op: Operator::And,
+            };
+        }
+        Ok(left)
+    }
+
+    fn element(&mut self, qualifiers: &[&'a Qualifier]) -> Result<RpnStructure, CclError> {
+        let offset = self.offset();
+        match self.peek() {
+            Tok::LParen => {
+                self.pos += 1;
+                let res = self.query(qualifiers)?;
+                if *self.peek() != Tok::RParen {
+                    return Err(syntax(self.offset(), "expected )"));
+                }
+                self.pos += 1;
+                Ok(res)
+            }
+            Tok::Word(_) if self.is_qualifier(self.pos) => {
+                let mut found = Vec::new();
+                while let Tok::Word(name) = self.peek() {
+                    found.extend(self.lookup(name, 0)?);
+                    self.pos += 1;
+                    match self.peek() {
+                        Tok::Comma => self.pos += 1,
+                        _ => break,
+                    }
+                }
+                let Tok::Relation(relation) = *self.peek() else {
+                    return Err(syntax(self.offset(), "expected relation"));
+                };
+                self.pos += 1;
+                if *self.peek() == Tok::LParen && relation == "=" {
+                    self.pos += 1;
+                    let res = self.query(&found)?;
+                    if *self.peek() != Tok::RParen {
+                        return Err(syntax(self.offset(), "expected )"));
+                    }
+                    self.pos += 1;
+                    return Ok(res);
+                }
+                self.term(&found, relation)
+            }
+            Tok::Word(_) | Tok::Quoted(_) => match qualifiers {
+                [] => match self.transform.get("term") {
+                    Some(term) => self.term(&[term], "="),
+                    None => self.term(&[], "="),
+                },
+                _ => self.term(qualifiers, "="),
+            },
+            _ => Err(syntax(offset, "expected search term")),
+        }
+    }
+
+    /// Words and strings up to the next operator or qualifier.
+    fn term(
+        &mut self,
+        qualifiers: &[&Qualifier],
+        relation: &str,
+    ) -> Result<RpnStructure, CclError> {
+        let offset = self.offset();
+        let mut chars: Vec<TermChar> = Vec::new();
+        loop {
+            let (s, masks) = match self.peek() {
+                Tok::Word(_) if self.is_qualifier(self.pos) => break,
+                Tok::Word(s) => (s.clone(), true),
+                Tok::Quoted(s) => (s.clone(), false),
+                _ => break,
+            };
+            if !chars.is_empty() {
+                chars.push((' ', false));
+            }
+            chars.extend(s.chars().map(|ch| (ch, masks && (ch == '?' || ch == '#'))));
+            self.pos += 1;
+        }
+        if offset == self.offset() {
+            return Err(syntax(offset, "expected search term"));
+        }
+        if qualifiers.is_empty() {
+            return operand(None, relation, &chars);
+        }
+        let queries: Result<Vec<RpnStructure>, CclError> = qualifiers
+            .iter()
+            .map(|q| operand(Some(q), relation, &chars))
+            .collect();
+        Ok(combine(Operator::Or, queries?))
+    }
+}
+
+/// `queries` combined with `op`, from left to right.
+fn combine(op: Operator, queries: Vec<RpnStructure>) -> RpnStructure {
+    let mut it = queries.into_iter();
+    let first = it.next().expect("at least one query");
+    it.fold(first, |left, right| RpnStructure::RpnOp {
+        left: Box::new(left),
+        right: Box::new(right),
+        op: op.clone(),
+    })
+}
+
+fn text(chars: &[TermChar]) -> String {
+    chars.iter().map(|(ch, _)| *ch).collect()
+}
+
+/// The term without truncation marks, and the truncation to use.
+fn truncation(chars: &[TermChar]) -> Result<(String, Option<i64>), CclError> {
+    let n = chars.len();
+    if !chars.iter().any(|(_, mask)| *mask) {
+        return Ok((text(chars), None));
+    }
+    let left = chars[0] == ('?', true);
+    let right = n > 1 && chars[n - 1] == ('?', true);
+    let from = usize::from(left);
+    let to = n - usize::from(right);
+    if !chars[from..to].iter().any(|(_, mask)| *mask) && from < to {
+        let value = match (left, right) {
+            (true, true) => BOTH,
+            (true, false) => LEFT,
+            _ => RIGHT,
+        };
+        return Ok((text(&chars[from..to]), Some(value)));
+    }
+    let literal = chars
+        .iter()
+        .any(|(ch, mask)| !mask && (*ch == '?' || *ch == '#'));
+    if literal {
+        return Err(CclError::UnsupportedTerm(text(chars)));
+    }
+    Ok((text(chars), Some(Z3958)))
+}
+
+/// Operand for a term with `qualifier`, split into words if it says so.
+fn operand(
+    qualifier: Option<&Qualifier>,
+    relation: &str,
+    chars: &[TermChar],
+) -> Result<RpnStructure, CclError> {
+    let words = qualifier.map_or(Words::Default, |q| q.words);
+    let list = match words {
+        Words::AndList => Some(Operator::And),
+        Words::OrList => Some(Operator::Or),
+        _ => None,
+    };
+    if let Some(op) = list {
+        let queries: Result<Vec<RpnStructure>, CclError> = chars
+            .split(|(ch, _)| ch.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(|word| attr_term(qualifier, relation, word))
+            .collect();
+        let queries = queries?;
+        if !queries.is_empty() {
+            return Ok(combine(op, queries));
+        }
+    }
+    attr_term(qualifier, relation, chars)
+}
+
+fn attr_term(
+    qualifier: Option<&Qualifier>,
+    relation: &str,
+    chars: &[TermChar],
+) -> Result<RpnStructure, CclError> {
+    let mut attributes = Vec::new();
+    let mut words = Words::Default;
+    if let Some(q) = qualifier {
+        if relation != "=" && !q.ordered {
+            return Err(CclError::UnsupportedRelation(String::from(relation)));
+        }
+        attributes = q.attributes.clone();
+        words = q.words;
+    } else if relation != "=" {
+        return Err(CclError::UnsupportedRelation(String::from(relation)));
+    }
+    if relation != "=" {
+        let (_, value) = RELATIONS.iter().find(|(r, _)| *r == relation).unwrap();
+        set_attribute(&mut attributes, 2, *value);
+    }
+    let (term, truncation) = truncation(chars)?;
+    let several = term.split_whitespace().nth(1).is_some();
+    match words {
+        Words::PhraseOrWord if several => set_attribute(&mut attributes, 4, 1),
+        Words::Phrase => set_attribute(&mut attributes, 4, 1),
+        Words::PhraseOrWord | Words::Word | Words::AndList | Words::OrList => {
+            set_attribute(&mut attributes, 4, 2)
+        }
+        Words::Default => {}
+    }
+    if let Some(value) = truncation {
+        let allowed = qualifier
+            .and_then(|q| q.truncation.as_ref())
+            .is_none_or(|allowed| allowed.contains(&value));
+        if !allowed {
+            return Err(CclError::UnsupportedMasking(text(chars)));
+        }
+        set_attribute(&mut attributes, 5, value);
+    }
+    Ok(RpnStructure::Operand(Operand::AttrTerm(
+        AttributesPlusTerm {
+            attributes,
+            term: RpnTerm::General(term.into_bytes()),
+        },
+    )))
+}
+
+/// Whether `term` must be quoted to be read back as one term.
+fn needs_quotes(term: &str) -> bool {
+    term.is_empty()
+        || term
+            .chars()
+            .any(|ch| ch.is_whitespace() || SPECIAL.contains(ch) || ch == '?' || ch == '#')
+        || ["and", "or", "not"]
+            .iter()
+            .any(|op| term.eq_ignore_ascii_case(op))
+}
+
+fn quote(term: &str) -> String {
+    let mut res = String::from("\"");
+    for ch in term.chars() {
+        if ch == '"' || ch == '\\' {
+            res.push('\\');
+        }
+        res.push(ch);
+    }
+    res.push('"');
+    res
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CclTransform {
+    qualifiers: Vec<Qualifier>,
+}
+
+impl CclTransform {
+    pub fn new() -> CclTransform {
+        CclTransform::default()
+    }
+
+    /// Read qualifiers in the format of YAZ's `ccl.bib`: a qualifier
+    /// name and its attributes on each line, with `#` starting a comment
+    /// line.
+    pub fn from_qualifiers(text: &str) -> Result<CclTransform, CclError> {
+        let mut res = CclTransform::new();
+        for (no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            res.qualifiers.push(Qualifier::parse(no + 1, line)?);
+        }
+        Ok(res)
+    }
+
+    /// Qualifier `name`, case insensitive.
+    fn get(&self, name: &str) -> Option<&Qualifier> {
+        self.qualifiers
+            .iter()
+            .find(|q| q.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Parse CCL into a Type-1 query with the Bib-1 attribute set.
+    pub fn ccl_to_rpn(&self, ccl: &str) -> Result<RpnQuery, CclError> {
+        let mut reader = CclReader {
+            transform: self,
+            tokens: lex(ccl)?,
+            pos: 0,
+        };
+        let rpn = reader.query(&[])?;
+        if *reader.peek() != Tok::End {
+            return Err(syntax(reader.offset(), "expected end of query"));
+        }
+        Ok(RpnQuery::new(rpn))
+    }
+
+    /// The qualifier, relation and term of an operand, as CCL.
+    fn attr_term(&self, at: &AttributesPlusTerm) -> Result<String, CclError> {
+        let unsupported = |a: &AttributeElement| CclError::UnsupportedAttribute(a.to_string());
+        let mut attributes = at.attributes.clone();
+        let foreign = attributes
+            .iter()
+            .find(|a| a.attribute_set.as_ref().is_some_and(|set| set != BIB1));
+        if let Some(a) = foreign {
+            return Err(unsupported(a));
+        }
+        let same = |a: &AttributeElement, b: &AttributeElement| {
+            a.attribute_type == b.attribute_type && a.value == b.value
+        };
+        let qualifier = self.qualifiers.iter().find(|q| {
+            q.aliases.is_empty()
+                && !q.attributes.is_empty()
+                && q.attributes
+                    .iter()
+                    .all(|w| attributes.iter().any(|a| same(a, w)))
+        });
+        if let Some(q) = qualifier {
+            attributes.retain(|a| !q.attributes.iter().any(|w| same(a, w)));
+        }
+        let mut relation = "=";
+        let mut structure = None;
+        let mut truncation = None;
+        for a in &attributes {
+            match (a.attribute_type, &a.value) {
+                (2, AttributeValue::Numeric(n)) => {
+                    let found = RELATIONS.iter().find(|(_, value)| value == n);
+                    relation = found.ok_or_else(|| unsupported(a))?.0;
+                }
+                (4, AttributeValue::Numeric(n @ (1 | 2))) => structure = Some((a, *n)),
+                (5, AttributeValue::Numeric(n @ (RIGHT | LEFT | BOTH | Z3958))) => {
+                    truncation = Some(*n)
+                }
+                (5, AttributeValue::Numeric(100)) => {}
+                _ => return Err(unsupported(a)),
+            }
+        }
+        if relation != "=" && !qualifier.is_some_and(|q| q.ordered) {
+            return Err(CclError::UnsupportedRelation(String::from(relation)));
+        }
+        let term = match &at.term {
+            RpnTerm::General(bytes) => String::from_utf8(bytes.clone()).map_err(|e| {
+                CclError::UnsupportedTerm(String::from_utf8_lossy(e.as_bytes()).into_owned())
+            })?,
+            RpnTerm::CharacterString(s) => s.clone(),
+            RpnTerm::Numeric(n) => n.to_string(),
+            RpnTerm::Null => return Err(CclError::UnsupportedTerm(String::from("null"))),
+        };
+        let unsupported_term = || CclError::UnsupportedTerm(term.clone());
+        // unqualified terms are read back with the `term` qualifier
+        let reader = qualifier.or_else(|| self.get("term"));
+        let several = term.split_whitespace().nth(1).is_some();
+        let read = match reader.map_or(Words::Default, |q| q.words) {
+            Words::AndList | Words::OrList if several => return Err(unsupported_term()),
+            Words::PhraseOrWord if several => Some(1),
+            Words::Phrase => Some(1),
+            Words::PhraseOrWord | Words::Word | Words::AndList | Words::OrList => Some(2),
+            Words::Default => None,
+        };
+        if let (Some((a, value)), Some(read)) = (structure, read) {
+            if value != read {
+                return Err(unsupported(a));
+            }
+        }
+        let written = match truncation {
+            Some(Z3958) => {
+                if needs_quotes(&term.replace(['?', '#'], "")) {
+                    return Err(unsupported_term());
+                }
+                term.clone()
+            }
+            Some(_) if needs_quotes(&term) => return Err(unsupported_term()),
+            Some(LEFT) => format!("?{}", term),
+            Some(RIGHT) => format!("{}?", term),
+            Some(_) => format!("?{}?", term),
+            None if needs_quotes(&term) => quote(&term),
+            None => term.clone(),
+        };
+        if let (Some(value), Some(allowed)) =
+            (truncation, reader.and_then(|q| q.truncation.as_ref()))
+        {
+            if !allowed.contains(&value) {
+                return Err(CclError::UnsupportedMasking(written));
+            }
+        }
+        match qualifier {
+            Some(q) => Ok(format!("{}{}{}", q.name, relation, written)),
+            None => Ok(written),
+        }
+    }
+
+    fn operand(&self, rpn: &RpnStructure) -> Result<String, CclError> {
+        match rpn {
+            RpnStructure::RpnOp { .. } => Ok(format!("({})", self.rpn(rpn)?)),
+            _ => self.rpn(rpn),
+        }
+    }
+
+    fn rpn(&self, rpn: &RpnStructure) -> Result<String, CclError> {
+        match rpn {
+            RpnStructure::Operand(Operand::AttrTerm(at)) => self.attr_term(at),
+            RpnStructure::Operand(Operand::ResultSet(id)) => {
+                Err(CclError::UnsupportedTerm(format!("result set {}", id)))
+            }
+            RpnStructure::RpnOp { left, right, op } => {
+                let op = match op {
+                    Operator::And => String::from("and"),
+                    Operator::Or => String::from("or"),
+                    Operator::AndNot => String::from("not"),
+                    Operator::Prox(prox)
+                        if prox.exclusion != Some(true)
+                            && prox.relation_type == 2
+                            && prox.unit == ProximityUnit::Known(2) =>
+                    {
+                        let mark = if prox.ordered { '!' } else { '%' };
+                        format!("{}{}", mark, prox.distance)
+                    }
+                    Operator::Prox(_) => {
+                        return Err(CclError::UnsupportedOperator(rpn.to_string()))
+                    }
+                };
+                Ok(format!(
+                    "{} {} {}",
+                    self.operand(left)?,
+                    op,
+                    self.operand(right)?
+                ))
+            }
+        }
+    }
+
+    /// Write a Type-1 query as CCL. Operands are given the first
+    /// qualifier, in the order they were read, whose attributes they all
+    /// have; other attributes must be relations, structure or truncation
+    /// that read back the same: ordered relations need `r=o`, structure
+    /// must be what an `s=` gives the term, and truncation must be
+    /// allowed by `t=`. Unqualified terms are read back with the `term`
+    /// qualifier.
+    pub fn rpn_to_ccl(&self, query: &RpnQuery) -> Result<String, CclError> {
+        if query.attribute_set != BIB1 {
+            let set: Vec<String> = query.attribute_set.iter().map(u32::to_string).collect();
+            return Err(CclError::UnsupportedAttribute(format!(
+                "set {}",
+                set.join(".")
+            )));
+        }
+        self.rpn(&query.rpn)
+    }
+
+    /// Parse CCL and convert it to CQL with the properties of `pqf`.
+    pub fn ccl_to_cql(&self, ccl: &str, pqf: &PqfTransform) -> Result<CqlNode, CclError> {
+        pqf.rpn_to_cql(&self.ccl_to_rpn(ccl)?)
+            .map_err(CclError::Pqf)
+    }
+
+    /// Convert CQL to CCL with the properties of `pqf`.
+    pub fn cql_to_ccl(&self, node: &CqlNode, pqf: &PqfTransform) -> Result<String, CclError> {
+        self.rpn_to_ccl(&pqf.to_rpn(node).map_err(CclError::Pqf)?)
+    }
+}
+
+impl FromStr for CclTransform {
+    type Err = CclError;
+
+    fn from_str(s: &str) -> Result<CclTransform, CclError> {
+        CclTransform::from_qualifiers(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALIFIERS: &str = "
+        # title and author are phrases
+        ti    u=4 s=pw t=l,r
+        au    u=1003 s=pw
+        date  u=30 r=o
+        kw    u=1016 s=al
+        term  s=pw
+        any   ti au
+    ";
+
+    const PROPERTIES: &str = "
+        set.dc = info:srw/cql-context-set/1/dc-v1.1
+        set = info:srw/cql-context-set/1/dc-v1.1
+        index.cql.serverChoice = 1=1016
+        index.dc.title = 1=4
+        index.dc.creator = 1=1003
+        index.dc.date = 1=30
+        relation.eq = 2=3
+        relation.adj = 2=3
+        relation.< = 2=1
+        relation.> = 2=5
+        relation.ge = 2=4
+        structure.adj = 4=1
+        structure.* = 4=2
+        truncation.right = 5=1
+        truncation.left = 5=2
+        truncation.z3958 = 5=104
+    ";
+
+    fn pqf(ccl: &str) -> Result<String, CclError> {
+        let transform: CclTransform = QUALIFIERS.parse().unwrap();
+        Ok(transform.ccl_to_rpn(ccl)?.to_string())
+    }
+
+    #[test]
+    fn qualifiers() {
+        let transform: CclTransform = QUALIFIERS.parse().unwrap();
+        assert_eq!(transform.qualifiers.len(), 6);
+        assert_eq!(transform.get("ANY").unwrap().aliases, ["ti", "au"]);
+        assert_eq!(
+            CclTransform::from_qualifiers("ti u=4\nau x=1").err(),
+            Some(CclError::InvalidQualifier {
+                line: 2,
+                text: String::from("au x=1")
+            })
+        );
+        assert_eq!(
+            CclTransform::from_qualifiers("ti s=xyz").err(),
+            Some(CclError::InvalidQualifier {
+                line: 1,
+                text: String::from("ti s=xyz")
+            })
+        );
+    }
+
+    #[test]
+    fn ccl_to_rpn() {
+        assert_eq!(pqf("ti=hobbit").unwrap(), "@attr 1=4 @attr 4=2 \"hobbit\"");
+        assert_eq!(
+            pqf("ti=lord of the rings").unwrap(),
+            "@attr 1=4 @attr 4=1 \"lord of the rings\""
+        );
+        assert_eq!(
+            pqf("ti=\"a=b\" c").unwrap(),
+            "@attr 1=4 @attr 4=1 \"a=b c\""
+        );
+        assert_eq!(
+            pqf("ti=hob?").unwrap(),
+            "@attr 1=4 @attr 4=2 @attr 5=1 \"hob\""
+        );
+        assert_eq!(
+            pqf("ti=?bit").unwrap(),
+            "@attr 1=4 @attr 4=2 @attr 5=2 \"bit\""
+        );
+        assert_eq!(
+            pqf("au=tolk#en").unwrap(),
+            "@attr 1=1003 @attr 4=2 @attr 5=104 \"tolk#en\""
+        );
+        assert_eq!(pqf("date>=1950").unwrap(), "@attr 1=30 @attr 2=4 \"1950\"");
+        assert_eq!(pqf("hobbit").unwrap(), "@attr 4=2 \"hobbit\"");
+        assert_eq!(
+            pqf("kw=ring lord").unwrap(),
+            "@and @attr 1=1016 @attr 4=2 \"ring\" @attr 1=1016 @attr 4=2 \"lord\""
+        );
+        assert_eq!(
+            pqf("any=tolkien").unwrap(),
+            "@or @attr 1=4 @attr 4=2 \"tolkien\" @attr 1=1003 @attr 4=2 \"tolkien\""
+        );
+        assert_eq!(pqf("ti,au=tolkien").unwrap(), pqf("any=tolkien").unwrap());
+        assert_eq!(
+            pqf("ti=(a or b)").unwrap(),
+            "@or @attr 1=4 @attr 4=2 \"a\" @attr 1=4 @attr 4=2 \"b\""
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            pqf("ti=a and au=b or date<2000").unwrap(),
+            "@or @and @attr 1=4 @attr 4=2 \"a\" @attr 1=1003 @attr 4=2 \"b\" \
+             @attr 1=30 @attr 2=1 \"2000\""
+        );
+        assert_eq!(
+            pqf("ti=a not (au=b or au=c)").unwrap(),
+            "@not @attr 1=4 @attr 4=2 \"a\" \
+             @or @attr 1=1003 @attr 4=2 \"b\" @attr 1=1003 @attr 4=2 \"c\""
+        );
+        assert_eq!(
+            pqf("ti=a au=b").unwrap(),
+            "@and @attr 1=4 @attr 4=2 \"a\" @attr 1=1003 @attr 4=2 \"b\""
+        );
+        assert_eq!(
+            pqf("a %2 b").unwrap(),
+            "@prox 0 2 0 2 k 2 @attr 4=2 \"a\" @attr 4=2 \"b\""
+        );
+        assert_eq!(
+            pqf("a ! b").unwrap(),
+            "@prox 0 1 1 2 k 2 @attr 4=2 \"a\" @attr 4=2 \"b\""
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            pqf("xx=a"),
+            Err(CclError::UnknownQualifier(String::from("xx")))
+        );
+        assert_eq!(
+            pqf("ti<a"),
+            Err(CclError::UnsupportedRelation(String::from("<")))
+        );
+        assert_eq!(
+            pqf("ti=h#bbit"),
+            Err(CclError::UnsupportedMasking(String::from("h#bbit")))
+        );
+        assert_eq!(
+            pqf("ti="),
+            Err(CclError::Syntax {
+                offset: 3,
+                message: String::from("expected search term")
+            })
+        );
+        assert_eq!(
+            pqf("(ti=a"),
+            Err(CclError::Syntax {
+                offset: 5,
+                message: String::from("expected )")
+            })
+        );
+        assert_eq!(
+            pqf("ti=a)"),
+            Err(CclError::Syntax {
+                offset: 4,
+                message: String::from("expected end of query")
+            })
+        );
+        assert_eq!(
+            pqf("a and"),
+            Err(CclError::Syntax {
+                offset: 5,
+                message: String::from("expected search term")
+            })
+        );
+        assert_eq!(
+            pqf("\"abc"),
+            Err(CclError::Syntax {
+                offset: 0,
+                message: String::from("unterminated string")
+            })
+        );
+    }
+
+    #[test]
+    fn rpn_to_ccl() {
+        let transform: CclTransform = QUALIFIERS.parse().unwrap();
+        let ccl = |pqf: &str| transform.rpn_to_ccl(&pqf.parse().unwrap());
+        assert_eq!(
+            ccl("@attr 1=4 @attr 4=1 \"lord of\"").unwrap(),
+            "ti=\"lord of\""
+        );
+        assert_eq!(
+            ccl("@and @attr 1=4 @attr 5=1 hob @or @attr 1=30 @attr 2=5 1950 x").unwrap(),
+            "ti=hob? and (date>1950 or x)"
+        );
+        assert_eq!(
+            ccl("@prox 0 3 1 2 k 2 @attr 1=1003 \"say \\\"hi\\\"\" and").unwrap(),
+            "au=\"say \\\"hi\\\"\" !3 \"and\""
+        );
+        assert_eq!(
+            ccl("@attr 1=1003 @attr 5=104 tolk#en").unwrap(),
+            "au=tolk#en"
+        );
+        assert_eq!(
+            ccl("@attr 1=9 x"),
+            Err(CclError::UnsupportedAttribute(String::from("@attr 1=9")))
+        );
+        assert_eq!(
+            ccl("@attr 2=5 x"),
+            Err(CclError::UnsupportedRelation(String::from(">")))
+        );
+        assert_eq!(
+            ccl("@attr 1=4 @attr 5=1 \"a b\""),
+            Err(CclError::UnsupportedTerm(String::from("a b")))
+        );
+        assert_eq!(
+            ccl("@prox 0 1 0 2 k 3 a b"),
+            Err(CclError::UnsupportedOperator(String::from(
+                "@prox 0 1 0 2 k 3 \"a\" \"b\""
+            )))
+        );
+        for query in [
+            "ti=hobbit",
+            "ti=\"lord of the rings\" and au=tolkien",
+            "date<1950 or (kw=ring not ti=?bit)",
+            "au=tolk#en %2 hobbit",
+        ] {
+            let rpn = transform.ccl_to_rpn(query).unwrap();
+            let ccl = transform.rpn_to_ccl(&rpn).unwrap();
+            assert_eq!(transform.ccl_to_rpn(&ccl).unwrap(), rpn, "{}", query);
+        }
+    }
+
+    #[test]
+    fn rpn_read_back() {
+        let transform: CclTransform = QUALIFIERS.parse().unwrap();
+        let ccl = |pqf: &str| transform.rpn_to_ccl(&pqf.parse().unwrap());
+        assert_eq!(
+            ccl("@attr 1=4 @attr 2=5 x"),
+            Err(CclError::UnsupportedRelation(String::from(">")))
+        );
+        assert_eq!(
+            ccl("@attr 1=4 @attr 4=1 hobbit"),
+            Err(CclError::UnsupportedAttribute(String::from("@attr 4=1")))
+        );
+        assert_eq!(
+            ccl("@attr 1=4 @attr 4=2 \"lord of\""),
+            Err(CclError::UnsupportedAttribute(String::from("@attr 4=2")))
+        );
+        assert_eq!(
+            ccl("@attr 4=1 x"),
+            Err(CclError::UnsupportedAttribute(String::from("@attr 4=1")))
+        );
+        assert_eq!(
+            ccl("@attr 1=1016 \"ring lord\""),
+            Err(CclError::UnsupportedTerm(String::from("ring lord")))
+        );
+        assert_eq!(
+            ccl("@attr 1=4 @attr 5=104 h#bbit"),
+            Err(CclError::UnsupportedMasking(String::from("h#bbit")))
+        );
+        let words: CclTransform = "ti u=4 s=w".parse().unwrap();
+        let ccl = |pqf: &str| words.rpn_to_ccl(&pqf.parse().unwrap());
+        assert_eq!(
+            ccl("@attr 1=4 @attr 2=5 x"),
+            Err(CclError::UnsupportedRelation(String::from(">")))
+        );
+        assert_eq!(
+            ccl("@attr 1=4 @attr 4=1 \"a b\""),
+            Err(CclError::UnsupportedAttribute(String::from("@attr 4=1")))
+        );
+        for query in [
+            "@attr 1=4 @attr 4=2 hobbit",
+            "@attr 1=4 @attr 4=1 \"lord of the rings\"",
+            "@and @attr 1=30 @attr 2=5 1950 @attr 4=2 x",
+            "@or @attr 1=1003 @attr 4=2 @attr 5=104 tolk#en @attr 1=4 @attr 4=2 @attr 5=2 bit",
+            "@and @attr 1=1016 @attr 4=2 ring @attr 1=1016 @attr 4=2 lord",
+            "@prox 0 2 1 2 k 2 @attr 4=2 a @attr 4=1 \"b c\"",
+        ] {
+            let rpn: RpnQuery = query.parse().unwrap();
+            let ccl = transform.rpn_to_ccl(&rpn).unwrap();
+            assert_eq!(transform.ccl_to_rpn(&ccl).unwrap(), rpn, "{}", query);
+        }
+    }
+
+    #[test]
+    fn cql_bridge() {
+        let transform: CclTransform = QUALIFIERS.parse().unwrap();
+        let properties: PqfTransform = PROPERTIES.parse().unwrap();
+        let cql =
+            |ccl: &str| Ok::<String, CclError>(transform.ccl_to_cql(ccl, &properties)?.to_cql());
+        assert_eq!(
+            cql("ti=lord of the rings and au=tolkien").unwrap(),
+            "dc.title adj \"lord of the rings\" and dc.creator = tolkien"
+        );
+        assert_eq!(
+            cql("date>1950 not ti=hob?").unwrap(),
+            "dc.date > 1950 not dc.title = hob*"
+        );
+        assert_eq!(cql("kw=ring").unwrap(), "ring");
+        assert_eq!(cql("ti=a %2 b").unwrap(), "dc.title = a prox/distance<=2 b");
+        assert_eq!(
+            cql("date<=1950"),
+            Err(CclError::Pqf(PqfError::UnsupportedAttribute(String::from(
+                "@attr 2=2"
+            ))))
+        );
+        let ccl = |cql: &str| transform.cql_to_ccl(&crate::parse(cql).unwrap(), &properties);
+        assert_eq!(
+            ccl("dc.title adj \"lord of\" and dc.creator = tolk?en").unwrap(),
+            "ti=\"lord of\" and au=tolk#en"
+        );
+        assert_eq!(ccl("dc.date > 1950").unwrap(), "date>1950");
+        assert_eq!(ccl("dc.title = hob*").unwrap(), "ti=hob?");
+        assert_matches!(
+            ccl("dc.date <= 1950"),
+            Err(CclError::Pqf(PqfError::Translation(e))) if e.text() == "<="
+        );
+    }
+}
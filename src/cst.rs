@@ -0,0 +1,627 @@
+//! Concrete syntax tree that keeps every character of the query.
+//!
+//! Where [`CqlNode`] describes what a query means, the concrete syntax
+//! tree records how it was written: whitespace, quotes, escapes, the case
+//! of keywords and redundant parentheses. Printing a tree gives back the
+//! original text byte for byte, so queries can be edited programmatically
+//! without disturbing the parts that were not touched.
+//!
+//! ```
+//! let mut tree = cql_rust::cst::parse("ti=x  AND (\"a b\")").unwrap();
+//! tree.rename_index("ti", "dc.title");
+//! tree.and_clause("year > 2000").unwrap();
+//! assert_eq!(tree.to_string(), "dc.title=x  AND (\"a b\") and year > 2000");
+//! ```
+
+use crate::error::ParseError;
+use crate::lexer::Lexer;
+use crate::lexer::Token;
+use crate::node::CqlNode;
+use crate::parser::Parser;
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    // tokens
+    Whitespace,
+    /// Unquoted word: index, term, relation, boolean operator or `sortby`.
+    Word,
+    /// Quoted string, including the quotes.
+    Quoted,
+    Eq,
+    Exact,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Slash,
+    LParen,
+    RParen,
+    // nodes
+    /// The whole query: prefix assignments, search and sort specification.
+    Query,
+    /// `>name=uri` or `>uri`.
+    PrefixAssignment,
+    /// Parenthesised query.
+    Group,
+    /// Left operand, [`SyntaxKind::BooleanOp`] and right operand.
+    Boolean,
+    /// Boolean operator with its modifiers.
+    BooleanOp,
+    /// Index, [`SyntaxKind::Relation`] and the clause it applies to.
+    SearchClause,
+    /// Relation with its modifiers.
+    Relation,
+    /// `/name`, optionally followed by a comparison and value.
+    Modifier,
+    /// Search term of a clause.
+    Term,
+    /// `sortby` followed by sort keys.
+    SortSpec,
+    /// Index of a sort key with its modifiers.
+    SortKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxToken {
+    kind: SyntaxKind,
+    text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxNode {
+    kind: SyntaxKind,
+    children: Vec<SyntaxElement>,
+}
+
+impl SyntaxToken {
+    pub fn new(kind: SyntaxKind, text: &str) -> SyntaxToken {
+        SyntaxToken {
+            kind,
+            text: String::from(text),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the text of the token. The text is not checked; use
+    /// [`SyntaxNode::to_cql_node`] to validate the edited query.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = String::from(text);
+    }
+}
+
+impl SyntaxNode {
+    pub fn new(kind: SyntaxKind, children: Vec<SyntaxElement>) -> SyntaxNode {
+        SyntaxNode { kind, children }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn children(&self) -> &[SyntaxElement] {
+        &self.children
+    }
+
+    pub fn children_mut(&mut self) -> &mut Vec<SyntaxElement> {
+        &mut self.children
+    }
+
+    /// Child nodes, skipping tokens.
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(n) => Some(n),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// This node and all nodes below it, in text order.
+    pub fn descendants(&self) -> Vec<&SyntaxNode> {
+        let mut res = vec![self];
+        for n in self.child_nodes() {
+            res.extend(n.descendants());
+        }
+        res
+    }
+
+    /// All tokens below this node, in text order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut res = Vec::new();
+        for c in &self.children {
+            match c {
+                SyntaxElement::Node(n) => res.extend(n.tokens()),
+                SyntaxElement::Token(t) => res.push(t),
+            }
+        }
+        res
+    }
+
+    pub fn tokens_mut(&mut self) -> Vec<&mut SyntaxToken> {
+        let mut res = Vec::new();
+        for c in &mut self.children {
+            match c {
+                SyntaxElement::Node(n) => res.extend(n.tokens_mut()),
+                SyntaxElement::Token(t) => res.push(t),
+            }
+        }
+        res
+    }
+
+    /// First token of this node that is not whitespace. For search
+    /// clauses and sort keys this is the index.
+    pub fn first_token(&self) -> Option<&SyntaxToken> {
+        self.tokens()
+            .into_iter()
+            .find(|t| t.kind != SyntaxKind::Whitespace)
+    }
+
+    fn first_token_mut(&mut self) -> Option<&mut SyntaxToken> {
+        self.children.iter_mut().find_map(|c| match c {
+            SyntaxElement::Token(t) if t.kind != SyntaxKind::Whitespace => Some(t),
+            _ => None,
+        })
+    }
+
+    /// Rename every index `from` of search clauses and sort keys to `to`,
+    /// comparing case-insensitively. Returns the number of renamed indexes.
+    pub fn rename_index(&mut self, from: &str, to: &str) -> usize {
+        let mut count = 0;
+        if self.kind == SyntaxKind::SearchClause || self.kind == SyntaxKind::SortKey {
+            if let Some(t) = self.first_token_mut() {
+                if t.text.eq_ignore_ascii_case(from) {
+                    t.set_text(to);
+                    count += 1;
+                }
+            }
+        }
+        for c in &mut self.children {
+            if let SyntaxElement::Node(n) = c {
+                count += n.rename_index(from, to);
+            }
+        }
+        count
+    }
+
+    /// Restrict the search of a [`SyntaxKind::Query`] by combining it
+    /// with `clause` using `and`. The clause is added after the search
+    /// and before any sort specification, in parentheses if it contains
+    /// boolean operators or prefix assignments.
+    pub fn and_clause(&mut self, clause: &str) -> Result<(), ParseError> {
+        let added = parse(clause)?;
+        let mut inner: Vec<SyntaxElement> = added
+            .children
+            .into_iter()
+            .filter(|c| match c {
+                SyntaxElement::Node(n) => n.kind != SyntaxKind::SortSpec,
+                SyntaxElement::Token(_) => false,
+            })
+            .collect();
+        let simple = inner.len() == 1
+            && matches!(&inner[0], SyntaxElement::Node(n) if n.kind != SyntaxKind::Boolean);
+        if !simple {
+            inner = vec![SyntaxElement::Node(parenthesise(inner))];
+        }
+        let Some(at) = self.children.iter().position(|c| match c {
+            SyntaxElement::Node(n) => {
+                n.kind != SyntaxKind::PrefixAssignment && n.kind != SyntaxKind::SortSpec
+            }
+            SyntaxElement::Token(_) => false,
+        }) else {
+            return Ok(());
+        };
+        let search = std::mem::replace(
+            &mut self.children[at],
+            SyntaxElement::Token(SyntaxToken::new(SyntaxKind::Whitespace, "")),
+        );
+        let op = SyntaxNode::new(
+            SyntaxKind::BooleanOp,
+            vec![SyntaxElement::Token(SyntaxToken::new(
+                SyntaxKind::Word,
+                "and",
+            ))],
+        );
+        let mut children = vec![
+            search,
+            SyntaxElement::Token(SyntaxToken::new(SyntaxKind::Whitespace, " ")),
+            SyntaxElement::Node(op),
+            SyntaxElement::Token(SyntaxToken::new(SyntaxKind::Whitespace, " ")),
+        ];
+        children.append(&mut inner);
+        self.children[at] = SyntaxElement::Node(SyntaxNode::new(SyntaxKind::Boolean, children));
+        Ok(())
+    }
+
+    /// Lower the tree to the abstract syntax tree, as parsing its text
+    /// would. Fails if edits left the query invalid.
+    pub fn to_cql_node(&self) -> Result<CqlNode, ParseError> {
+        let text = self.to_string();
+        Parser::new().parse(text.chars().by_ref())
+    }
+}
+
+/// Group `inner` in parentheses, separating its elements by spaces.
+fn parenthesise(inner: Vec<SyntaxElement>) -> SyntaxNode {
+    let mut group = vec![SyntaxElement::Token(SyntaxToken::new(
+        SyntaxKind::LParen,
+        "(",
+    ))];
+    for (i, c) in inner.into_iter().enumerate() {
+        if i > 0 {
+            group.push(SyntaxElement::Token(SyntaxToken::new(
+                SyntaxKind::Whitespace,
+                " ",
+            )));
+        }
+        group.push(c);
+    }
+    group.push(SyntaxElement::Token(SyntaxToken::new(
+        SyntaxKind::RParen,
+        ")",
+    )));
+    SyntaxNode::new(SyntaxKind::Group, group)
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in &self.children {
+            match c {
+                SyntaxElement::Node(n) => write!(f, "{}", n)?,
+                SyntaxElement::Token(t) => write!(f, "{}", t.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse `text` into a concrete syntax tree of kind [`SyntaxKind::Query`].
+pub fn parse(text: &str) -> Result<SyntaxNode, ParseError> {
+    // validate first, so that building the tree can assume a valid query
+    Parser::new().parse(text.chars().by_ref())?;
+    let mut lexer = Lexer::new();
+    let mut get = text.chars();
+    lexer.start(&mut get);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.lex(&mut get)?;
+        let span = lexer.token_span();
+        if token == Token::EOS {
+            tokens.push((token, span));
+            break;
+        }
+        tokens.push((token, span));
+    }
+    let mut builder = Builder {
+        text,
+        tokens,
+        pos: 0,
+        last_end: 0,
+        stack: vec![SyntaxNode::new(SyntaxKind::Query, Vec::new())],
+    };
+    builder.query();
+    Ok(builder.stack.pop().unwrap())
+}
+
+struct Builder<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    last_end: usize,
+    stack: Vec<SyntaxNode>,
+}
+
+impl Builder<'_> {
+    fn look(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)].0
+    }
+
+    fn top(&mut self) -> &mut SyntaxNode {
+        self.stack.last_mut().unwrap()
+    }
+
+    /// Add the whitespace before the current token to the innermost node.
+    fn trivia(&mut self) {
+        let start = self.tokens[self.pos].1.start.byte;
+        if self.last_end < start {
+            let text = &self.text[self.last_end..start];
+            self.top()
+                .children
+                .push(SyntaxElement::Token(SyntaxToken::new(
+                    SyntaxKind::Whitespace,
+                    text,
+                )));
+            self.last_end = start;
+        }
+    }
+
+    fn bump(&mut self) {
+        self.trivia();
+        let (token, span) = &self.tokens[self.pos];
+        let text = &self.text[span.start.byte..span.end.byte];
+        let kind = match token {
+            Token::EQ => SyntaxKind::Eq,
+            Token::Exact => SyntaxKind::Exact,
+            Token::NE => SyntaxKind::Ne,
+            Token::LT => SyntaxKind::Lt,
+            Token::GT => SyntaxKind::Gt,
+            Token::LE => SyntaxKind::Le,
+            Token::GE => SyntaxKind::Ge,
+            Token::Modifier => SyntaxKind::Slash,
+            Token::LP => SyntaxKind::LParen,
+            Token::RP => SyntaxKind::RParen,
+            _ if text.starts_with('"') => SyntaxKind::Quoted,
+            _ => SyntaxKind::Word,
+        };
+        let token = SyntaxToken::new(kind, text);
+        self.last_end = span.end.byte;
+        self.top().children.push(SyntaxElement::Token(token));
+        self.pos += 1;
+    }
+
+    fn start(&mut self, kind: SyntaxKind) {
+        self.trivia();
+        self.stack.push(SyntaxNode::new(kind, Vec::new()));
+    }
+
+    /// Start a node holding the children of the innermost node from
+    /// `checkpoint` on.
+    fn start_at(&mut self, checkpoint: usize, kind: SyntaxKind) {
+        let children = self.top().children.split_off(checkpoint);
+        self.stack.push(SyntaxNode::new(kind, children));
+    }
+
+    fn finish(&mut self) {
+        let node = self.stack.pop().unwrap();
+        self.top().children.push(SyntaxElement::Node(node));
+    }
+
+    fn is_term(token: &Token) -> bool {
+        matches!(
+            token,
+            Token::SimpleString(_) | Token::PrefixName(_) | Token::Boolop(_) | Token::Sortby(_)
+        )
+    }
+
+    fn is_relation_symbol(token: &Token) -> bool {
+        matches!(
+            token,
+            Token::EQ | Token::Exact | Token::NE | Token::LT | Token::GT | Token::LE | Token::GE
+        )
+    }
+
+    fn query(&mut self) {
+        self.prefixes();
+        self.scoped_clause();
+        if let Token::Sortby(_) = self.look() {
+            self.start(SyntaxKind::SortSpec);
+            self.bump();
+            while Self::is_term(self.look()) {
+                self.start(SyntaxKind::SortKey);
+                self.bump();
+                self.modifiers();
+                self.finish();
+            }
+            self.finish();
+        }
+        self.trivia();
+    }
+
+    fn prefixes(&mut self) {
+        while *self.look() == Token::GT {
+            self.start(SyntaxKind::PrefixAssignment);
+            self.bump();
+            self.bump();
+            if *self.look() == Token::EQ {
+                self.bump();
+                self.bump();
+            }
+            self.finish();
+        }
+    }
+
+    fn modifiers(&mut self) {
+        while *self.look() == Token::Modifier {
+            self.start(SyntaxKind::Modifier);
+            self.bump();
+            self.bump();
+            if Self::is_relation_symbol(self.look()) {
+                self.bump();
+                self.bump();
+            }
+            self.finish();
+        }
+    }
+
+    fn scoped_clause(&mut self) {
+        self.trivia();
+        let checkpoint = self.top().children.len();
+        self.search_clause();
+        while let Token::Boolop(_) = self.look() {
+            self.start_at(checkpoint, SyntaxKind::Boolean);
+            self.start(SyntaxKind::BooleanOp);
+            self.bump();
+            self.modifiers();
+            self.finish();
+            self.search_clause();
+            self.finish();
+        }
+    }
+
+    fn search_clause(&mut self) {
+        if *self.look() == Token::LP {
+            self.start(SyntaxKind::Group);
+            self.bump();
+            self.prefixes();
+            self.scoped_clause();
+            self.bump();
+            self.finish();
+            return;
+        }
+        let relation = match self.peek() {
+            Token::PrefixName(_) => true,
+            token => Self::is_relation_symbol(token),
+        };
+        if relation {
+            self.start(SyntaxKind::SearchClause);
+            self.bump();
+            self.start(SyntaxKind::Relation);
+            self.bump();
+            self.modifiers();
+            self.finish();
+            self.search_clause();
+            self.finish();
+        } else {
+            self.start(SyntaxKind::Term);
+            self.bump();
+            self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for query in [
+            "a",
+            "  a  ",
+            "ti=x",
+            "ti  =  \"x y\"",
+            "dc.title ADJ \"a \\\"b\\\"\" And (( x )) oR\n\ty",
+            ">dc=\"http://dc\" > \"http://default\" dc.ti = (a or b) sortby  dc.date/sort.descending ",
+            "a prox/unit=word/distance<=2 b NOT c",
+            "ti == é\\x",
+            "ti =/cql.word/x=y a sortby x y",
+        ] {
+            let tree = parse(query).unwrap();
+            assert_eq!(tree.to_string(), query);
+            assert_eq!(tree.kind(), SyntaxKind::Query);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("ti =").is_err());
+        assert!(parse("(a").is_err());
+    }
+
+    #[test]
+    fn structure() {
+        let tree = parse(" (ti = a) AND/x=1 b sortby c").unwrap();
+        let kinds: Vec<SyntaxKind> = tree.descendants().iter().map(|n| n.kind()).collect();
+        assert_eq!(
+            kinds,
+            [
+                SyntaxKind::Query,
+                SyntaxKind::Boolean,
+                SyntaxKind::Group,
+                SyntaxKind::SearchClause,
+                SyntaxKind::Relation,
+                SyntaxKind::Term,
+                SyntaxKind::BooleanOp,
+                SyntaxKind::Modifier,
+                SyntaxKind::Term,
+                SyntaxKind::SortSpec,
+                SyntaxKind::SortKey,
+            ]
+        );
+        assert_eq!(
+            tree.children()[0],
+            SyntaxElement::Token(SyntaxToken::new(SyntaxKind::Whitespace, " "))
+        );
+        let boolean = tree.child_nodes().next().unwrap();
+        assert_eq!(boolean.to_string(), "(ti = a) AND/x=1 b");
+        let op = boolean.child_nodes().nth(1).unwrap();
+        assert_eq!(op.first_token().unwrap().text(), "AND");
+    }
+
+    #[test]
+    fn rename_index() {
+        let mut tree = parse("TI=a or (ti adj \"b c\" and au=d) sortby ti/ascending").unwrap();
+        assert_eq!(tree.rename_index("ti", "dc.title"), 3);
+        assert_eq!(
+            tree.to_string(),
+            "dc.title=a or (dc.title adj \"b c\" and au=d) sortby dc.title/ascending"
+        );
+        assert_matches!(tree.to_cql_node(), Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.left(), CqlNode::St(st) => {
+                    assert_eq!(st.index(), "dc.title");
+                });
+            });
+            assert_eq!(root.sort()[0].index(), "dc.title");
+        });
+    }
+
+    #[test]
+    fn and_clause() {
+        let mut tree = parse(">dc=\"http://dc\" a OR b  sortby date").unwrap();
+        tree.and_clause("year>2000").unwrap();
+        assert_eq!(
+            tree.to_string(),
+            ">dc=\"http://dc\" a OR b and year>2000  sortby date"
+        );
+
+        let mut tree = parse("a").unwrap();
+        tree.and_clause(" x or y ").unwrap();
+        assert_eq!(tree.to_string(), "a and (x or y)");
+        assert_matches!(tree.to_cql_node(), Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.value(), "and");
+                assert_matches!(bo.right(), CqlNode::Boolean(_));
+            });
+        });
+
+        let mut tree = parse("a").unwrap();
+        tree.and_clause(">dc=\"http://dc\" dc.x=y").unwrap();
+        assert_eq!(tree.to_string(), "a and (>dc=\"http://dc\" dc.x=y)");
+        assert!(tree.to_cql_node().is_ok());
+
+        let mut tree = parse("a").unwrap();
+        assert!(tree.and_clause("x =").is_err());
+        assert_eq!(tree.to_string(), "a");
+    }
+
+    #[test]
+    fn edit_tokens() {
+        let mut tree = parse("ti = old").unwrap();
+        for t in tree.tokens_mut() {
+            if t.text() == "old" {
+                t.set_text("\"new term\"");
+            }
+        }
+        assert_eq!(tree.to_string(), "ti = \"new term\"");
+        assert_matches!(tree.to_cql_node(), Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert_eq!(st.term(), Some("new term"));
+            });
+        });
+
+        for t in tree.tokens_mut() {
+            if t.kind() == SyntaxKind::Eq {
+                t.set_text("");
+            }
+        }
+        assert!(tree.to_cql_node().is_err());
+    }
+}
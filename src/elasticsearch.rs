@@ -0,0 +1,526 @@
+//! Translation of CQL to the Elasticsearch (and OpenSearch) query DSL.
+//!
+//! Indexes are mapped to fields with [`ElasticsearchTransform::field`],
+//! optionally with a keyword subfield for matching whole values, and
+//! `cql.serverChoice` searches the fields given to
+//! [`ElasticsearchTransform::default_fields`]. Search clauses translate
+//! as follows:
+//!
+//! | CQL                  | Query                                        |
+//! |----------------------|----------------------------------------------|
+//! | `ti = "a b"`         | `match_phrase` on the text field             |
+//! | `ti == "a b"`        | `term` on the keyword field                  |
+//! | `ti = a*`            | `prefix`                                     |
+//! | `ti = a?c*`          | `wildcard`                                   |
+//! | `ti all "a b"`       | `bool.must` of `match` per word              |
+//! | `ti any "a b"`       | `bool.should` of `match` per word            |
+//! | `ti any/string "a b"`| `terms` on the keyword field                 |
+//! | `year < 2000`        | `range`, on the keyword field if any         |
+//! | `ti <> a`            | `bool.must_not` of `term`                    |
+//!
+//! The relation modifiers `cql.respectCase` and `cql.string` select the
+//! keyword field, and `cql.ignoreCase` and `cql.word` the analysed text
+//! field. Booleans become `bool` queries, with nested `and` and `or`
+//! flattened and `not` as `must_not`. Sort keys become the `sort` array
+//! of [`ElasticsearchTransform::to_search`], on the keyword fields.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::term::is_masked;
+use crate::term::text;
+use crate::term::TermChar;
+use crate::translate::cql_name;
+use crate::translate::missing_value;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+/// `{kind: {field: value}}`
+fn leaf(kind: &str, field: &str, value: Value) -> Value {
+    let mut inner = Map::new();
+    inner.insert(String::from(field), value);
+    json!({ kind: inner })
+}
+
+/// `{field: options}`
+fn leaf_object(field: &str, options: Map<String, Value>) -> Value {
+    let mut res = Map::new();
+    res.insert(String::from(field), Value::Object(options));
+    Value::Object(res)
+}
+
+/// `{"bool": {occur: queries}}`, or the query itself if only one.
+fn bool_query(occur: &str, mut queries: Vec<Value>) -> Value {
+    if queries.len() == 1 {
+        return queries.remove(0);
+    }
+    json!({ "bool": { occur: queries } })
+}
+
+/// `prefix` for a single trailing `*`, `wildcard` otherwise.
+fn masked(field: &str, chars: &[TermChar]) -> Value {
+    if let Some((TermChar::Star, rest)) = chars.split_last() {
+        if !rest.is_empty() && !is_masked(rest) {
+            return leaf("prefix", field, json!({ "value": text(rest) }));
+        }
+    }
+    let mut pattern = String::new();
+    for ch in chars {
+        match ch {
+            TermChar::Literal(ch) => {
+                if "*?\\".contains(*ch) {
+                    pattern.push('\\');
+                }
+                pattern.push(*ch);
+            }
+            _ => pattern.push(ch.as_char()),
+        }
+    }
+    leaf("wildcard", field, json!({ "value": pattern }))
+}
+
+/// Query for one word of `all` and `any`.
+fn word(field: &str, chars: &[TermChar], keyword: bool) -> Value {
+    if is_masked(chars) {
+        masked(field, chars)
+    } else if keyword {
+        leaf("term", field, json!(text(chars)))
+    } else {
+        leaf("match", field, json!(text(chars)))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    text: String,
+    keyword: Option<String>,
+}
+
+impl Field {
+    fn name(&self, keyword: bool) -> &str {
+        match &self.keyword {
+            Some(name) if keyword => name,
+            _ => &self.text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ElasticsearchTransform {
+    fields: Mapping<Field>,
+}
+
+impl ElasticsearchTransform {
+    pub fn new() -> ElasticsearchTransform {
+        ElasticsearchTransform::default()
+    }
+
+    /// Search `index` in `field`. Without a keyword subfield, `field` is used
+    /// for exact matches, ranges and sorting too. `index` is matched as by
+    /// [`Mapping`].
+    pub fn field(&mut self, index: &str, field: &str, keyword: Option<&str>) {
+        let field = Field {
+            text: String::from(field),
+            keyword: keyword.map(String::from),
+        };
+        self.fields.insert(index, field);
+    }
+
+    /// Text fields searched, in `bool.should`, for `cql.serverChoice`
+    /// unless it is mapped with [`ElasticsearchTransform::field`].
+    pub fn default_fields(&mut self, fields: &[&str]) {
+        let fields = fields
+            .iter()
+            .map(|f| Field {
+                text: String::from(*f),
+                keyword: None,
+            })
+            .collect();
+        self.fields.server_choice(fields);
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.fields.insert_relation(name, relation);
+    }
+
+    fn fields_of(&self, clause: &Clause<'_>) -> Result<Vec<&Field>, TranslationError> {
+        self.fields
+            .resolve(clause.index())
+            .filter(|fields| !fields.is_empty())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))
+    }
+
+    /// The query on one field, with the keyword field if `keyword`.
+    fn clause(
+        field: &Field,
+        relation: &str,
+        clause: &Clause<'_>,
+        keyword: bool,
+    ) -> Result<Value, TranslationError> {
+        let name = field.name(keyword);
+        let term = clause.term();
+        let words = term.words();
+        let res = match relation {
+            "=" | "adj" | "scr" | "==" | "exact" | "<>" => {
+                let query = if !is_masked(&term.chars) {
+                    let kind = if keyword { "term" } else { "match_phrase" };
+                    leaf(kind, name, json!(term.text()))
+                } else if keyword || words.len() == 1 {
+                    masked(name, &term.chars)
+                } else {
+                    return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+                };
+                if relation == "<>" {
+                    json!({ "bool": { "must_not": [query] } })
+                } else {
+                    query
+                }
+            }
+            "any" if keyword && !is_masked(&term.chars) && words.len() > 1 => {
+                let words: Vec<String> = words.iter().map(|w| text(w)).collect();
+                leaf("terms", name, json!(words))
+            }
+            "all" | "any" => {
+                let words = clause.words()?;
+                let queries = words.iter().map(|w| word(name, w, keyword)).collect();
+                let occur = if relation == "all" { "must" } else { "should" };
+                bool_query(occur, queries)
+            }
+            "<" | "<=" | ">" | ">=" => {
+                let op = match relation {
+                    "<" => "lt",
+                    "<=" => "lte",
+                    ">" => "gt",
+                    _ => "gte",
+                };
+                clause.unmasked()?;
+                leaf("range", name, json!({ op: term.text() }))
+            }
+            _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        };
+        Ok(res)
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<Value, TranslationError> {
+        clause.unanchored()?;
+        let relation = self.fields.relation(clause)?;
+        let mut keyword = matches!(
+            relation.as_str(),
+            "==" | "exact" | "<>" | "<" | "<=" | ">" | ">="
+        );
+        for m in clause.st().modifiers() {
+            match cql_name(m.name()).as_deref() {
+                Some("ignorecase" | "word") if m.value().is_none() => keyword = false,
+                Some("respectcase" | "string") if m.value().is_none() => keyword = true,
+                _ => {
+                    let kind = TranslationErrorKind::UnsupportedRelationModifier;
+                    return Err(TranslationError::modifier(kind, m));
+                }
+            }
+        }
+        let queries: Result<Vec<Value>, TranslationError> = self
+            .fields_of(clause)?
+            .into_iter()
+            .map(|field| ElasticsearchTransform::clause(field, &relation, clause, keyword))
+            .collect();
+        Ok(bool_query("should", queries?))
+    }
+
+    /// Translate the search part of a query to a query DSL object.
+    pub fn to_query(&self, node: &CqlNode) -> Result<Value, TranslationError> {
+        Translator { transform: self }.translate(node)
+    }
+
+    /// Translate a query to a search request body, with `query`, and
+    /// `sort` if the query has sort keys.
+    pub fn to_search(&self, node: &CqlNode) -> Result<Value, TranslationError> {
+        let mut translator = Translator { transform: self };
+        let mut body = Map::new();
+        body.insert(String::from("query"), translator.translate(node)?);
+        let sort = translator.translate_sort(node)?;
+        if !sort.is_empty() {
+            body.insert(String::from("sort"), Value::Array(sort));
+        }
+        Ok(Value::Object(body))
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a ElasticsearchTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = Value;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<Value, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, Value)>,
+    ) -> Result<Value, TranslationError> {
+        let mut queries: Vec<Value> = operands.into_iter().map(|(_, q)| q).collect();
+        let res = match op {
+            BooleanOp::And => json!({ "bool": { "must": queries } }),
+            BooleanOp::Or => json!({ "bool": { "should": queries } }),
+            BooleanOp::Not => {
+                let must = vec![queries.remove(0)];
+                json!({ "bool": { "must": must, "must_not": queries } })
+            }
+            BooleanOp::Prox => return Err(TranslationError::boolean(bo)),
+        };
+        Ok(res)
+    }
+
+    fn flatten(&self, _op: BooleanOp) -> bool {
+        true
+    }
+}
+
+impl SortTranslator for Translator<'_> {
+    type SortKey = Value;
+
+    fn sort_key(&mut self, key: &St) -> Result<Value, TranslationError> {
+        let field = self
+            .transform
+            .fields
+            .get(key.index())
+            .ok_or_else(|| TranslationError::index(key))?;
+        let mut descending = false;
+        let mut missing = None;
+        for m in key.modifiers() {
+            match missing_value(m) {
+                Some(high) => missing = Some(high),
+                None => descending = crate::translate::descending(m)?,
+            }
+        }
+        let mut options = Map::new();
+        options.insert(
+            String::from("order"),
+            json!(if descending { "desc" } else { "asc" }),
+        );
+        if let Some(high) = missing {
+            let value = if high != descending {
+                "_last"
+            } else {
+                "_first"
+            };
+            options.insert(String::from("missing"), json!(value));
+        }
+        Ok(leaf_object(field.name(true), options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform() -> ElasticsearchTransform {
+        let mut transform = ElasticsearchTransform::new();
+        transform.field("dc.title", "title", Some("title.keyword"));
+        transform.field("date", "year", None);
+        transform
+    }
+
+    fn query(cql: &str) -> Result<Value, TranslationError> {
+        transform().to_query(&crate::parse(cql).unwrap())
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            query("dc.title = \"a b\"").unwrap(),
+            json!({"match_phrase": {"title": "a b"}})
+        );
+        assert_eq!(
+            query("dc.title adj \"a \\\"b\\\"\"").unwrap(),
+            json!({"match_phrase": {"title": "a \"b\""}})
+        );
+        assert_eq!(
+            query("dc.title == \"a b\"").unwrap(),
+            json!({"term": {"title.keyword": "a b"}})
+        );
+        assert_eq!(
+            query("dc.title all \"a b\"").unwrap(),
+            json!({"bool": {"must": [{"match": {"title": "a"}}, {"match": {"title": "b"}}]}})
+        );
+        assert_eq!(
+            query("dc.title any \"a b*\"").unwrap(),
+            json!({"bool": {"should": [
+                {"match": {"title": "a"}},
+                {"prefix": {"title": {"value": "b"}}}
+            ]}})
+        );
+        assert_eq!(
+            query("dc.title any a").unwrap(),
+            json!({"match": {"title": "a"}})
+        );
+        assert_eq!(
+            query("dc.title all \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            query("date < 2000").unwrap(),
+            json!({"range": {"year": {"lt": "2000"}}})
+        );
+        assert_eq!(
+            query("dc.title >= m").unwrap(),
+            json!({"range": {"title.keyword": {"gte": "m"}}})
+        );
+        assert_eq!(
+            query("dc.title <> x").unwrap(),
+            json!({"bool": {"must_not": [{"term": {"title.keyword": "x"}}]}})
+        );
+    }
+
+    #[test]
+    fn masking() {
+        assert_eq!(
+            query("dc.title = hob*").unwrap(),
+            json!({"prefix": {"title": {"value": "hob"}}})
+        );
+        assert_eq!(
+            query("dc.title = h?b\\*t*").unwrap(),
+            json!({"wildcard": {"title": {"value": "h?b\\*t*"}}})
+        );
+        assert_eq!(
+            query("dc.title == \"the hob*\"").unwrap(),
+            json!({"prefix": {"title.keyword": {"value": "the hob"}}})
+        );
+        assert_eq!(
+            query("dc.title = \"the hob*\"").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "the hob*")
+        );
+        assert_eq!(
+            query("date > 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        assert_eq!(
+            query("dc.title = ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(
+            query("dc.title =/cql.respectCase \"a b\"").unwrap(),
+            json!({"term": {"title.keyword": "a b"}})
+        );
+        assert_eq!(
+            query("dc.title ==/ignoreCase \"a b\"").unwrap(),
+            json!({"match_phrase": {"title": "a b"}})
+        );
+        assert_eq!(
+            query("dc.title any/string \"a b\"").unwrap(),
+            json!({"terms": {"title.keyword": ["a", "b"]}})
+        );
+        assert_eq!(
+            query("dc.title all/cql.word \"a\"").unwrap(),
+            json!({"match": {"title": "a"}})
+        );
+        assert_eq!(
+            query("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            query("dc.title = a and dc.title = b and date > 1").unwrap(),
+            json!({"bool": {"must": [
+                {"match_phrase": {"title": "a"}},
+                {"match_phrase": {"title": "b"}},
+                {"range": {"year": {"gt": "1"}}}
+            ]}})
+        );
+        assert_eq!(
+            query("dc.title = a or (dc.title = b and dc.title = c)").unwrap(),
+            json!({"bool": {"should": [
+                {"match_phrase": {"title": "a"}},
+                {"bool": {"must": [
+                    {"match_phrase": {"title": "b"}},
+                    {"match_phrase": {"title": "c"}}
+                ]}}
+            ]}})
+        );
+        assert_eq!(
+            query("dc.title = a not dc.title = b not dc.title = c").unwrap(),
+            json!({"bool": {
+                "must": [{"bool": {
+                    "must": [{"match_phrase": {"title": "a"}}],
+                    "must_not": [{"match_phrase": {"title": "b"}}]
+                }}],
+                "must_not": [{"match_phrase": {"title": "c"}}]
+            }})
+        );
+        assert_eq!(
+            query("dc.title = a prox dc.title = b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn server_choice() {
+        assert_eq!(
+            query("a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "cql.serverChoice")
+        );
+        let mut transform = transform();
+        transform.default_fields(&["title", "body"]);
+        let node = crate::parse("a").unwrap();
+        assert_eq!(
+            transform.to_query(&node).unwrap(),
+            json!({"bool": {"should": [
+                {"match_phrase": {"title": "a"}},
+                {"match_phrase": {"body": "a"}}
+            ]}})
+        );
+    }
+
+    #[test]
+    fn sort() {
+        let node = crate::parse(
+            "dc.title = a sortby date/sort.descending dc.title/missingValue=highValue",
+        )
+        .unwrap();
+        assert_eq!(
+            transform().to_search(&node).unwrap(),
+            json!({
+                "query": {"match_phrase": {"title": "a"}},
+                "sort": [
+                    {"year": {"order": "desc"}},
+                    {"title.keyword": {"order": "asc", "missing": "_last"}}
+                ]
+            })
+        );
+        let node = crate::parse("dc.title = a").unwrap();
+        assert_eq!(
+            transform().to_search(&node).unwrap(),
+            json!({"query": {"match_phrase": {"title": "a"}}})
+        );
+        let node = crate::parse("dc.title = a sortby date/sort.ignoreCase").unwrap();
+        assert_eq!(
+            transform().to_search(&node).unwrap_err().parts(),
+            (
+                TranslationErrorKind::UnsupportedSortModifier,
+                "sort.ignoreCase"
+            )
+        );
+    }
+}
@@ -1,2 +1,169 @@
-#[derive(Debug, Clone)]
-pub struct ParseError;
+use crate::span::Position;
+use crate::span::Span;
+use std::fmt;
+
+/// The kind of problem found while parsing a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The query contains nothing but whitespace.
+    EmptyQuery,
+    /// A search term or parenthesised query was expected.
+    MissingSearchTerm,
+    /// A `(` is not closed or a `)` has no matching `(`.
+    UnbalancedParenthesis,
+    /// A `/` is not followed by a modifier name.
+    MissingModifierName,
+    /// A modifier comparison is not followed by a value.
+    MissingModifierValue,
+    /// A `>` is not followed by a prefix name or URI.
+    InvalidPrefixAssignment,
+    /// A token that cannot continue the query.
+    UnexpectedToken,
+}
+
+/// Error from parsing a query, with the position of the offending token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseError {
+    inner: Box<Inner>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Inner {
+    kind: ErrorKind,
+    message: String,
+    span: Span,
+    token: String,
+    expected: Vec<String>,
+}
+
+impl ParseError {
+    pub(crate) fn new(
+        kind: ErrorKind,
+        message: &str,
+        span: Span,
+        token: &str,
+        expected: &[&str],
+    ) -> ParseError {
+        let inner = Inner {
+            kind,
+            message: String::from(message),
+            span,
+            token: String::from(token),
+            expected: expected.iter().map(|s| String::from(*s)).collect(),
+        };
+        ParseError {
+            inner: Box::new(inner),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.inner.kind
+    }
+
+    /// Description of the problem, such as "expected search term after relation".
+    pub fn message(&self) -> &str {
+        &self.inner.message
+    }
+
+    /// Where the offending token starts.
+    pub fn position(&self) -> Position {
+        self.inner.span.start
+    }
+
+    /// The offending token; empty at the end of the query.
+    pub fn span(&self) -> Span {
+        self.inner.span
+    }
+
+    /// The offending token as it would be shown to a user, such as `"="`
+    /// or `end of query`.
+    pub fn token(&self) -> &str {
+        &self.inner.token
+    }
+
+    /// Descriptions of the tokens that would have been accepted instead.
+    pub fn expected(&self) -> &[String] {
+        &self.inner.expected
+    }
+
+    /// Show the line of `query` containing the error with a caret under
+    /// the offending token, followed by the error message.
+    pub fn render(&self, query: &str) -> String {
+        let line = query
+            .split('\n')
+            .nth(self.inner.span.start.line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r');
+        // keep tabs so that the caret lines up with the query line
+        let indent: String = line
+            .chars()
+            .take(self.inner.span.start.column - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{}\n{}^ {}, found {}",
+            line, indent, self.inner.message, self.inner.token
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, found {} at line {}, column {}",
+            self.inner.message,
+            self.inner.token,
+            self.inner.span.start.line,
+            self.inner.span.start.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(line: usize, column: usize) -> ParseError {
+        let position = Position {
+            offset: 0,
+            byte: 0,
+            line,
+            column,
+        };
+        ParseError::new(
+            ErrorKind::MissingSearchTerm,
+            "expected search term after relation",
+            Span::new(position, position),
+            "end of query",
+            &["search term", "\"(\""],
+        )
+    }
+
+    #[test]
+    fn display() {
+        let e = error_at(1, 5);
+        assert_eq!(
+            e.to_string(),
+            "expected search term after relation, found end of query at line 1, column 5"
+        );
+        assert_eq!(e.expected(), ["search term", "\"(\""]);
+    }
+
+    #[test]
+    fn render() {
+        let e = error_at(1, 5);
+        assert_eq!(
+            e.render("ti ="),
+            "ti =\n    ^ expected search term after relation, found end of query"
+        );
+
+        let e = error_at(2, 3);
+        assert_eq!(
+            e.render("a and\r\n\tb ="),
+            "\tb =\n\t ^ expected search term after relation, found end of query"
+        );
+    }
+}
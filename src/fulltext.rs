@@ -0,0 +1,587 @@
+//! Translation of CQL to full-text search expressions: PostgreSQL
+//! `tsquery` text for `to_tsquery`, and SQLite FTS5 `MATCH` expressions.
+//!
+//! Both are given as one string, to be bound as a parameter. Only text
+//! searches translate: `=` and `adj` become phrases, `all` and `any`
+//! words combined with and and or, and a trailing `*` on a word a prefix
+//! search. Other masking, and relations such as `<` or `==`, are errors.
+//! Sort keys are ignored.
+//!
+//! | CQL                  | `tsquery`                 | FTS5                     |
+//! |----------------------|---------------------------|--------------------------|
+//! | `"a b"`              | `('a' <-> 'b')`           | `"a b"`                  |
+//! | `ti all "a b*"`      | `('a':A & 'b':*A)`        | `title : ("a" AND "b" *)`|
+//! | `a not b`            | `'a' & !'b'`              | `"a" NOT "b"`            |
+//! | `a prox/distance=2/ordered b` | `'a' <2> 'b'`    | not supported            |
+//! | `a prox/distance<=3 b`| not supported            | `NEAR("a" "b", 2)`       |
+//! | `ti = ^a`            | not supported             | `title : ^ "a"`          |
+//!
+//! A `tsquery` has no columns, so indexes are mapped to weights with
+//! [`TsQueryTransform::index`]; FTS5 indexes to column filters with
+//! [`Fts5Transform::index`]. `cql.serverChoice` searches everything
+//! unless mapped.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::term::is_masked;
+use crate::term::text;
+use crate::term::TermChar;
+use crate::translate::infix;
+use crate::translate::unqualified;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// A word to search for.
+struct Word {
+    text: String,
+    /// Right-truncated with `*`.
+    prefix: bool,
+}
+
+/// The relation of a text search, as mapped by `mapping`, checking it
+/// has no modifiers.
+fn relation<T>(mapping: &Mapping<T>, clause: &Clause<'_>) -> Result<String, TranslationError> {
+    clause.no_modifiers()?;
+    let relation = mapping.relation(clause)?;
+    match relation.as_str() {
+        "=" | "adj" | "scr" | "all" | "any" => Ok(relation),
+        _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+    }
+}
+
+/// The words of a term; the anchors are left to the caller.
+fn words(clause: &Clause<'_>) -> Result<Vec<Word>, TranslationError> {
+    let mut res = Vec::new();
+    for word in clause.words()? {
+        let (body, prefix) = match word.split_last() {
+            Some((TermChar::Star, body)) => (body, true),
+            _ => (word, false),
+        };
+        if body.is_empty() || is_masked(body) {
+            return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+        }
+        res.push(Word {
+            text: text(body),
+            prefix,
+        });
+    }
+    Ok(res)
+}
+
+/// Proximity of `prox`: the distance, if at most or exactly, and
+/// whether ordered. Only word units are supported.
+fn proximity(modifiers: &[Modifier]) -> Result<(u32, bool, bool), TranslationError> {
+    let mut distance = 1;
+    let mut exact = false;
+    let mut ordered = false;
+    for m in modifiers {
+        let unsupported =
+            || TranslationError::modifier(TranslationErrorKind::UnsupportedProxModifier, m);
+        match (unqualified(m.name()).as_str(), m.relation(), m.value()) {
+            ("unit", Some("="), Some(unit)) if unit.eq_ignore_ascii_case("word") => (),
+            ("distance", Some(rel @ ("=" | "<=")), Some(value)) => {
+                distance = value.parse().map_err(|_| unsupported())?;
+                exact = rel == "=";
+            }
+            ("ordered", None, None) => ordered = true,
+            ("unordered", None, None) => ordered = false,
+            _ => return Err(unsupported()),
+        }
+    }
+    Ok((distance, exact, ordered))
+}
+
+/// Unsupported prox modifier `name` of `bo`.
+fn prox_modifier(bo: &Boolean, name: &str) -> TranslationError {
+    let kind = TranslationErrorKind::UnsupportedProxModifier;
+    match bo
+        .modifiers()
+        .iter()
+        .find(|m| unqualified(m.name()) == name)
+    {
+        Some(m) => TranslationError::modifier(kind, m),
+        None => TranslationError::new(kind, name, bo.span()),
+    }
+}
+
+/// A search clause for a phrase, without anchors.
+fn phrase_clause<'a, T>(mapping: &Mapping<T>, node: &'a CqlNode) -> Option<Clause<'a>> {
+    let CqlNode::St(st) = node else {
+        return None;
+    };
+    let clause = Clause::new(st, false);
+    let phrase = matches!(
+        relation(mapping, &clause).as_deref(),
+        Ok("=" | "adj" | "scr")
+    );
+    let term = clause.term();
+    (phrase && !term.first && !term.last).then_some(clause)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TsQueryTransform {
+    weights: Mapping<String>,
+}
+
+impl TsQueryTransform {
+    pub fn new() -> TsQueryTransform {
+        TsQueryTransform::default()
+    }
+
+    /// Search `index` in the lexemes with `weights`, such as `A` or `AB`.
+    /// `index` is matched as by [`Mapping`].
+    pub fn index(&mut self, index: &str, weights: &str) {
+        self.weights.insert(index, weights.to_ascii_uppercase());
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.weights.insert_relation(name, relation);
+    }
+
+    fn lexeme(word: &Word, weights: &str, out: &mut String) {
+        out.push('\'');
+        for ch in word.text.chars() {
+            match ch {
+                '\'' => out.push_str("''"),
+                '\\' => out.push_str("\\\\"),
+                _ => out.push(ch),
+            }
+        }
+        out.push('\'');
+        if word.prefix || !weights.is_empty() {
+            out.push(':');
+        }
+        if word.prefix {
+            out.push('*');
+        }
+        out.push_str(weights);
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        let weights = self
+            .weights
+            .resolve(clause.index())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        // any weight for an unmapped cql.serverChoice
+        let weights = weights.first().map_or("", |w| w.as_str());
+        let relation = relation(&self.weights, clause)?;
+        clause.unanchored()?;
+        let words = words(clause)?;
+        let mut out = String::new();
+        let op = match relation.as_str() {
+            "all" => " & ",
+            "any" => " | ",
+            _ => " <-> ",
+        };
+        if words.len() > 1 {
+            out.push('(');
+        }
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                out.push_str(op);
+            }
+            TsQueryTransform::lexeme(word, weights, &mut out);
+        }
+        if words.len() > 1 {
+            out.push(')');
+        }
+        Ok(out)
+    }
+
+    /// Translate a query to `tsquery` text.
+    pub fn to_tsquery(&self, node: &CqlNode) -> Result<String, TranslationError> {
+        TsQueryTranslator { transform: self }.translate(node)
+    }
+}
+
+struct TsQueryTranslator<'a> {
+    transform: &'a TsQueryTransform,
+}
+
+impl QueryTranslator for TsQueryTranslator<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        let op = match op {
+            BooleanOp::And => String::from(" & "),
+            BooleanOp::Or => String::from(" | "),
+            BooleanOp::Not => String::from(" & !"),
+            BooleanOp::Prox => {
+                let (distance, exact, ordered) = proximity(bo.modifiers())?;
+                if !exact && distance != 1 {
+                    return Err(prox_modifier(bo, "distance"));
+                }
+                let op = match distance {
+                    1 => String::from(" <-> "),
+                    n => format!(" <{}> ", n),
+                };
+                if !ordered {
+                    // both orders, as <N> is ordered
+                    let reversed = operands.iter().rev().cloned().collect();
+                    return Ok(format!(
+                        "({} | {})",
+                        infix(operands, &op),
+                        infix(reversed, &op)
+                    ));
+                }
+                op
+            }
+        };
+        Ok(infix(operands, &op))
+    }
+
+    fn accepts(&self, op: BooleanOp, modified: bool) -> bool {
+        op == BooleanOp::Prox || !modified
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fts5Transform {
+    columns: Mapping<Vec<String>>,
+}
+
+impl Fts5Transform {
+    pub fn new() -> Fts5Transform {
+        Fts5Transform::default()
+    }
+
+    /// Search `index` in the FTS5 `columns`. `index` is matched as by
+    /// [`Mapping`].
+    pub fn index(&mut self, index: &str, columns: &[&str]) {
+        let columns = columns.iter().map(|c| String::from(*c)).collect();
+        self.columns.insert(index, columns);
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.columns.insert_relation(name, relation);
+    }
+
+    /// The column filter for the index of `clause`, such as `title : `.
+    fn filter(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        let columns = self
+            .columns
+            .resolve(clause.index())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        // all columns for an unmapped cql.serverChoice
+        let res = match columns.first().map_or(&[][..], |c| c.as_slice()) {
+            [] => String::new(),
+            [column] => format!("{} : ", column),
+            columns => format!("{{{}}} : ", columns.join(" ")),
+        };
+        Ok(res)
+    }
+
+    /// A phrase of `words`, where only the last can be a prefix.
+    fn phrase(
+        clause: &Clause<'_>,
+        words: &[Word],
+        out: &mut String,
+    ) -> Result<(), TranslationError> {
+        if words[..words.len() - 1].iter().any(|w| w.prefix) {
+            return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+        }
+        out.push('"');
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(&word.text.replace('"', "\"\""));
+        }
+        out.push('"');
+        if words[words.len() - 1].prefix {
+            out.push_str(" *");
+        }
+        Ok(())
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        let filter = self.filter(clause)?;
+        let relation = relation(&self.columns, clause)?;
+        let term = clause.term();
+        let words = words(clause)?;
+        let phrase = matches!(relation.as_str(), "=" | "adj" | "scr");
+        if term.last || (term.first && !phrase) {
+            return Err(clause.error(TranslationErrorKind::UnsupportedAnchoring));
+        }
+        let mut out = filter;
+        if term.first {
+            out.push_str("^ ");
+        }
+        if phrase {
+            Fts5Transform::phrase(clause, &words, &mut out)?;
+            return Ok(out);
+        }
+        let op = if relation == "all" { " AND " } else { " OR " };
+        if words.len() > 1 {
+            out.push('(');
+        }
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                out.push_str(op);
+            }
+            Fts5Transform::phrase(clause, std::slice::from_ref(word), &mut out)?;
+        }
+        if words.len() > 1 {
+            out.push(')');
+        }
+        Ok(out)
+    }
+
+    /// `NEAR` group of two phrases on the same index.
+    fn near(&self, bo: &Boolean) -> Result<String, TranslationError> {
+        let (distance, exact, ordered) = proximity(bo.modifiers())?;
+        if exact {
+            return Err(prox_modifier(bo, "distance"));
+        }
+        if ordered {
+            return Err(prox_modifier(bo, "ordered"));
+        }
+        let a = phrase_clause(&self.columns, bo.left());
+        let b = phrase_clause(&self.columns, bo.right());
+        let (Some(a), Some(b)) = (a, b) else {
+            return Err(TranslationError::boolean(bo));
+        };
+        let mut out = self.filter(&a)?;
+        if out != self.filter(&b)? {
+            return Err(TranslationError::boolean(bo));
+        }
+        out.push_str("NEAR(");
+        Fts5Transform::phrase(&a, &words(&a)?, &mut out)?;
+        out.push(' ');
+        Fts5Transform::phrase(&b, &words(&b)?, &mut out)?;
+        // NEAR counts the tokens between the phrases
+        out.push_str(&format!(", {})", distance.saturating_sub(1)));
+        Ok(out)
+    }
+
+    /// Translate a query to an FTS5 `MATCH` expression.
+    pub fn to_match(&self, node: &CqlNode) -> Result<String, TranslationError> {
+        Fts5Translator { transform: self }.translate(node)
+    }
+}
+
+struct Fts5Translator<'a> {
+    transform: &'a Fts5Transform,
+}
+
+impl QueryTranslator for Fts5Translator<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        let op = match op {
+            BooleanOp::And => " AND ",
+            BooleanOp::Or => " OR ",
+            BooleanOp::Not => " NOT ",
+            BooleanOp::Prox => return self.transform.near(bo),
+        };
+        Ok(infix(operands, op))
+    }
+
+    fn accepts(&self, op: BooleanOp, modified: bool) -> bool {
+        op == BooleanOp::Prox || !modified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn tsquery(query: &str) -> Result<String, TranslationError> {
+        let mut transform = TsQueryTransform::new();
+        transform.index("dc.title", "a");
+        transform.index("dc.creator", "BC");
+        transform.to_tsquery(&crate::parse(query).unwrap())
+    }
+
+    fn fts5(query: &str) -> Result<String, TranslationError> {
+        let mut transform = Fts5Transform::new();
+        transform.index("dc.title", &["title"]);
+        transform.index("dc.creator", &["author"]);
+        transform.index("text", &["title", "author"]);
+        transform.relation("bib.contains", "any");
+        transform.to_match(&crate::parse(query).unwrap())
+    }
+
+    #[test]
+    fn tsqueries() {
+        assert_eq!(tsquery("fat").unwrap(), "'fat'");
+        assert_eq!(tsquery("\"fat rats\"").unwrap(), "('fat' <-> 'rats')");
+        assert_eq!(
+            tsquery("dc.title all \"fat ra*\"").unwrap(),
+            "('fat':A & 'ra':*A)"
+        );
+        assert_eq!(
+            tsquery("dc.creator any \"o'neil\"").unwrap(),
+            "'o''neil':BC"
+        );
+        assert_eq!(
+            tsquery("a and (b or c) not d").unwrap(),
+            "('a' & ('b' | 'c')) & !'d'"
+        );
+        assert_eq!(
+            tsquery("a prox/distance=2/ordered b").unwrap(),
+            "'a' <2> 'b'"
+        );
+        assert_eq!(
+            tsquery("a prox \"b c\"").unwrap(),
+            "('a' <-> ('b' <-> 'c') | ('b' <-> 'c') <-> 'a')"
+        );
+        assert_eq!(
+            tsquery("a prox/distance<=3 b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedProxModifier, "distance")
+        );
+        assert_eq!(
+            tsquery("a prox/unit=sentence b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedProxModifier, "unit")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            tsquery("*fix").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "*fix")
+        );
+        assert_eq!(
+            fts5("fi?x").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "fi?x")
+        );
+        assert_eq!(
+            fts5("\"a* b\"").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a* b")
+        );
+        assert_eq!(
+            tsquery("^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            fts5("a^").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "a^")
+        );
+        assert_eq!(
+            tsquery("dc.date < 2000").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.date")
+        );
+        assert_eq!(
+            tsquery("dc.title == a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "==")
+        );
+        assert_eq!(
+            fts5("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+        let e = fts5("dc.title = \" \"").unwrap_err();
+        assert_eq!(e.parts(), (TranslationErrorKind::EmptyTerm, " "));
+        assert_eq!(e.span().source("dc.title = \" \""), Some("\" \""));
+        assert_eq!(e.to_string(), "no words to search for in \" \"");
+        assert_eq!(
+            fts5("a prox (b or c)").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+        assert_eq!(
+            fts5("a prox/ordered b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedProxModifier, "ordered")
+        );
+    }
+
+    #[test]
+    fn fts5_expressions() {
+        assert_eq!(fts5("\"fat rats\"").unwrap(), "\"fat rats\"");
+        assert_eq!(
+            fts5("dc.title = \"fat ra*\"").unwrap(),
+            "title : \"fat ra\" *"
+        );
+        assert_eq!(
+            fts5("dc.title all \"a b*\"").unwrap(),
+            "title : (\"a\" AND \"b\" *)"
+        );
+        assert_eq!(
+            fts5("dc.title bib.contains \"a b\"").unwrap(),
+            "title : (\"a\" OR \"b\")"
+        );
+        assert_eq!(fts5("text = say").unwrap(), "{title author} : \"say\"");
+        assert_eq!(fts5("dc.title = ^a").unwrap(), "title : ^ \"a\"");
+        assert_eq!(fts5("\"6\\\"\"").unwrap(), "\"6\"\"\"");
+        assert_eq!(
+            fts5("a not (b or c) and d").unwrap(),
+            "(\"a\" NOT (\"b\" OR \"c\")) AND \"d\""
+        );
+        assert_eq!(
+            fts5("dc.title = a prox/distance<=3 dc.title = \"b c\"").unwrap(),
+            "title : NEAR(\"a\" \"b c\", 2)"
+        );
+    }
+
+    fn search(db: &Connection, query: &str) -> Vec<i64> {
+        let expr = fts5(query).unwrap();
+        let mut stmt = db
+            .prepare("SELECT rowid FROM books WHERE books MATCH ? ORDER BY rowid")
+            .unwrap();
+        let rows = stmt.query_map([expr], |row| row.get(0)).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn sqlite_fts5() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE VIRTUAL TABLE books USING fts5(title, author);
+             INSERT INTO books (rowid, title, author) VALUES
+                 (1, 'The Hobbit', 'J. R. R. Tolkien'),
+                 (2, 'The Lord of the Rings', 'J. R. R. Tolkien'),
+                 (3, 'The Fellowship of the Ring', 'Tolkien'),
+                 (4, 'Ring of Fire', 'Hobbit Smith');",
+        )
+        .unwrap();
+        assert_eq!(search(&db, "hobbit"), [1, 4]);
+        assert_eq!(search(&db, "dc.title = hobbit"), [1]);
+        assert_eq!(search(&db, "dc.title = ring*"), [2, 3, 4]);
+        assert_eq!(search(&db, "dc.title = \"the ring\""), [3]);
+        assert_eq!(search(&db, "dc.title = ^ring"), [4]);
+        assert_eq!(search(&db, "dc.title all \"ring of\""), [3, 4]);
+        assert_eq!(search(&db, "dc.title any \"hobbit fire\""), [1, 4]);
+        assert_eq!(search(&db, "tolkien not dc.title = rings"), [1, 3]);
+        assert_eq!(
+            search(&db, "dc.title = lord prox/distance<=3 dc.title = rings"),
+            [2]
+        );
+        assert_eq!(
+            search(&db, "dc.title = lord prox/distance<=2 dc.title = rings"),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            search(&db, "text = smith or dc.creator = \"r. tolkien\""),
+            [1, 2, 4]
+        );
+    }
+}
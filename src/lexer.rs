@@ -1,6 +1,10 @@
 use crate::error::ParseError;
+use crate::span::Position;
+use crate::span::Span;
+use std::fmt;
 
 #[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) enum Token {
     EOS,
     EQ,
@@ -19,10 +23,34 @@ pub(crate) enum Token {
     Sortby(String),
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::EOS => write!(f, "end of query"),
+            Token::EQ => write!(f, "\"=\""),
+            Token::NE => write!(f, "\"<>\""),
+            Token::LT => write!(f, "\"<\""),
+            Token::GT => write!(f, "\">\""),
+            Token::LE => write!(f, "\"<=\""),
+            Token::GE => write!(f, "\">=\""),
+            Token::Exact => write!(f, "\"==\""),
+            Token::Modifier => write!(f, "\"/\""),
+            Token::LP => write!(f, "\"(\""),
+            Token::RP => write!(f, "\")\""),
+            Token::PrefixName(s) | Token::SimpleString(s) | Token::Boolop(s) | Token::Sortby(s) => {
+                write!(f, "\"{}\"", s)
+            }
+        }
+    }
+}
+
 pub(crate) struct Lexer {
     strict: bool,
     first: bool,
     look_ch: Option<char>,
+    pos: Position,
+    token_start: Position,
+    token_end: Position,
 }
 
 impl Lexer {
@@ -31,22 +59,50 @@ impl Lexer {
             strict: false,
             first: true,
             look_ch: None,
+            pos: Position::default(),
+            token_start: Position::default(),
+            token_end: Position::default(),
         }
     }
 
-    pub(crate) fn strict(self: &mut Self, strict: bool) {
+    pub(crate) fn strict(&mut self, strict: bool) {
         self.strict = strict;
     }
 
-    pub(crate) fn next(self: &mut Self, get: &mut dyn Iterator<Item = char>) {
+    /// Start lexing a new query from `get`.
+    pub(crate) fn start(&mut self, get: &mut dyn Iterator<Item = char>) {
+        self.pos = Position::default();
+        self.token_start = Position::default();
+        self.token_end = Position::default();
+        self.look_ch = get.next();
+        self.first = false;
+    }
+
+    pub(crate) fn next(&mut self, get: &mut dyn Iterator<Item = char>) {
+        if let Some(ch) = self.look_ch {
+            self.pos.advance(ch);
+        }
         self.look_ch = get.next();
         self.first = false;
     }
 
-    pub(crate) fn lex(
-        self: &mut Self,
-        get: &mut dyn Iterator<Item = char>,
-    ) -> Result<Token, ParseError> {
+    /// Position of the first character of the token last returned by `lex`.
+    pub(crate) fn token_start(&self) -> Position {
+        self.token_start
+    }
+
+    /// Text of the token last returned by `lex`, including quotes.
+    pub(crate) fn token_span(&self) -> Span {
+        Span::new(self.token_start, self.token_end)
+    }
+
+    pub(crate) fn lex(&mut self, get: &mut dyn Iterator<Item = char>) -> Result<Token, ParseError> {
+        let token = self.lex_token(get)?;
+        self.token_end = self.pos;
+        Ok(token)
+    }
+
+    fn lex_token(&mut self, get: &mut dyn Iterator<Item = char>) -> Result<Token, ParseError> {
         if self.first {
             self.next(get);
         }
@@ -56,6 +112,7 @@ impl Lexer {
             }
             self.next(get);
         }
+        self.token_start = self.pos;
         let ch = match self.look_ch {
             Some(ch1) => ch1,
             None => return Ok(Token::EOS),
@@ -69,7 +126,7 @@ impl Lexer {
                         return Ok(Token::Exact);
                     }
                 }
-                return Ok(Token::EQ);
+                Ok(Token::EQ)
             }
             '>' => {
                 self.next(get);
@@ -79,7 +136,7 @@ impl Lexer {
                         return Ok(Token::GE);
                     }
                 }
-                return Ok(Token::GT);
+                Ok(Token::GT)
             }
             '<' => {
                 self.next(get);
@@ -93,19 +150,19 @@ impl Lexer {
                         return Ok(Token::NE);
                     }
                 }
-                return Ok(Token::LT);
+                Ok(Token::LT)
             }
             '/' => {
                 self.next(get);
-                return Ok(Token::Modifier);
+                Ok(Token::Modifier)
             }
             '(' => {
                 self.next(get);
-                return Ok(Token::LP);
+                Ok(Token::LP)
             }
             ')' => {
                 self.next(get);
-                return Ok(Token::RP);
+                Ok(Token::RP)
             }
             '"' => {
                 self.next(get);
@@ -124,12 +181,11 @@ impl Lexer {
                         }
                     }
                 }
-                return Ok(Token::SimpleString(s));
+                Ok(Token::SimpleString(s))
             }
             _ => {
                 let mut s = String::new();
                 let mut relation_like = self.strict;
-                println!("relation_like = {}", relation_like);
                 while let Some(ch) = self.look_ch {
                     if " \n()=<>/".find(ch).is_some() {
                         break;
@@ -168,10 +224,9 @@ impl Lexer {
                     relation_like = true;
                 }
                 if relation_like {
-                    println!("returning prefixname");
                     return Ok(Token::PrefixName(s));
                 }
-                return Ok(Token::SimpleString(s));
+                Ok(Token::SimpleString(s))
             }
         }
     }
@@ -319,4 +374,57 @@ mod tests {
         let res = my.lex(it.borrow_mut());
         assert!(res.is_ok_and(|tok| tok == Token::PrefixName(String::from("adJ"))));
     }
+
+    #[test]
+    fn positions() {
+        let mut it = "ti =\n  \"ä b\" x".chars();
+        let mut my = Lexer::new();
+        my.start(it.borrow_mut());
+
+        let res = my.lex(it.borrow_mut());
+        assert!(res.is_ok_and(|tok| tok == Token::SimpleString(String::from("ti"))));
+        assert_eq!(my.token_start(), Position::default());
+
+        let res = my.lex(it.borrow_mut());
+        assert!(res.is_ok_and(|tok| tok == Token::EQ));
+        assert_eq!(my.token_start().offset, 3);
+        assert_eq!(my.token_start().column, 4);
+
+        let res = my.lex(it.borrow_mut());
+        assert!(res.is_ok_and(|tok| tok == Token::SimpleString(String::from("ä b"))));
+        assert_eq!(my.token_start().offset, 7);
+        assert_eq!(my.token_start().line, 2);
+        assert_eq!(my.token_start().column, 3);
+
+        let res = my.lex(it.borrow_mut());
+        assert!(res.is_ok_and(|tok| tok == Token::SimpleString(String::from("x"))));
+        assert_eq!(my.token_start().offset, 13);
+        assert_eq!(my.token_start().byte, 14);
+        assert_eq!(my.token_start().column, 9);
+
+        let res = my.lex(it.borrow_mut());
+        assert!(res.is_ok_and(|tok| tok == Token::EOS));
+        assert_eq!(my.token_start().offset, 14);
+    }
+
+    #[test]
+    fn spans() {
+        let query = " (dc.ti<>\"a b\"/x";
+        let mut it = query.chars();
+        let mut my = Lexer::new();
+        my.start(it.borrow_mut());
+        let mut texts = Vec::new();
+        while my.lex(it.borrow_mut()).is_ok_and(|tok| tok != Token::EOS) {
+            texts.push(my.token_span().source(query).unwrap());
+        }
+        assert_eq!(texts, ["(", "dc.ti", "<>", "\"a b\"", "/", "x"]);
+        assert!(my.token_span().is_empty());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Token::EOS.to_string(), "end of query");
+        assert_eq!(Token::NE.to_string(), "\"<>\"");
+        assert_eq!(Token::Boolop(String::from("AND")).to_string(), "\"AND\"");
+    }
 }
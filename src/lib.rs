@@ -1,8 +1,93 @@
+//! Parser for the Contextual Query Language (CQL).
+//!
+//! ```
+//! let query = cql_rust::parse("dc.title = \"the hobbit\" and year > 1930").unwrap();
+//! let cql_rust::CqlNode::Root(root) = query else { panic!() };
+//! let cql_rust::CqlNode::Boolean(bo) = root.search() else { panic!() };
+//! assert_eq!(bo.value(), "and");
+//! ```
+//!
+//! # Features
+//!
+//! `serde`: [`CqlNode`] and the types in [`node`] implement `Serialize`
+//! and `Deserialize`. Nodes are objects tagged by `"type"`; fields that
+//! are absent, empty or `None` are left out, and spans are not included:
+//!
+//! ```
+//! # #[cfg(feature = "serde")] {
+//! use serde_json::json;
+//!
+//! let text = ">dc=\"http://dc\" dc.title = hobbit and x sortby dc.date/sort.descending";
+//! let query = cql_rust::parse(text).unwrap();
+//! let cql = "info:srw/cql-context-set/1/cql-v1.2";
+//! assert_eq!(serde_json::to_value(&query).unwrap(), json!({
+//!     "type": "root",
+//!     "search": {
+//!         "type": "boolean", "value": "and",
+//!         "left": {"type": "searchClause", "index": "dc.title", "indexUri": "http://dc",
+//!                  "relation": "=", "relationUri": cql, "term": "hobbit"},
+//!         "right": {"type": "searchClause", "index": "cql.serverChoice", "indexUri": cql,
+//!                   "relation": "=", "relationUri": cql, "term": "x"},
+//!         "prefixes": [{"name": "dc", "uri": "http://dc"}]},
+//!     "sort": [{"index": "dc.date", "indexUri": "http://dc",
+//!               "modifiers": [{"name": "sort.descending",
+//!                              "nameUri": "info:srw/cql-context-set/1/sort-v1.0"}]}]
+//! }));
+//! # }
+//! ```
+//!
+//! An error node from a recovering parse is
+//! `{"type": "error", "message": "expected search term"}`.
+//!
+//! Modifiers are `{"name", "nameUri", "relation", "value"}`, with
+//! `relation` and `value` given together or not at all. Deserializing
+//! rejects trees the parser cannot produce, such as a root nested inside
+//! a boolean, unknown boolean operators, search clauses without a term
+//! and sort keys with one.
+//!
+//! `elasticsearch`: the [`elasticsearch`] module, translating queries to
+//! the Elasticsearch and OpenSearch query DSL as `serde_json` values.
+//!
+//! `mongodb`: the [`mongodb`] module, translating queries to MongoDB
+//! filter documents as `serde_json` values or BSON.
+//!
+//! `tantivy`: the [`tantivy`](mod@tantivy) module, translating queries to
+//! tantivy query objects.
+
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
 
+pub mod ccl;
+pub mod cst;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
 pub mod error;
+pub mod fulltext;
 pub mod lexer;
+#[cfg(feature = "mongodb")]
+pub mod mongodb;
 pub mod node;
+pub mod odata;
 pub mod parser;
+pub mod pqf;
+pub mod rpn;
+pub mod solr;
+pub mod span;
+pub mod sparql;
+pub mod sql;
+#[cfg(feature = "tantivy")]
+pub mod tantivy;
+pub mod term;
+pub mod translate;
+pub mod writer;
+pub mod xcql;
+pub mod xpath;
+
+pub use error::ParseError;
+pub use node::CqlNode;
+
+/// Parse a query, failing at the first error.
+pub fn parse(query: &str) -> Result<CqlNode, ParseError> {
+    query.parse()
+}
@@ -0,0 +1,441 @@
+//! Translation of CQL to MongoDB filter documents.
+//!
+//! Indexes are mapped to field paths with [`MongoTransform::field`].
+//! Search clauses translate as follows:
+//!
+//! | CQL              | Filter                                              |
+//! |------------------|-----------------------------------------------------|
+//! | `ti = "a.b"`     | `{"title": {"$regex": "a\\.b", "$options": "i"}}`   |
+//! | `ti = ^a*`       | `{"title": {"$regex": "^a.*", "$options": "i"}}`    |
+//! | `ti == a`        | `{"title": "a"}`                                    |
+//! | `ti == a?`       | `{"title": {"$regex": "^a.$"}}`                     |
+//! | `ti all "a b"`   | `{"$and": [{"title": {"$regex": "a", ...}}, ...]}`  |
+//! | `ti any "a b"`   | `{"$or": [...]}`                                    |
+//! | `year < 2000`    | `{"year": {"$lt": 2000}}`                           |
+//! | `ti <> a`        | `{"title": {"$ne": "a"}}`                           |
+//! | `"a b"`          | `{"$text": {"$search": "\"a b\""}}`                 |
+//!
+//! So `=` and `adj` match the term anywhere in the field, ignoring
+//! case, unless it is anchored with `^`, and masked `==` terms must
+//! match the whole value. Terms are escaped for regular expressions.
+//! Terms on number fields are converted to numbers. `cql.serverChoice`
+//! uses the text index with `$text`, which MongoDB allows once per query
+//! and not under `$nor`. Booleans become `$and`, `$or` and `$nor`. Sort
+//! keys are ignored.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::term::is_masked;
+use crate::term::regex;
+use crate::term::text;
+use crate::term::TermChar;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+/// Type of a field, deciding how terms are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FieldType {
+    #[default]
+    String,
+    /// Terms are converted to integers, or doubles if they are not.
+    Number,
+}
+
+/// `{path: condition}`
+fn field(path: &str, condition: Value) -> Value {
+    let mut res = Map::new();
+    res.insert(String::from(path), condition);
+    Value::Object(res)
+}
+
+/// `{op: queries}`, or the query itself if only one.
+fn combine(op: &str, mut queries: Vec<Value>) -> Value {
+    if queries.len() == 1 {
+        return queries.remove(0);
+    }
+    json!({ op: queries })
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    path: String,
+    field_type: FieldType,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MongoTransform {
+    fields: Mapping<Field>,
+}
+
+/// Filter being built; `$text` may only be used once.
+struct Builder<'a> {
+    transform: &'a MongoTransform,
+    text_used: bool,
+}
+
+impl Builder<'_> {
+    /// `$text` search for a `cql.serverChoice` clause.
+    fn text(&mut self, clause: &Clause<'_>, relation: &str) -> Result<Value, TranslationError> {
+        if self.text_used || clause.negated() {
+            return Err(clause.error(TranslationErrorKind::UnsupportedIndex));
+        }
+        self.text_used = true;
+        clause.unanchored()?;
+        clause.unmasked()?;
+        let words: Vec<String> = clause
+            .words()?
+            .iter()
+            .map(|w| text(w))
+            // a leading `-` would negate the word
+            .map(|w| String::from(w.replace('"', "").trim_start_matches('-')))
+            .filter(|w| !w.is_empty())
+            .collect();
+        if words.is_empty() {
+            return Err(clause.error(TranslationErrorKind::EmptyTerm));
+        }
+        let search = match relation {
+            "=" | "adj" | "scr" if words.len() > 1 => format!("\"{}\"", words.join(" ")),
+            "all" => {
+                let words: Vec<String> = words.iter().map(|w| format!("\"{}\"", w)).collect();
+                words.join(" ")
+            }
+            "=" | "adj" | "scr" | "any" => words.join(" "),
+            _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        };
+        Ok(json!({ "$text": { "$search": search } }))
+    }
+
+    fn number(clause: &Clause<'_>) -> Result<Value, TranslationError> {
+        clause.unanchored()?;
+        clause.unmasked()?;
+        let literal = clause.term().text();
+        let literal = literal.trim();
+        if let Ok(n) = literal.parse::<i64>() {
+            return Ok(json!(n));
+        }
+        match literal
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+        {
+            Some(n) => Ok(Value::Number(n)),
+            None => Err(clause.error(TranslationErrorKind::InvalidNumber)),
+        }
+    }
+
+    /// Case-insensitive regular expression finding `chars` anywhere.
+    fn contains(path: &str, chars: &[TermChar], first: bool, last: bool) -> Value {
+        let pattern = regex(chars, first, last);
+        field(path, json!({ "$regex": pattern, "$options": "i" }))
+    }
+
+    fn field_clause(
+        &self,
+        target: &Field,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<Value, TranslationError> {
+        let term = clause.term();
+        let path = target.path.as_str();
+        let op = match relation {
+            "<" => "$lt",
+            "<=" => "$lte",
+            ">" => "$gt",
+            ">=" => "$gte",
+            "<>" => "$ne",
+            _ => "",
+        };
+        if target.field_type == FieldType::Number {
+            let value = Builder::number(clause)?;
+            return match relation {
+                "=" | "==" | "exact" | "scr" => Ok(field(path, value)),
+                _ if !op.is_empty() => Ok(field(path, json!({ op: value }))),
+                _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+            };
+        }
+        match relation {
+            "=" | "adj" | "scr" => Ok(Builder::contains(path, &term.chars, term.first, term.last)),
+            "all" | "any" => {
+                clause.unanchored()?;
+                let queries = clause
+                    .words()?
+                    .iter()
+                    .map(|w| Builder::contains(path, w, false, false))
+                    .collect();
+                Ok(combine(
+                    if relation == "all" { "$and" } else { "$or" },
+                    queries,
+                ))
+            }
+            "==" | "exact" => {
+                clause.unanchored()?;
+                if is_masked(&term.chars) {
+                    let pattern = regex(&term.chars, true, true);
+                    return Ok(field(path, json!({ "$regex": pattern })));
+                }
+                Ok(field(path, json!(term.text())))
+            }
+            _ if !op.is_empty() => {
+                clause.unanchored()?;
+                clause.unmasked()?;
+                Ok(field(path, json!({ op: term.text() })))
+            }
+            _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+    }
+
+    fn search_clause(&mut self, clause: &Clause<'_>) -> Result<Value, TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.transform.fields.relation(clause)?;
+        let index = clause.index();
+        match self.transform.fields.get(index) {
+            Some(field) => self.field_clause(field, &relation, clause),
+            None if index.eq_ignore_ascii_case("cql.serverChoice") => self.text(clause, &relation),
+            None => Err(clause.error(TranslationErrorKind::UnsupportedIndex)),
+        }
+    }
+}
+
+impl QueryTranslator for Builder<'_> {
+    type Output = Value;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<Value, TranslationError> {
+        self.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, Value)>,
+    ) -> Result<Value, TranslationError> {
+        let mut queries: Vec<Value> = operands.into_iter().map(|(_, q)| q).collect();
+        match op {
+            BooleanOp::And => Ok(json!({ "$and": queries })),
+            BooleanOp::Or => Ok(json!({ "$or": queries })),
+            BooleanOp::Not => {
+                let nor = queries.split_off(1);
+                queries.push(json!({ "$nor": nor }));
+                Ok(json!({ "$and": queries }))
+            }
+            BooleanOp::Prox => Err(TranslationError::boolean(bo)),
+        }
+    }
+
+    fn flatten(&self, _op: BooleanOp) -> bool {
+        true
+    }
+}
+
+impl MongoTransform {
+    pub fn new() -> MongoTransform {
+        MongoTransform::default()
+    }
+
+    /// Search `index` in the field at `path`, such as `title` or
+    /// `meta.title`. `index` is matched as by [`Mapping`].
+    pub fn field(&mut self, index: &str, path: &str, field_type: FieldType) {
+        let field = Field {
+            path: String::from(path),
+            field_type,
+        };
+        self.fields.insert(index, field);
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.fields.insert_relation(name, relation);
+    }
+
+    /// Translate a query to a filter document.
+    pub fn to_filter(&self, node: &CqlNode) -> Result<Value, TranslationError> {
+        let mut builder = Builder {
+            transform: self,
+            text_used: false,
+        };
+        builder.translate(node)
+    }
+
+    /// Translate a query to a filter document as BSON.
+    pub fn to_bson(&self, node: &CqlNode) -> Result<bson::Document, TranslationError> {
+        let filter = self.to_filter(node)?;
+        // an object of strings, numbers and arrays always converts
+        Ok(bson::to_document(&filter).expect("filter converts to BSON"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn transform() -> MongoTransform {
+        let mut transform = MongoTransform::new();
+        transform.field("dc.title", "title", FieldType::String);
+        transform.field("dc.creator", "meta.author", FieldType::String);
+        transform.field("year", "year", FieldType::Number);
+        transform
+    }
+
+    fn filter(query: &str) -> Result<Value, TranslationError> {
+        transform().to_filter(&crate::parse(query).unwrap())
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            filter("dc.title = \"a.b (c)\"").unwrap(),
+            json!({"title": {"$regex": "a\\.b \\(c\\)", "$options": "i"}})
+        );
+        assert_eq!(
+            filter("dc.title = ^the*").unwrap(),
+            json!({"title": {"$regex": "^the.*", "$options": "i"}})
+        );
+        assert_eq!(
+            filter("dc.creator == \"Tolkien\"").unwrap(),
+            json!({"meta.author": "Tolkien"})
+        );
+        assert_eq!(
+            filter("dc.creator == Tol?ien*").unwrap(),
+            json!({"meta.author": {"$regex": "^Tol.ien.*$"}})
+        );
+        assert_eq!(
+            filter("dc.title any \"a b\"").unwrap(),
+            json!({"$or": [
+                {"title": {"$regex": "a", "$options": "i"}},
+                {"title": {"$regex": "b", "$options": "i"}}
+            ]})
+        );
+        assert_eq!(
+            filter("dc.title all \"\"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, "")
+        );
+        assert_eq!(
+            filter("year < 2000").unwrap(),
+            json!({"year": {"$lt": 2000}})
+        );
+        assert_eq!(
+            filter("year >= 1.5").unwrap(),
+            json!({"year": {"$gte": 1.5}})
+        );
+        assert_eq!(filter("year = 1937").unwrap(), json!({"year": 1937}));
+        assert_eq!(
+            filter("dc.title <> x").unwrap(),
+            json!({"title": {"$ne": "x"}})
+        );
+        assert_eq!(
+            filter("dc.title > m").unwrap(),
+            json!({"title": {"$gt": "m"}})
+        );
+    }
+
+    #[test]
+    fn text_search() {
+        assert_eq!(
+            filter("\"a b\"").unwrap(),
+            json!({"$text": {"$search": "\"a b\""}})
+        );
+        assert_eq!(
+            filter("cql.serverChoice all \"a b\"").unwrap(),
+            json!({"$text": {"$search": "\"a\" \"b\""}})
+        );
+        assert_eq!(
+            filter("cql.serverChoice any \"a b\" and year > 1").unwrap(),
+            json!({"$and": [{"$text": {"$search": "a b"}}, {"year": {"$gt": 1}}]})
+        );
+        assert_eq!(
+            filter("cql.serverChoice any \"a -b --\"").unwrap(),
+            json!({"$text": {"$search": "a b"}})
+        );
+        assert_eq!(
+            filter("cql.serverChoice any \"- --\"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, "- --")
+        );
+        assert_eq!(
+            filter("a and b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "cql.serverChoice")
+        );
+        assert_eq!(
+            filter("year > 1 not a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "cql.serverChoice")
+        );
+        assert_eq!(
+            filter("a*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a*")
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            filter("dc.title == a and dc.title == b and (dc.title == c or dc.title == d)").unwrap(),
+            json!({"$and": [
+                {"title": "a"},
+                {"title": "b"},
+                {"$or": [{"title": "c"}, {"title": "d"}]}
+            ]})
+        );
+        assert_eq!(
+            filter("dc.title == a not (dc.title == b or dc.title == c)").unwrap(),
+            json!({"$and": [
+                {"title": "a"},
+                {"$nor": [{"title": "b"}, {"title": "c"}]}
+            ]})
+        );
+        assert_eq!(
+            filter("dc.title == a prox dc.title == b")
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            filter("dc.subject = a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            filter("year = 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        assert_eq!(
+            filter("year = soon").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidNumber, "soon")
+        );
+        assert_eq!(
+            filter("year any 1").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "any")
+        );
+        assert_eq!(
+            filter("dc.title < ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            filter("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+    }
+
+    #[test]
+    fn bson() {
+        let node = crate::parse("dc.title = ^a* and year <= 2000").unwrap();
+        assert_eq!(
+            transform().to_bson(&node).unwrap(),
+            doc! {"$and": [
+                {"title": {"$regex": "^a.*", "$options": "i"}},
+                {"year": {"$lte": 2000_i64}}
+            ]}
+        );
+    }
+}
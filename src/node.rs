@@ -1,38 +1,302 @@
-use std::rc::Rc;
+use crate::error::ParseError;
+use crate::parser::Parser;
+use crate::span::Ignored;
+use crate::span::Span;
+use std::str::FromStr;
 
-#[cfg_attr(test, derive(Debug))]
+#[cfg(feature = "serde")]
+mod serialize;
+
+/// URI of the CQL context set, which `cql.` indexes and unqualified relations belong to.
+pub const CQL_CONTEXT_SET: &str = "info:srw/cql-context-set/1/cql-v1.2";
+
+/// URI of the sort context set, which unqualified sort key modifiers belong to.
+pub const SORT_CONTEXT_SET: &str = "info:srw/cql-context-set/1/sort-v1.0";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Prefix {
+    name: Option<String>,
+    uri: String,
+    span: Ignored<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Modifier {
+    name: String,
+    name_uri: Option<String>,
+    relation: Option<String>,
+    value: Option<String>,
+    span: Ignored<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct St {
     index: String,
     index_uri: Option<String>,
     term: Option<String>,
     relation: String,
     relation_uri: Option<String>,
-    modifiers: Option<Rc<St>>,
+    modifiers: Vec<Modifier>,
+    prefixes: Vec<Prefix>,
+    span: Ignored<Span>,
+    index_span: Ignored<Option<Span>>,
+    relation_span: Ignored<Option<Span>>,
+    term_span: Ignored<Option<Span>>,
 }
 
-#[cfg_attr(test, derive(Debug))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Boolean {
     value: String,
     left: Box<CqlNode>,
     right: Box<CqlNode>,
-    modifiers: Option<Rc<St>>,
+    modifiers: Vec<Modifier>,
+    prefixes: Vec<Prefix>,
+    span: Ignored<Span>,
 }
 
-#[cfg_attr(test, derive(Debug))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Root {
     search: Box<CqlNode>,
     sort: Vec<St>,
+    span: Ignored<Span>,
+}
+
+/// Placeholder for a part of the query that could not be parsed, left
+/// in the tree by [`Parser::parse_recovering`](crate::parser::Parser::parse_recovering).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ErrorNode {
+    message: String,
+    span: Ignored<Span>,
 }
 
-#[cfg_attr(test, derive(Debug))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::large_enum_variant)]
 pub enum CqlNode {
     St(St),
     Boolean(Boolean),
     Root(Root),
+    Error(ErrorNode),
+}
+
+impl Prefix {
+    /// Assignment of `uri` to prefix `name`, or to the default context
+    /// set if `name` is `None`.
+    pub fn new(name: Option<&str>, uri: &str) -> Prefix {
+        Prefix {
+            name: name.map(String::from),
+            uri: String::from(uri),
+            span: Ignored(Span::default()),
+        }
+    }
+
+    pub(crate) fn with_span(mut self, span: Span) -> Prefix {
+        self.span = Ignored(span);
+        self
+    }
+
+    /// Prefix name, `None` for an assignment of the default context set.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// From the `>` to the end of the URI.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+}
+
+impl Modifier {
+    /// Modifier `/name`, or `/name relation value` when both are given.
+    pub fn new(name: &str, relation: Option<&str>, value: Option<&str>) -> Modifier {
+        Modifier {
+            name: String::from(name),
+            name_uri: None,
+            relation: relation.map(String::from),
+            value: value.map(String::from),
+            span: Ignored(Span::default()),
+        }
+    }
+
+    pub(crate) fn with_span(mut self, span: Span) -> Modifier {
+        self.span = Ignored(span);
+        self
+    }
+
+    pub fn with_uri(mut self, name_uri: Option<String>) -> Modifier {
+        self.name_uri = name_uri;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Context set URI of the modifier name, if its prefix could be resolved.
+    pub fn name_uri(&self) -> Option<&str> {
+        self.name_uri.as_deref()
+    }
+
+    /// Comparison symbol, `None` for a modifier without a value.
+    pub fn relation(&self) -> Option<&str> {
+        self.relation.as_deref()
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// From the `/` to the end of the value or name.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+}
+
+impl St {
+    pub fn with_uris(mut self, index_uri: Option<String>, relation_uri: Option<String>) -> St {
+        self.index_uri = index_uri;
+        self.relation_uri = relation_uri;
+        self
+    }
+
+    pub(crate) fn with_spans(
+        mut self,
+        index_span: Option<Span>,
+        relation_span: Option<Span>,
+    ) -> St {
+        self.index_span = Ignored(index_span);
+        self.relation_span = Ignored(relation_span);
+        self
+    }
+
+    pub(crate) fn with_span(mut self, span: Span) -> St {
+        self.span = Ignored(span);
+        self
+    }
+
+    pub fn index(&self) -> &str {
+        &self.index
+    }
+
+    /// Context set URI of the index, if its prefix could be resolved.
+    pub fn index_uri(&self) -> Option<&str> {
+        self.index_uri.as_deref()
+    }
+
+    pub fn term(&self) -> Option<&str> {
+        self.term.as_deref()
+    }
+
+    pub fn relation(&self) -> &str {
+        &self.relation
+    }
+
+    /// Context set URI of the relation, if its prefix could be resolved.
+    pub fn relation_uri(&self) -> Option<&str> {
+        self.relation_uri.as_deref()
+    }
+
+    pub fn modifiers(&self) -> &[Modifier] {
+        &self.modifiers
+    }
+
+    /// Prefix assignments scoping over this clause, outermost first.
+    pub fn prefixes(&self) -> &[Prefix] {
+        &self.prefixes
+    }
+
+    /// The clause from index to term. A term that inherits index and
+    /// relation from an enclosing `index relation (...)` spans just the
+    /// term. Sort keys span index and modifiers.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+
+    /// `None` for the implicit `cql.serverChoice` index.
+    pub fn index_span(&self) -> Option<Span> {
+        self.index_span.0
+    }
+
+    /// `None` for the implicit `=` relation and for sort keys.
+    pub fn relation_span(&self) -> Option<Span> {
+        self.relation_span.0
+    }
+
+    /// Including quotes, `None` for sort keys.
+    pub fn term_span(&self) -> Option<Span> {
+        self.term_span.0
+    }
+}
+
+impl Boolean {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn left(&self) -> &CqlNode {
+        &self.left
+    }
+
+    pub fn right(&self) -> &CqlNode {
+        &self.right
+    }
+
+    pub fn modifiers(&self) -> &[Modifier] {
+        &self.modifiers
+    }
+
+    /// Prefix assignments scoping over this boolean, outermost first.
+    pub fn prefixes(&self) -> &[Prefix] {
+        &self.prefixes
+    }
+
+    /// From the start of the left operand to the end of the right one,
+    /// including any parentheses around them.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+}
+
+impl Root {
+    pub fn search(&self) -> &CqlNode {
+        &self.search
+    }
+
+    pub fn sort(&self) -> &[St] {
+        &self.sort
+    }
+
+    /// The whole query.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+}
+
+impl ErrorNode {
+    /// Description of what was missing or unexpected.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The offending token; empty at the end of the query.
+    pub fn span(&self) -> Span {
+        self.span.0
+    }
+}
+
+impl FromStr for CqlNode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<CqlNode, ParseError> {
+        Parser::new().parse(s.chars().by_ref())
+    }
 }
 
 impl CqlNode {
-    pub(crate) fn mk_sc_dup(st: &St, term: &str) -> CqlNode {
+    pub(crate) fn mk_sc_dup(st: &St, term: &str, term_span: Span) -> CqlNode {
         let st2 = St {
             index: st.index.clone(),
             index_uri: st.index_uri.clone(),
@@ -40,48 +304,96 @@ impl CqlNode {
             relation: st.relation.clone(),
             relation_uri: st.relation_uri.clone(),
             modifiers: st.modifiers.clone(),
+            prefixes: Vec::new(),
+            span: Ignored(term_span),
+            index_span: st.index_span,
+            relation_span: st.relation_span,
+            term_span: Ignored(Some(term_span)),
         };
-        return CqlNode::St(st2);
+        CqlNode::St(st2)
     }
 
-    pub(crate) fn mk_sc(
-        index: &str,
-        relation: &str,
-        term: Option<&str>,
-        modifiers: Option<Rc<St>>,
-    ) -> St {
-        let term = match term {
-            Some(s) => Some(String::from(s)),
-            _ => None,
-        };
+    /// Search clause, or sort key if `term` is `None` and `relation` empty.
+    pub fn mk_sc(index: &str, relation: &str, term: Option<&str>, modifiers: Vec<Modifier>) -> St {
         St {
             index: String::from(index),
             index_uri: None,
-            term,
+            term: term.map(String::from),
             relation: String::from(relation),
             relation_uri: None,
             modifiers,
+            prefixes: Vec::new(),
+            span: Ignored(Span::default()),
+            index_span: Ignored(None),
+            relation_span: Ignored(None),
+            term_span: Ignored(None),
         }
     }
 
-    pub(crate) fn mk_boolean(
+    pub fn mk_boolean(
         value: &str,
         left: Box<CqlNode>,
         right: Box<CqlNode>,
-        modifiers: Option<Rc<St>>,
+        modifiers: Vec<Modifier>,
     ) -> CqlNode {
         let bo = Boolean {
             value: String::from(value),
             left,
             right,
             modifiers,
+            prefixes: Vec::new(),
+            span: Ignored(Span::default()),
         };
         CqlNode::Boolean(bo)
     }
-    pub(crate) fn mk_root(search: Box<CqlNode>, sort: Vec<St>) -> CqlNode {
-        let root = Root { search, sort };
+    pub fn mk_root(search: Box<CqlNode>, sort: Vec<St>) -> CqlNode {
+        let root = Root {
+            search,
+            sort,
+            span: Ignored(Span::default()),
+        };
         CqlNode::Root(root)
     }
+
+    pub fn mk_error(message: &str, span: Span) -> CqlNode {
+        CqlNode::Error(ErrorNode {
+            message: String::from(message),
+            span: Ignored(span),
+        })
+    }
+
+    pub(crate) fn with_span(mut self, span: Span) -> CqlNode {
+        match &mut self {
+            CqlNode::St(st) => st.span = Ignored(span),
+            CqlNode::Boolean(bo) => bo.span = Ignored(span),
+            CqlNode::Root(root) => root.span = Ignored(span),
+            CqlNode::Error(e) => e.span = Ignored(span),
+        }
+        self
+    }
+
+    /// The part of the query this node was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            CqlNode::St(st) => st.span.0,
+            CqlNode::Boolean(bo) => bo.span.0,
+            CqlNode::Root(root) => root.span.0,
+            CqlNode::Error(e) => e.span.0,
+        }
+    }
+
+    /// Attach prefix assignments scoping over this node. Assignments
+    /// given here are outermost, so they go before any already present.
+    pub fn add_prefixes(&mut self, mut prefixes: Vec<Prefix>) {
+        let existing = match self {
+            CqlNode::St(st) => &mut st.prefixes,
+            CqlNode::Boolean(bo) => &mut bo.prefixes,
+            CqlNode::Root(root) => return root.search.add_prefixes(prefixes),
+            CqlNode::Error(_) => return,
+        };
+        prefixes.append(existing);
+        *existing = prefixes;
+    }
 }
 
 #[cfg(test)]
@@ -90,35 +402,40 @@ mod tests {
 
     #[test]
     fn create_sc() {
-        let n = CqlNode::mk_sc("ti", "=", Some(&"value"), None);
+        let n = CqlNode::mk_sc("ti", "=", Some("value"), Vec::new());
         assert_eq!(n.index, "ti");
         assert_eq!(n.relation, "=");
         assert!(n.term.is_some_and(|val| val == "value"));
         assert!(n.index_uri.is_none());
         assert!(n.relation_uri.is_none());
-        assert!(n.modifiers.is_none());
-        assert!(n.modifiers.is_none());
+        assert!(n.modifiers.is_empty());
+        assert!(n.prefixes.is_empty());
     }
 
     #[test]
     fn create_sort() {
-        let sc = CqlNode::St(CqlNode::mk_sc("ti", "=", None, None));
+        let sc = CqlNode::St(CqlNode::mk_sc("ti", "=", None, Vec::new()));
         let my_root = CqlNode::mk_root(Box::new(sc), Vec::new());
         assert_matches!(my_root, CqlNode::Root(n) => {
-                assert!(n.sort.len() == 0);
+                assert!(n.sort.is_empty());
         });
     }
 
     #[test]
     fn create_tree() {
-        let my_sc1 = Box::new(CqlNode::St(CqlNode::mk_sc("ti", "=", Some(&"house"), None)));
+        let my_sc1 = Box::new(CqlNode::St(CqlNode::mk_sc(
+            "ti",
+            "=",
+            Some("house"),
+            Vec::new(),
+        )));
         let my_sc2 = Box::new(CqlNode::St(CqlNode::mk_sc(
             "au",
             "=",
-            Some(&"andersen"),
-            None,
+            Some("andersen"),
+            Vec::new(),
         )));
-        let my_bool = CqlNode::mk_boolean("And", my_sc1, my_sc2, None);
+        let my_bool = CqlNode::mk_boolean("And", my_sc1, my_sc2, Vec::new());
 
         assert_matches!(my_bool, CqlNode::Boolean(n) => {
             assert_eq!("And", n.value);
@@ -132,4 +449,51 @@ mod tests {
             });
         });
     }
+
+    fn hash(node: &CqlNode) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        node.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn from_str() {
+        let a: CqlNode = "ti = house and au = andersen".parse().unwrap();
+        let b: CqlNode = "ti=house   and au=andersen".parse().unwrap();
+        assert_ne!(a.span(), b.span());
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(a.clone(), a);
+
+        let c: CqlNode = "ti = house and au = hans".parse().unwrap();
+        assert_ne!(a, c);
+
+        assert!("ti =".parse::<CqlNode>().is_err());
+        assert_eq!(crate::parse("ti = house and au = andersen"), Ok(a));
+    }
+
+    #[test]
+    fn constructed_equals_parsed() {
+        let ti = CqlNode::mk_sc("ti", "=", Some("house"), Vec::new())
+            .with_uris(None, Some(String::from(CQL_CONTEXT_SET)));
+        let modifier = Modifier::new("distance", Some("<"), Some("3"))
+            .with_uri(Some(String::from(CQL_CONTEXT_SET)));
+        let au = CqlNode::mk_sc("dc.creator", "=", Some("andersen"), Vec::new()).with_uris(
+            Some(String::from("http://dc")),
+            Some(String::from(CQL_CONTEXT_SET)),
+        );
+        let mut search = CqlNode::mk_boolean(
+            "prox",
+            Box::new(CqlNode::St(ti)),
+            Box::new(CqlNode::St(au)),
+            vec![modifier],
+        );
+        search.add_prefixes(vec![Prefix::new(Some("dc"), "http://dc")]);
+        let root = CqlNode::mk_root(Box::new(search), Vec::new());
+
+        let parsed =
+            crate::parse(">dc=\"http://dc\" ti = house prox/distance<3 dc.creator = andersen");
+        assert_eq!(parsed, Ok(root));
+    }
 }
@@ -0,0 +1,389 @@
+//! Serde support for the tree, in the JSON shape documented at the crate
+//! root. The shape is given by the representation types here rather than
+//! by the fields of the tree, so that it stays stable. Spans are not
+//! serialized.
+
+use super::Boolean;
+use super::CqlNode;
+use super::ErrorNode;
+use super::Modifier;
+use super::Prefix;
+use super::Root;
+use super::St;
+use crate::span::Ignored;
+use crate::span::Span;
+use serde::de::Error;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::borrow::Cow;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PrefixRepr<'a> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<Cow<'a, str>>,
+    uri: Cow<'a, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ModifierRepr<'a> {
+    name: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name_uri: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relation: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct StRepr<'a> {
+    index: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index_uri: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "str::is_empty")]
+    relation: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relation_uri: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    modifiers: Cow<'a, [Modifier]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    term: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    prefixes: Cow<'a, [Prefix]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BooleanRepr<'a> {
+    value: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    modifiers: Cow<'a, [Modifier]>,
+    left: Cow<'a, CqlNode>,
+    right: Cow<'a, CqlNode>,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    prefixes: Cow<'a, [Prefix]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RootRepr<'a> {
+    search: Cow<'a, CqlNode>,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    sort: Cow<'a, [St]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ErrorRepr<'a> {
+    message: Cow<'a, str>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[allow(clippy::large_enum_variant)]
+enum NodeRepr<'a> {
+    SearchClause(Cow<'a, St>),
+    Boolean(Cow<'a, Boolean>),
+    Root(Cow<'a, Root>),
+    Error(ErrorRepr<'a>),
+}
+
+fn borrowed(s: &Option<String>) -> Option<Cow<'_, str>> {
+    s.as_deref().map(Cow::Borrowed)
+}
+
+fn owned(s: Option<Cow<'_, str>>) -> Option<String> {
+    s.map(Cow::into_owned)
+}
+
+impl Serialize for Prefix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = PrefixRepr {
+            name: borrowed(&self.name),
+            uri: Cow::Borrowed(&self.uri),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Prefix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Prefix, D::Error> {
+        let repr = PrefixRepr::deserialize(deserializer)?;
+        Ok(Prefix::new(repr.name.as_deref(), &repr.uri))
+    }
+}
+
+impl Serialize for Modifier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = ModifierRepr {
+            name: Cow::Borrowed(&self.name),
+            name_uri: borrowed(&self.name_uri),
+            relation: borrowed(&self.relation),
+            value: borrowed(&self.value),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Modifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Modifier, D::Error> {
+        let repr = ModifierRepr::deserialize(deserializer)?;
+        if repr.name.is_empty() {
+            return Err(D::Error::custom("modifier without name"));
+        }
+        if repr.relation.is_some() != repr.value.is_some() {
+            return Err(D::Error::custom(
+                "modifier relation and value must be given together",
+            ));
+        }
+        let modifier = Modifier::new(&repr.name, repr.relation.as_deref(), repr.value.as_deref());
+        Ok(modifier.with_uri(owned(repr.name_uri)))
+    }
+}
+
+impl Serialize for St {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = StRepr {
+            index: Cow::Borrowed(&self.index),
+            index_uri: borrowed(&self.index_uri),
+            relation: Cow::Borrowed(&self.relation),
+            relation_uri: borrowed(&self.relation_uri),
+            modifiers: Cow::Borrowed(&self.modifiers),
+            term: borrowed(&self.term),
+            prefixes: Cow::Borrowed(&self.prefixes),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for St {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<St, D::Error> {
+        let repr = StRepr::deserialize(deserializer)?;
+        if repr.index.is_empty() {
+            return Err(D::Error::custom("search clause without index"));
+        }
+        let mut st = CqlNode::mk_sc(
+            &repr.index,
+            &repr.relation,
+            repr.term.as_deref(),
+            repr.modifiers.into_owned(),
+        )
+        .with_uris(owned(repr.index_uri), owned(repr.relation_uri));
+        st.prefixes = repr.prefixes.into_owned();
+        Ok(st)
+    }
+}
+
+impl Serialize for Boolean {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = BooleanRepr {
+            value: Cow::Borrowed(&self.value),
+            modifiers: Cow::Borrowed(&self.modifiers),
+            left: Cow::Borrowed(&self.left),
+            right: Cow::Borrowed(&self.right),
+            prefixes: Cow::Borrowed(&self.prefixes),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Boolean {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Boolean, D::Error> {
+        let repr = BooleanRepr::deserialize(deserializer)?;
+        let known = ["and", "or", "not", "prox"];
+        if !known.iter().any(|op| repr.value.eq_ignore_ascii_case(op)) {
+            let message = format!("unknown boolean operator \"{}\"", repr.value);
+            return Err(D::Error::custom(message));
+        }
+        if matches!(*repr.left, CqlNode::Root(_)) || matches!(*repr.right, CqlNode::Root(_)) {
+            return Err(D::Error::custom("root node nested inside boolean"));
+        }
+        Ok(Boolean {
+            value: repr.value.into_owned(),
+            left: Box::new(repr.left.into_owned()),
+            right: Box::new(repr.right.into_owned()),
+            modifiers: repr.modifiers.into_owned(),
+            prefixes: repr.prefixes.into_owned(),
+            span: Ignored(Span::default()),
+        })
+    }
+}
+
+impl Serialize for Root {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = RootRepr {
+            search: Cow::Borrowed(&self.search),
+            sort: Cow::Borrowed(&self.sort),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Root {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Root, D::Error> {
+        let repr = RootRepr::deserialize(deserializer)?;
+        if let CqlNode::Root(_) = *repr.search {
+            return Err(D::Error::custom("root node nested inside root"));
+        }
+        if repr
+            .sort
+            .iter()
+            .any(|key| key.term.is_some() || !key.relation.is_empty())
+        {
+            return Err(D::Error::custom("sort key with relation or term"));
+        }
+        Ok(Root {
+            search: Box::new(repr.search.into_owned()),
+            sort: repr.sort.into_owned(),
+            span: Ignored(Span::default()),
+        })
+    }
+}
+
+impl Serialize for CqlNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self {
+            CqlNode::St(st) => NodeRepr::SearchClause(Cow::Borrowed(st)),
+            CqlNode::Boolean(bo) => NodeRepr::Boolean(Cow::Borrowed(bo)),
+            CqlNode::Root(root) => NodeRepr::Root(Cow::Borrowed(root)),
+            CqlNode::Error(e) => NodeRepr::Error(ErrorRepr {
+                message: Cow::Borrowed(&e.message),
+            }),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CqlNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<CqlNode, D::Error> {
+        let node = match NodeRepr::deserialize(deserializer)? {
+            NodeRepr::SearchClause(st) => {
+                if st.term.is_none() || st.relation.is_empty() {
+                    return Err(D::Error::custom("search clause without relation or term"));
+                }
+                CqlNode::St(st.into_owned())
+            }
+            NodeRepr::Boolean(bo) => CqlNode::Boolean(bo.into_owned()),
+            NodeRepr::Root(root) => CqlNode::Root(root.into_owned()),
+            NodeRepr::Error(e) => CqlNode::Error(ErrorNode {
+                message: e.message.into_owned(),
+                span: Ignored(Span::default()),
+            }),
+        };
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn from_json(value: serde_json::Value) -> Result<CqlNode, String> {
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn shape() {
+        let node = crate::parse(
+            ">dc=\"http://dc\" dc.ti =/cql.word x and/rel.x y sortby ti/sort.descending",
+        )
+        .unwrap();
+        let value = serde_json::to_value(&node).unwrap();
+        let cql = "info:srw/cql-context-set/1/cql-v1.2";
+        let expected = json!({
+            "type": "root",
+            "search": {
+                "type": "boolean",
+                "value": "and",
+                "modifiers": [{"name": "rel.x"}],
+                "left": {
+                    "type": "searchClause",
+                    "index": "dc.ti",
+                    "indexUri": "http://dc",
+                    "relation": "=",
+                    "relationUri": cql,
+                    "modifiers": [{"name": "cql.word", "nameUri": cql}],
+                    "term": "x"
+                },
+                "right": {
+                    "type": "searchClause",
+                    "index": "cql.serverChoice",
+                    "indexUri": cql,
+                    "relation": "=",
+                    "relationUri": cql,
+                    "term": "y"
+                },
+                "prefixes": [{"name": "dc", "uri": "http://dc"}]
+            },
+            "sort": [{
+                "index": "ti",
+                "modifiers": [{
+                    "name": "sort.descending",
+                    "nameUri": "info:srw/cql-context-set/1/sort-v1.0"
+                }]
+            }]
+        });
+        assert_eq!(value, expected);
+        assert_eq!(from_json(value), Ok(node));
+    }
+
+    #[test]
+    fn round_trip() {
+        for query in [
+            "a",
+            "ti any/x<\"1 2\" \"\"",
+            ">\"http://d\" a prox/unit=word (b or c) sortby x y/sort.ascending",
+        ] {
+            let node = crate::parse(query).unwrap();
+            let text = serde_json::to_string(&node).unwrap();
+            assert_eq!(serde_json::from_str::<CqlNode>(&text).unwrap(), node);
+        }
+        let mut my = crate::parser::Parser::new();
+        let (node, _) = my.parse_recovering(&mut "a and".chars());
+        let value = serde_json::to_value(&node).unwrap();
+        assert_eq!(value["search"]["right"]["type"], "error");
+        assert_eq!(from_json(value), Ok(node));
+    }
+
+    #[test]
+    fn invalid() {
+        let st = json!({"type": "searchClause", "index": "a", "relation": "=", "term": "b"});
+        let root = json!({"type": "root", "search": st});
+
+        let e = from_json(json!({"type": "boolean", "value": "and", "left": st, "right": root}));
+        assert!(e.unwrap_err().contains("root node nested inside boolean"));
+
+        let e = from_json(json!({"type": "root", "search": root}));
+        assert!(e.unwrap_err().contains("root node nested inside root"));
+
+        let e = from_json(json!({"type": "boolean", "value": "xor", "left": st, "right": st}));
+        assert!(e.unwrap_err().contains("unknown boolean operator \"xor\""));
+
+        let e = from_json(json!({"type": "searchClause", "index": "a", "relation": "="}));
+        assert!(e
+            .unwrap_err()
+            .contains("search clause without relation or term"));
+
+        let key = json!({"index": "a", "relation": "=", "term": "b"});
+        let e = from_json(json!({"type": "root", "search": st, "sort": [key]}));
+        assert!(e.unwrap_err().contains("sort key with relation or term"));
+
+        let e = from_json(
+            json!({"type": "searchClause", "index": "a", "relation": "=", "term": "b",
+            "modifiers": [{"name": "x", "value": "1"}]}),
+        );
+        assert!(e.unwrap_err().contains("must be given together"));
+
+        let e = from_json(json!({"type": "searchClause", "index": "a", "rel": "="}));
+        assert!(e.unwrap_err().contains("unknown field `rel`"));
+
+        let e = from_json(json!({"type": "triple"}));
+        assert!(e.unwrap_err().contains("unknown variant `triple`"));
+    }
+}
@@ -0,0 +1,455 @@
+//! Translation of CQL to OData v4 `$filter` and `$orderby` options.
+//!
+//! Indexes are mapped to properties with [`ODataTransform::property`],
+//! and `cql.serverChoice` searches the properties given to
+//! [`ODataTransform::default_properties`]. Relations translate as
+//! follows:
+//!
+//! | CQL              | `$filter`                                         |
+//! |------------------|---------------------------------------------------|
+//! | `ti = a`         | `contains(tolower(Title), 'a')`                   |
+//! | `ti = ^a`        | `startswith(tolower(Title), 'a')`                 |
+//! | `ti = a^`        | `endswith(tolower(Title), 'a')`                   |
+//! | `ti == "a b"`    | `Title eq 'a b'`                                  |
+//! | `ti == a*`       | `startswith(Title, 'a')`                          |
+//! | `ti all "a b"`   | `(contains(...) and contains(...))`               |
+//! | `year < 2000`    | `Year lt 2000`                                    |
+//! | `ti <> a`        | `Title ne 'a'`                                    |
+//! | `a not b`        | `a and not (b)`                                   |
+//!
+//! `=` ignores case, and `==` does not. Masking is limited to `*` at the
+//! start or end of a term, which the string functions can express.
+//! Sort keys are returned as the `$orderby` option, such as
+//! `Year desc,Title asc`.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::term::is_masked;
+use crate::term::text;
+use crate::term::trim_stars;
+use crate::term::TermChar;
+use crate::translate::descending;
+use crate::translate::infix;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// OData system query options for a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ODataQuery {
+    /// The `$filter` option.
+    pub filter: String,
+    /// The `$orderby` option, if the query has sort keys.
+    pub orderby: Option<String>,
+}
+
+/// Type of a property, deciding how terms are written as literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PropertyType {
+    /// `Edm.String`, with quoted literals.
+    #[default]
+    String,
+    /// Numeric types, such as `Edm.Int32` and `Edm.Decimal`.
+    Number,
+    /// `Edm.Date`, such as `2000-01-31`.
+    Date,
+}
+
+/// `s` as a string literal, with single quotes doubled.
+fn string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// The term as a literal of `property_type`.
+fn literal(clause: &Clause<'_>, property_type: PropertyType) -> Result<String, TranslationError> {
+    clause.unanchored()?;
+    clause.unmasked()?;
+    let value = clause.term().text();
+    let invalid = || clause.error(TranslationErrorKind::InvalidLiteral);
+    match property_type {
+        PropertyType::String => Ok(string(&value)),
+        PropertyType::Number => {
+            let number = value
+                .chars()
+                .all(|ch| ch.is_ascii_digit() || "+-.eE".contains(ch))
+                && value.parse::<f64>().is_ok();
+            number.then_some(value).ok_or_else(invalid)
+        }
+        PropertyType::Date => {
+            let date = value.len() == 10
+                && value.char_indices().all(|(i, ch)| match i {
+                    4 | 7 => ch == '-',
+                    _ => ch.is_ascii_digit(),
+                });
+            date.then_some(value).ok_or_else(invalid)
+        }
+    }
+}
+
+/// Filter matching `chars` of the term of `clause` in `property`,
+/// anchored at the start and end as given by [`trim_stars`]. Only
+/// leading and trailing `*` can be masked.
+fn matches(
+    property: &str,
+    clause: &Clause<'_>,
+    (chars, start, end): (&[TermChar], bool, bool),
+    ignore_case: bool,
+) -> Result<String, TranslationError> {
+    if is_masked(chars) {
+        return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+    }
+    let mut value = text(chars);
+    let property = if ignore_case {
+        value = value.to_lowercase();
+        format!("tolower({})", property)
+    } else {
+        String::from(property)
+    };
+    let value = string(&value);
+    Ok(match (start, end) {
+        (true, true) => format!("{} eq {}", property, value),
+        (true, false) => format!("startswith({}, {})", property, value),
+        (false, true) => format!("endswith({}, {})", property, value),
+        (false, false) => format!("contains({}, {})", property, value),
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    property_type: PropertyType,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ODataTransform {
+    properties: Mapping<Property>,
+}
+
+impl ODataTransform {
+    pub fn new() -> ODataTransform {
+        ODataTransform::default()
+    }
+
+    /// Search `index` in `property`, which may be a path such as
+    /// `Author/Name`. `index` is matched as by [`Mapping`].
+    pub fn property(&mut self, index: &str, property: &str, property_type: PropertyType) {
+        let property = Property {
+            name: String::from(property),
+            property_type,
+        };
+        self.properties.insert(index, property);
+    }
+
+    /// String properties searched, with `or`, for `cql.serverChoice`
+    /// unless it is mapped with [`ODataTransform::property`].
+    pub fn default_properties(&mut self, properties: &[&str]) {
+        let properties = properties
+            .iter()
+            .map(|p| Property {
+                name: String::from(*p),
+                property_type: PropertyType::String,
+            })
+            .collect();
+        self.properties.server_choice(properties);
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.properties.insert_relation(name, relation);
+    }
+
+    /// The filter on a single property.
+    fn clause(
+        target: &Property,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<String, TranslationError> {
+        let (property, property_type) = (target.name.as_str(), target.property_type);
+        let term = clause.term();
+        let op = match relation {
+            "<" => "lt",
+            "<=" => "le",
+            ">" => "gt",
+            ">=" => "ge",
+            "<>" => "ne",
+            _ => "",
+        };
+        if property_type != PropertyType::String {
+            return match relation {
+                "=" | "==" | "exact" | "scr" => Ok(format!(
+                    "{} eq {}",
+                    property,
+                    literal(clause, property_type)?
+                )),
+                _ if !op.is_empty() => Ok(format!(
+                    "{} {} {}",
+                    property,
+                    op,
+                    literal(clause, property_type)?
+                )),
+                _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+            };
+        }
+        match relation {
+            "=" | "adj" | "scr" => matches(property, clause, term.trim_stars(), true),
+            "all" | "any" => {
+                clause.unanchored()?;
+                let words: Result<Vec<String>, TranslationError> = clause
+                    .words()?
+                    .iter()
+                    .map(|w| matches(property, clause, trim_stars(w, false, false), true))
+                    .collect();
+                let words = words?;
+                let op = if relation == "all" { " and " } else { " or " };
+                match words.len() {
+                    1 => Ok(words.join(op)),
+                    _ => Ok(format!("({})", words.join(op))),
+                }
+            }
+            "==" | "exact" => {
+                clause.unanchored()?;
+                matches(property, clause, trim_stars(&term.chars, true, true), false)
+            }
+            _ if !op.is_empty() => Ok(format!(
+                "{} {} {}",
+                property,
+                op,
+                literal(clause, property_type)?
+            )),
+            _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.properties.relation(clause)?;
+        let properties = self
+            .properties
+            .resolve(clause.index())
+            .filter(|properties| !properties.is_empty())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        let clauses: Result<Vec<String>, TranslationError> = properties
+            .into_iter()
+            .map(|p| ODataTransform::clause(p, &relation, clause))
+            .collect();
+        let clauses = clauses?;
+        if clauses.len() == 1 {
+            return Ok(clauses.join(""));
+        }
+        Ok(format!("({})", clauses.join(" or ")))
+    }
+
+    /// Translate a query to `$filter` and `$orderby` options.
+    pub fn to_odata(&self, node: &CqlNode) -> Result<ODataQuery, TranslationError> {
+        let mut translator = Translator { transform: self };
+        let orderby = translator.translate_sort(node)?;
+        Ok(ODataQuery {
+            filter: translator.translate(node)?,
+            orderby: (!orderby.is_empty()).then(|| orderby.join(",")),
+        })
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a ODataTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        mut operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        match op {
+            BooleanOp::And => Ok(infix(operands, " and ")),
+            BooleanOp::Or => Ok(infix(operands, " or ")),
+            BooleanOp::Not => {
+                let right = operands.pop().map(|(_, s)| s).unwrap_or_default();
+                Ok(format!("{} and not ({})", infix(operands, ""), right))
+            }
+            BooleanOp::Prox => Err(TranslationError::boolean(bo)),
+        }
+    }
+}
+
+impl SortTranslator for Translator<'_> {
+    type SortKey = String;
+
+    fn sort_key(&mut self, key: &St) -> Result<String, TranslationError> {
+        let property = self
+            .transform
+            .properties
+            .get(key.index())
+            .ok_or_else(|| TranslationError::index(key))?;
+        let mut direction = "asc";
+        for m in key.modifiers() {
+            direction = if descending(m)? { "desc" } else { "asc" };
+        }
+        Ok(format!("{} {}", property.name, direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform() -> ODataTransform {
+        let mut transform = ODataTransform::new();
+        transform.property("dc.title", "Title", PropertyType::String);
+        transform.property("creator", "Author/Name", PropertyType::String);
+        transform.property("year", "Year", PropertyType::Number);
+        transform.property("date", "Published", PropertyType::Date);
+        transform.default_properties(&["Title", "Description"]);
+        transform
+    }
+
+    fn filter(query: &str) -> Result<String, TranslationError> {
+        let node = crate::parse(query).unwrap();
+        Ok(transform().to_odata(&node)?.filter)
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            filter("dc.title = Hobbit").unwrap(),
+            "contains(tolower(Title), 'hobbit')"
+        );
+        assert_eq!(
+            filter("dc.title = \"^the lord*\"").unwrap(),
+            "startswith(tolower(Title), 'the lord')"
+        );
+        assert_eq!(
+            filter("dc.title = ring^").unwrap(),
+            "endswith(tolower(Title), 'ring')"
+        );
+        assert_eq!(
+            filter("dc.title = ^ring^").unwrap(),
+            "tolower(Title) eq 'ring'"
+        );
+        assert_eq!(
+            filter("creator == \"O'Brien\"").unwrap(),
+            "Author/Name eq 'O''Brien'"
+        );
+        assert_eq!(
+            filter("creator == Tolk*").unwrap(),
+            "startswith(Author/Name, 'Tolk')"
+        );
+        assert_eq!(
+            filter("creator == *kien*").unwrap(),
+            "contains(Author/Name, 'kien')"
+        );
+        assert_eq!(
+            filter("dc.title any \"lord ring\"").unwrap(),
+            "(contains(tolower(Title), 'lord') or contains(tolower(Title), 'ring'))"
+        );
+        assert_eq!(filter("year < 1950").unwrap(), "Year lt 1950");
+        assert_eq!(filter("year = 1937").unwrap(), "Year eq 1937");
+        assert_eq!(
+            filter("date >= 1937-09-21").unwrap(),
+            "Published ge 1937-09-21"
+        );
+        assert_eq!(filter("dc.title <> a").unwrap(), "Title ne 'a'");
+        assert_eq!(filter("dc.title <= m").unwrap(), "Title le 'm'");
+        assert_eq!(
+            filter("hobbit").unwrap(),
+            "(contains(tolower(Title), 'hobbit') or contains(tolower(Description), 'hobbit'))"
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            filter("dc.title == a and (year > 1 or year < 0)").unwrap(),
+            "Title eq 'a' and (Year gt 1 or Year lt 0)"
+        );
+        assert_eq!(
+            filter("dc.title == a not dc.title == b").unwrap(),
+            "Title eq 'a' and not (Title eq 'b')"
+        );
+        assert_eq!(
+            filter("dc.title == a not (dc.title == b or dc.title == c)").unwrap(),
+            "Title eq 'a' and not (Title eq 'b' or Title eq 'c')"
+        );
+        assert_eq!(
+            filter("a prox b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn orderby() {
+        let node = crate::parse("a sortby year/sort.descending dc.title").unwrap();
+        assert_eq!(
+            transform().to_odata(&node).unwrap().orderby.as_deref(),
+            Some("Year desc,Title asc")
+        );
+        let node = crate::parse("a sortby year/missingValue=lowValue").unwrap();
+        assert_eq!(
+            transform().to_odata(&node).unwrap_err().parts(),
+            (
+                TranslationErrorKind::UnsupportedSortModifier,
+                "missingValue"
+            )
+        );
+        let node = crate::parse("a").unwrap();
+        assert_eq!(transform().to_odata(&node).unwrap().orderby, None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            filter("dc.title = a*b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a*b")
+        );
+        assert_eq!(
+            filter("dc.title == a?").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a?")
+        );
+        assert_eq!(
+            filter("dc.title < a*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a*")
+        );
+        assert_eq!(
+            filter("dc.title == ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            filter("year = soon").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidLiteral, "soon")
+        );
+        assert_eq!(
+            filter("date = 1937").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidLiteral, "1937")
+        );
+        assert_eq!(
+            filter("year any 1").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "any")
+        );
+        assert_eq!(
+            filter("dc.title all \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            filter("dc.subject = a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            filter("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+    }
+}
@@ -1,13 +1,90 @@
+use crate::error::ErrorKind;
 use crate::error::ParseError;
 use crate::lexer::Lexer;
 use crate::lexer::Token;
 use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::Prefix;
 use crate::node::St;
-use std::rc::Rc;
+use crate::node::CQL_CONTEXT_SET;
+use crate::node::SORT_CONTEXT_SET;
+use crate::span::Position;
+use crate::span::Span;
+
+/// Look up the URI bound to a prefix in `prefixes`, innermost (last)
+/// assignment first. `None` looks up the default context set given by
+/// `>"uri"`.
+pub(crate) fn prefix_uri(prefixes: &[Prefix], name: Option<&str>) -> Option<String> {
+    let bound = prefixes.iter().rev().find(|p| match (p.name(), name) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        (None, None) => true,
+        _ => false,
+    });
+    if let Some(p) = bound {
+        return Some(String::from(p.uri()));
+    }
+    match name {
+        Some(name) if name.eq_ignore_ascii_case("cql") => Some(String::from(CQL_CONTEXT_SET)),
+        Some(name) if name.eq_ignore_ascii_case("sort") => Some(String::from(SORT_CONTEXT_SET)),
+        _ => None,
+    }
+}
+
+/// Context set URI of a modifier name. Unqualified names belong to the
+/// context set given by `default_set`.
+pub(crate) fn modifier_uri(prefixes: &[Prefix], name: &str, default_set: &str) -> Option<String> {
+    match name.split_once('.') {
+        Some((prefix, _)) => prefix_uri(prefixes, Some(prefix)),
+        None => prefix_uri(prefixes, Some(default_set)),
+    }
+}
+
+/// Fill in the context set URIs of index and relation from the prefix
+/// assignments in scope. Unqualified indexes belong to the default
+/// context set, unqualified relations to the CQL context set.
+pub(crate) fn resolve(prefixes: &[Prefix], st: St, index: &str, relation: &str) -> St {
+    let index_uri = match index.split_once('.') {
+        Some((prefix, _)) => prefix_uri(prefixes, Some(prefix)),
+        None => prefix_uri(prefixes, None),
+    };
+    let relation_uri = match relation.split_once('.') {
+        Some((prefix, _)) => prefix_uri(prefixes, Some(prefix)),
+        None if relation.is_empty() => None,
+        None => prefix_uri(prefixes, Some("cql")),
+    };
+    st.with_uris(index_uri, relation_uri)
+}
+
+/// Whether filling in the context set URIs of `st` from `prefixes` gives
+/// the ones it has, so that reading it there again gives it back.
+pub(crate) fn resolves(prefixes: &[Prefix], st: &St) -> bool {
+    // sort keys have no relation and their modifiers are sort modifiers
+    let default_set = if st.relation().is_empty() {
+        "sort"
+    } else {
+        "cql"
+    };
+    let res = resolve(prefixes, st.clone(), st.index(), st.relation());
+    res.index_uri() == st.index_uri()
+        && res.relation_uri() == st.relation_uri()
+        && st
+            .modifiers()
+            .iter()
+            .all(|m| m.name_uri() == modifier_uri(prefixes, m.name(), default_set).as_deref())
+}
 
 pub struct Parser {
     look: Token,
     lexer: Lexer,
+    prefixes: Vec<Prefix>,
+    diagnostics: Option<Vec<ParseError>>,
+    prev_end: Position,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
@@ -15,41 +92,108 @@ impl Parser {
         Parser {
             look: Token::EOS,
             lexer: Lexer::new(),
+            prefixes: Vec::new(),
+            diagnostics: None,
+            prev_end: Position::default(),
         }
     }
 
-    pub fn strict(self: &mut Self, strict: bool) {
+    pub fn strict(&mut self, strict: bool) {
         self.lexer.strict(strict);
     }
 
-    fn search_term(self: &mut Self) -> Option<String> {
+    /// Move to the next token.
+    fn advance(&mut self, get: &mut dyn Iterator<Item = char>) -> Result<(), ParseError> {
+        self.prev_end = self.lexer.token_span().end;
+        self.look = self.lexer.lex(get)?;
+        Ok(())
+    }
+
+    /// Span from `start` to the end of the last token consumed.
+    fn span_from(&self, start: Position) -> Span {
+        Span::new(start, self.prev_end)
+    }
+
+    fn error(&self, kind: ErrorKind, message: &str, expected: &[&str]) -> ParseError {
+        ParseError::new(
+            kind,
+            message,
+            self.lexer.token_span(),
+            &self.look.to_string(),
+            expected,
+        )
+    }
+
+    /// Record `err` when recovering from errors, fail with it otherwise.
+    /// Only the first error at a given position is recorded, so that one
+    /// problem does not cascade into several diagnostics.
+    fn report(&mut self, err: ParseError) -> Result<(), ParseError> {
+        match &mut self.diagnostics {
+            Some(diagnostics) => {
+                let offset = err.position().offset;
+                if diagnostics
+                    .last()
+                    .is_none_or(|e| e.position().offset != offset)
+                {
+                    diagnostics.push(err);
+                }
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    /// Like `report`, but return an error node to stand in for the
+    /// missing part of the query.
+    fn recover(&mut self, err: ParseError) -> Result<CqlNode, ParseError> {
+        let node = CqlNode::mk_error(err.message(), err.span());
+        self.report(err)?;
+        Ok(node)
+    }
+
+    /// Skip the current token and those following it, up to the next
+    /// token that can continue a query: a boolean operator, `)`, end of
+    /// query and, outside parentheses, `sortby`.
+    fn skip_unexpected(
+        &mut self,
+        get: &mut dyn Iterator<Item = char>,
+        nested: bool,
+    ) -> Result<(), ParseError> {
+        loop {
+            self.advance(get)?;
+            match self.look {
+                Token::EOS | Token::RP | Token::Boolop(_) => return Ok(()),
+                Token::Sortby(_) if !nested => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn search_term(&mut self) -> Option<String> {
         match &self.look {
             Token::SimpleString(name)
             | Token::PrefixName(name)
             | Token::Boolop(name)
-            | Token::Sortby(name) => {
-                return Some(String::from(name));
-            }
-            _ => return None,
+            | Token::Sortby(name) => Some(String::from(name)),
+            _ => None,
         }
     }
 
-    fn relation_symbol(self: &mut Self) -> Option<String> {
-        let lead;
-        match &self.look {
-            Token::EQ => lead = "=",
-            Token::GT => lead = ">",
-            Token::LT => lead = "<",
-            Token::GE => lead = ">=",
-            Token::LE => lead = "<=",
-            Token::NE => lead = "<>",
-            Token::Exact => lead = "==",
+    fn relation_symbol(&mut self) -> Option<String> {
+        let lead = match &self.look {
+            Token::EQ => "=",
+            Token::GT => ">",
+            Token::LT => "<",
+            Token::GE => ">=",
+            Token::LE => "<=",
+            Token::NE => "<>",
+            Token::Exact => "==",
             _ => return None,
-        }
+        };
         Some(String::from(lead))
     }
 
-    fn relation(self: &mut Self) -> Option<String> {
+    fn relation(&mut self) -> Option<String> {
         if let Some(lead) = self.relation_symbol() {
             return Some(lead);
         }
@@ -59,119 +203,306 @@ impl Parser {
         None
     }
 
-    fn boolean(self: &mut Self) -> Option<String> {
+    fn boolean(&mut self) -> Option<String> {
         match &self.look {
-            Token::Boolop(name) => return Some(String::from(name)),
-            _ => return None,
+            Token::Boolop(name) => Some(String::from(name)),
+            _ => None,
         }
     }
 
+    fn modifier_uri(&self, name: &str, default_set: &str) -> Option<String> {
+        modifier_uri(&self.prefixes, name, default_set)
+    }
+
+    fn resolve(&self, st: St, index: &str, relation: &str) -> St {
+        resolve(&self.prefixes, st, index, relation)
+    }
+
+    fn prefix_assignments(
+        &mut self,
+        get: &mut dyn Iterator<Item = char>,
+    ) -> Result<Vec<Prefix>, ParseError> {
+        let mut res = Vec::new();
+        while self.look == Token::GT {
+            let start = self.lexer.token_start();
+            self.advance(get)?;
+            let Some(first) = self.search_term() else {
+                let err = self.error(
+                    ErrorKind::InvalidPrefixAssignment,
+                    "expected prefix name or URI after \">\"",
+                    &["prefix name", "URI"],
+                );
+                self.report(err)?;
+                continue;
+            };
+            self.advance(get)?;
+            let prefix = if self.look == Token::EQ {
+                self.advance(get)?;
+                let Some(uri) = self.search_term() else {
+                    let err = self.error(
+                        ErrorKind::InvalidPrefixAssignment,
+                        "expected URI in prefix assignment",
+                        &["URI"],
+                    );
+                    self.report(err)?;
+                    continue;
+                };
+                self.advance(get)?;
+                Prefix::new(Some(&first), &uri)
+            } else {
+                Prefix::new(None, &first)
+            };
+            let prefix = prefix.with_span(self.span_from(start));
+            self.prefixes.push(prefix.clone());
+            res.push(prefix);
+        }
+        Ok(res)
+    }
+
     fn modifiers(
-        self: &mut Self,
+        &mut self,
         get: &mut dyn Iterator<Item = char>,
-    ) -> Result<Option<Rc<St>>, ParseError> {
-        let mut res: Option<Rc<St>> = None;
+        default_set: &str,
+    ) -> Result<Vec<Modifier>, ParseError> {
+        let mut res = Vec::new();
         while let Token::Modifier = &self.look {
-            self.look = self.lexer.lex(get)?;
-            if let Some(modifier) = self.search_term() {
-                self.look = self.lexer.lex(get)?;
-                if let Some(relation) = self.relation_symbol() {
-                    self.look = self.lexer.lex(get)?;
-                    if let Some(value) = self.search_term() {
-                        res = Some(Rc::new(CqlNode::mk_sc(
-                            &modifier,
-                            &relation,
-                            Some(&value),
-                            None,
-                        )));
-                        self.look = self.lexer.lex(get)?;
-                    } else {
-                        return Err(ParseError);
-                    }
+            let start = self.lexer.token_start();
+            self.advance(get)?;
+            let Some(name) = self.search_term() else {
+                let err = self.error(
+                    ErrorKind::MissingModifierName,
+                    "expected modifier name after \"/\"",
+                    &["modifier name"],
+                );
+                self.report(err)?;
+                continue;
+            };
+            self.advance(get)?;
+            let modifier = if let Some(relation) = self.relation_symbol() {
+                self.advance(get)?;
+                if let Some(value) = self.search_term() {
+                    self.advance(get)?;
+                    Modifier::new(&name, Some(&relation), Some(&value))
                 } else {
-                    res = Some(Rc::new(CqlNode::mk_sc(&modifier, "", None, None)));
+                    let err = self.error(
+                        ErrorKind::MissingModifierValue,
+                        "expected modifier value",
+                        &["modifier value"],
+                    );
+                    self.report(err)?;
+                    Modifier::new(&name, None, None)
                 }
             } else {
-                return Err(ParseError);
-            }
+                Modifier::new(&name, None, None)
+            };
+            let modifier = modifier
+                .with_uri(self.modifier_uri(&name, default_set))
+                .with_span(self.span_from(start));
+            res.push(modifier);
         }
         Ok(res)
     }
 
+    /// Parse a search clause. `after` describes what precedes it, for
+    /// error messages. `start` is where the index of `rel` starts, when
+    /// directly preceding the clause.
     fn search_clause(
-        self: &mut Self,
+        &mut self,
         get: &mut dyn Iterator<Item = char>,
         rel: &St,
+        after: &str,
+        start: Option<Position>,
     ) -> Result<CqlNode, ParseError> {
         if self.look == Token::LP {
-            self.look = self.lexer.lex(get)?;
-            let res = self.cql_query(get, rel)?;
-            if self.look != Token::RP {
-                return Err(ParseError);
+            self.advance(get)?;
+            let inner_start = self.lexer.token_start();
+            let mut res = self.cql_query(get, rel, " after \"(\"")?;
+            while self.look != Token::RP {
+                let err = self.error(
+                    ErrorKind::UnbalancedParenthesis,
+                    "expected \")\" to close \"(\"",
+                    &["\")\"", "boolean operator"],
+                );
+                self.report(err)?;
+                if self.look == Token::EOS {
+                    return Ok(res);
+                }
+                self.skip_unexpected(get, true)?;
+                res = self.boolean_tail(get, res, rel, inner_start)?;
             }
-            self.look = self.lexer.lex(get)?;
+            self.advance(get)?;
             return Ok(res);
         }
         let n = self.search_term();
         if let Some(n) = n {
-            self.look = self.lexer.lex(get)?;
+            let term_span = self.lexer.token_span();
+            let start = start.unwrap_or(term_span.start);
+            self.advance(get)?;
             if let Some(relation) = self.relation() {
-                self.look = self.lexer.lex(get)?;
-                let modifiers = self.modifiers(get)?;
-                let rel = CqlNode::mk_sc(&n, &relation, None, modifiers);
-                return self.search_clause(get, &rel);
+                let relation_span = self.lexer.token_span();
+                self.advance(get)?;
+                let modifiers = self.modifiers(get, "cql")?;
+                let rel = CqlNode::mk_sc(&n, &relation, None, modifiers)
+                    .with_spans(Some(term_span), Some(relation_span));
+                let rel = self.resolve(rel, &n, &relation);
+                return self.search_clause(get, &rel, " after relation", Some(start));
             }
-            return Ok(CqlNode::mk_sc_dup(rel, &n));
+            let node = CqlNode::mk_sc_dup(rel, &n, term_span).with_span(self.span_from(start));
+            return Ok(node);
         }
         // missing search !
-        Err(ParseError)
+        let expected = ["search term", "\"(\""];
+        if after.is_empty() && self.prefixes.is_empty() && self.look == Token::EOS {
+            let err = self.error(ErrorKind::EmptyQuery, "expected search term", &expected);
+            return self.recover(err);
+        }
+        let message = format!("expected search term{}", after);
+        let err = self.error(ErrorKind::MissingSearchTerm, &message, &expected);
+        self.recover(err)
     }
 
     fn scoped_clause(
-        self: &mut Self,
+        &mut self,
+        get: &mut dyn Iterator<Item = char>,
+        rel: &St,
+        after: &str,
+    ) -> Result<CqlNode, ParseError> {
+        let start = self.lexer.token_start();
+        let left = self.search_clause(get, rel, after, None)?;
+        self.boolean_tail(get, left, rel, start)
+    }
+
+    /// Parse the boolean operators and search clauses following `left`,
+    /// which starts at `start`.
+    fn boolean_tail(
+        &mut self,
         get: &mut dyn Iterator<Item = char>,
+        mut left: CqlNode,
         rel: &St,
+        start: Position,
     ) -> Result<CqlNode, ParseError> {
-        let mut left = self.search_clause(get, rel)?;
         while let Some(op) = self.boolean() {
-            self.look = self.lexer.lex(get)?;
-            let modifiers = self.modifiers(get)?;
-            let right = self.search_clause(get, rel)?;
-            left = CqlNode::mk_boolean(&op, Box::new(left), Box::new(right), modifiers);
+            self.advance(get)?;
+            let modifiers = self.modifiers(get, "cql")?;
+            let right = self.search_clause(get, rel, " after boolean operator", None)?;
+            left = CqlNode::mk_boolean(&op, Box::new(left), Box::new(right), modifiers)
+                .with_span(self.span_from(start));
         }
         Ok(left)
     }
 
     fn cql_query(
-        self: &mut Self,
+        &mut self,
         get: &mut dyn Iterator<Item = char>,
         rel: &St,
+        after: &str,
     ) -> Result<CqlNode, ParseError> {
-        let res = self.scoped_clause(get, rel)?;
+        let mark = self.prefixes.len();
+        let prefixes = self.prefix_assignments(get)?;
+        let mut res = self.scoped_clause(get, rel, after)?;
+        res.add_prefixes(prefixes);
+        self.prefixes.truncate(mark);
         Ok(res)
     }
 
-    pub fn parse(
-        self: &mut Self,
+    /// Parse a query, failing at the first error.
+    pub fn parse(&mut self, get: &mut dyn Iterator<Item = char>) -> Result<CqlNode, ParseError> {
+        self.diagnostics = None;
+        self.query(get)
+    }
+
+    /// Parse a query, continuing after errors. Missing parts of the query
+    /// are replaced by [`CqlNode::Error`] nodes and unexpected tokens are
+    /// skipped. Returns the best-effort tree with all errors found, in
+    /// the order they occur in the query.
+    pub fn parse_recovering(
+        &mut self,
         get: &mut dyn Iterator<Item = char>,
-    ) -> Result<CqlNode, ParseError> {
-        self.lexer.next(get);
-        self.look = self.lexer.lex(get)?;
-        let rel = CqlNode::mk_sc("cql.serverChoice", "=", None, None);
-        let search = self.cql_query(get, &rel)?;
+    ) -> (CqlNode, Vec<ParseError>) {
+        self.diagnostics = Some(Vec::new());
+        let res = self.query(get);
+        let mut diagnostics = self.diagnostics.take().unwrap_or_default();
+        match res {
+            Ok(node) => (node, diagnostics),
+            Err(err) => {
+                let node = CqlNode::mk_error(err.message(), err.span());
+                diagnostics.push(err);
+                (CqlNode::mk_root(Box::new(node), Vec::new()), diagnostics)
+            }
+        }
+    }
+
+    fn query(&mut self, get: &mut dyn Iterator<Item = char>) -> Result<CqlNode, ParseError> {
+        self.prefixes.clear();
+        self.lexer.start(get);
+        self.advance(get)?;
+        let query_start = self.lexer.token_start();
+        // top-level prefix assignments also scope over the sort keys
+        let prefixes = self.prefix_assignments(get)?;
+        let rel = CqlNode::mk_sc("cql.serverChoice", "=", None, Vec::new());
+        let rel = self.resolve(rel, "cql.serverChoice", "=");
+        let start = self.lexer.token_start();
+        let mut search = self.scoped_clause(get, &rel, "")?;
         let mut sort = Vec::new();
-        if let Token::Sortby(_sortby) = &self.look {
-            self.look = self.lexer.lex(get)?;
-            while let Some(index) = &self.search_term() {
-                self.look = self.lexer.lex(get)?;
-                let modifiers = self.modifiers(get)?;
-                sort.push(CqlNode::mk_sc(&index, "", None, modifiers));
+        loop {
+            match &self.look {
+                Token::EOS => break,
+                Token::Sortby(_) => {
+                    self.advance(get)?;
+                    self.sort_keys(get, &mut sort)?;
+                    break;
+                }
+                Token::RP => {
+                    let err = self.error(
+                        ErrorKind::UnbalancedParenthesis,
+                        "unexpected \")\" without matching \"(\"",
+                        &["boolean operator", "\"sortby\"", "end of query"],
+                    );
+                    self.report(err)?;
+                }
+                _ => {
+                    let err = self.error(
+                        ErrorKind::UnexpectedToken,
+                        "expected boolean operator, \"sortby\" or end of query",
+                        &["boolean operator", "\"sortby\"", "end of query"],
+                    );
+                    self.report(err)?;
+                }
             }
+            self.skip_unexpected(get, false)?;
+            search = self.boolean_tail(get, search, &rel, start)?;
         }
-        if self.look != Token::EOS {
-            return Err(ParseError);
+        search.add_prefixes(prefixes);
+        let root = CqlNode::mk_root(Box::new(search), sort);
+        Ok(root.with_span(self.span_from(query_start)))
+    }
+
+    fn sort_keys(
+        &mut self,
+        get: &mut dyn Iterator<Item = char>,
+        sort: &mut Vec<St>,
+    ) -> Result<(), ParseError> {
+        loop {
+            while let Some(index) = &self.search_term() {
+                let index_span = self.lexer.token_span();
+                self.advance(get)?;
+                let modifiers = self.modifiers(get, "sort")?;
+                let key = CqlNode::mk_sc(index, "", None, modifiers)
+                    .with_spans(Some(index_span), None)
+                    .with_span(self.span_from(index_span.start));
+                sort.push(self.resolve(key, index, ""));
+            }
+            if self.look == Token::EOS {
+                return Ok(());
+            }
+            let err = self.error(
+                ErrorKind::UnexpectedToken,
+                "expected sort key or end of query",
+                &["sort key", "end of query"],
+            );
+            self.report(err)?;
+            self.advance(get)?;
         }
-        Ok(CqlNode::mk_root(Box::new(search), sort))
     }
 }
 
@@ -291,4 +622,433 @@ mod tests {
         let res = my.parse("foo equals x".chars().borrow_mut());
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn prefix_errors() {
+        let mut my = Parser::new();
+        let res = my.parse(">".chars().borrow_mut());
+        assert!(res.is_err());
+
+        let res = my.parse(">dc=".chars().borrow_mut());
+        assert!(res.is_err());
+
+        let res = my.parse(">dc=\"http://purl.org/dc\"".chars().borrow_mut());
+        assert!(res.is_err());
+
+        let res = my.parse("a and >dc=x b".chars().borrow_mut());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn prefix_assignments() {
+        let mut my = Parser::new();
+        let res = my.parse(
+            ">dc=\"info:srw/cql-context-set/1/dc-v1.1\" dc.title=x"
+                .chars()
+                .borrow_mut(),
+        );
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert_eq!(st.index(), "dc.title");
+                assert_eq!(st.index_uri(), Some("info:srw/cql-context-set/1/dc-v1.1"));
+                assert_eq!(st.relation_uri(), Some(CQL_CONTEXT_SET));
+                assert_eq!(st.prefixes().len(), 1);
+                assert_eq!(st.prefixes()[0].name(), Some("dc"));
+                assert_eq!(st.prefixes()[0].uri(), "info:srw/cql-context-set/1/dc-v1.1");
+            });
+        });
+
+        let res = my.parse(">\"http://default\" title = x".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert_eq!(st.index_uri(), Some("http://default"));
+                assert_eq!(st.prefixes()[0].name(), None);
+            });
+        });
+
+        let res = my.parse("x".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert_eq!(st.index(), "cql.serverChoice");
+                assert_eq!(st.index_uri(), Some(CQL_CONTEXT_SET));
+                assert!(st.prefixes().is_empty());
+            });
+        });
+
+        let res = my.parse("title = x and dc.title = y".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.left(), CqlNode::St(st) => {
+                    assert!(st.index_uri().is_none());
+                });
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert!(st.index_uri().is_none());
+                });
+            });
+        });
+
+        let res = my.parse("ti dc.near x".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert!(st.relation_uri().is_none());
+            });
+        });
+    }
+
+    #[test]
+    fn prefix_scope() {
+        let mut my = Parser::new();
+        let res = my.parse(
+            ">dc=\"http://a\" (>dc=\"http://b\" dc.ti = x) and dc.ti = y sortby dc.date"
+                .chars()
+                .borrow_mut(),
+        );
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.prefixes().len(), 1);
+                assert_matches!(bo.left(), CqlNode::St(st) => {
+                    assert_eq!(st.index_uri(), Some("http://b"));
+                    assert_eq!(st.prefixes().len(), 1);
+                    assert_eq!(st.prefixes()[0].uri(), "http://b");
+                });
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert_eq!(st.index_uri(), Some("http://a"));
+                    assert!(st.prefixes().is_empty());
+                });
+            });
+            assert_eq!(root.sort().len(), 1);
+            assert_eq!(root.sort()[0].index_uri(), Some("http://a"));
+            assert!(root.sort()[0].relation_uri().is_none());
+        });
+
+        let res = my.parse(
+            ">dc=\"http://a\" >bath=\"http://c\" (bath.name = x or dc.ti = y)"
+                .chars()
+                .borrow_mut(),
+        );
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.prefixes().len(), 2);
+                assert_eq!(bo.prefixes()[0].name(), Some("dc"));
+                assert_eq!(bo.prefixes()[1].name(), Some("bath"));
+                assert_matches!(bo.left(), CqlNode::St(st) => {
+                    assert_eq!(st.index_uri(), Some("http://c"));
+                });
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert_eq!(st.index_uri(), Some("http://a"));
+                });
+            });
+        });
+
+        let res = my.parse("(>dc=\"http://a\" x) or dc.ti = y".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert!(st.index_uri().is_none());
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn modifiers() {
+        let mut my = Parser::new();
+        let res = my.parse("ti =/a=1/b=2/c x".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                let m = st.modifiers();
+                assert_eq!(m.len(), 3);
+                assert_eq!(m[0].name(), "a");
+                assert_eq!(m[0].relation(), Some("="));
+                assert_eq!(m[0].value(), Some("1"));
+                assert_eq!(m[1].name(), "b");
+                assert_eq!(m[1].value(), Some("2"));
+                assert_eq!(m[2].name(), "c");
+                assert!(m[2].relation().is_none());
+                assert!(m[2].value().is_none());
+            });
+        });
+
+        let res = my.parse("ti adj/cql.word/cql.ignoreCase x".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                let m = st.modifiers();
+                assert_eq!(m.len(), 2);
+                assert_eq!(m[0].name(), "cql.word");
+                assert_eq!(m[0].name_uri(), Some(CQL_CONTEXT_SET));
+                assert_eq!(m[1].name(), "cql.ignoreCase");
+            });
+        });
+
+        let res = my.parse("ti = (a or b)".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert!(bo.modifiers().is_empty());
+            });
+        });
+
+        let res = my.parse("ti =/x=y (a or b)".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert_eq!(st.modifiers().len(), 1);
+                    assert_eq!(st.modifiers()[0].name(), "x");
+                });
+            });
+        });
+
+        let res = my.parse("a prox/unit=word/distance<=3 b".chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                let m = bo.modifiers();
+                assert_eq!(m.len(), 2);
+                assert_eq!(m[0].name(), "unit");
+                assert_eq!(m[0].value(), Some("word"));
+                assert_eq!(m[0].name_uri(), Some(CQL_CONTEXT_SET));
+                assert_eq!(m[1].name(), "distance");
+                assert_eq!(m[1].relation(), Some("<="));
+                assert_eq!(m[1].value(), Some("3"));
+            });
+        });
+
+        let res = my.parse(
+            ">dc=\"http://dc\" a sortby title/sort.ascending/missingOmit date/dc.x"
+                .chars()
+                .borrow_mut(),
+        );
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            let sort = root.sort();
+            assert_eq!(sort.len(), 2);
+            assert_eq!(sort[0].modifiers().len(), 2);
+            assert_eq!(sort[0].modifiers()[0].name(), "sort.ascending");
+            assert_eq!(sort[0].modifiers()[0].name_uri(), Some(SORT_CONTEXT_SET));
+            assert_eq!(sort[0].modifiers()[1].name(), "missingOmit");
+            assert_eq!(sort[0].modifiers()[1].name_uri(), Some(SORT_CONTEXT_SET));
+            assert_eq!(sort[1].modifiers()[0].name_uri(), Some("http://dc"));
+        });
+    }
+
+    fn check_error(query: &str, kind: ErrorKind, column: usize, token: &str) -> ParseError {
+        let mut my = Parser::new();
+        let res = my.parse(query.chars().borrow_mut());
+        assert_matches!(res, Err(e) => {
+            assert_eq!(e.kind(), kind);
+            assert_eq!(e.position().column, column);
+            assert_eq!(e.token(), token);
+            e
+        })
+    }
+
+    #[test]
+    fn error_details() {
+        let e = check_error("  ", ErrorKind::EmptyQuery, 3, "end of query");
+        assert_eq!(e.expected(), ["search term", "\"(\""]);
+
+        let e = check_error("ti =", ErrorKind::MissingSearchTerm, 5, "end of query");
+        assert_eq!(e.message(), "expected search term after relation");
+        assert_eq!(
+            e.render("ti ="),
+            "ti =\n    ^ expected search term after relation, found end of query"
+        );
+
+        let e = check_error("a and )", ErrorKind::MissingSearchTerm, 7, "\")\"");
+        assert_eq!(e.message(), "expected search term after boolean operator");
+
+        let e = check_error("(and", ErrorKind::UnbalancedParenthesis, 5, "end of query");
+        assert_eq!(e.expected(), ["\")\"", "boolean operator"]);
+
+        let e = check_error("()", ErrorKind::MissingSearchTerm, 2, "\")\"");
+        assert_eq!(e.message(), "expected search term after \"(\"");
+
+        check_error("a)", ErrorKind::UnbalancedParenthesis, 2, "\")\"");
+        check_error("ti = / ", ErrorKind::MissingModifierName, 8, "end of query");
+        check_error("ti = /x= )", ErrorKind::MissingModifierValue, 10, "\")\"");
+        check_error(">dc=x", ErrorKind::MissingSearchTerm, 6, "end of query");
+        check_error("> = a", ErrorKind::InvalidPrefixAssignment, 3, "\"=\"");
+        check_error(">dc=(", ErrorKind::InvalidPrefixAssignment, 5, "\"(\"");
+        check_error("foo equals x", ErrorKind::UnexpectedToken, 5, "\"equals\"");
+        check_error("a sortby b (", ErrorKind::UnexpectedToken, 12, "\"(\"");
+
+        let e = check_error("a and\n\tb c", ErrorKind::UnexpectedToken, 4, "\"c\"");
+        assert_eq!(e.position().line, 2);
+        assert_eq!(e.position().offset, 9);
+        assert_eq!(
+            e.to_string(),
+            "expected boolean operator, \"sortby\" or end of query, found \"c\" at line 2, column 4"
+        );
+    }
+
+    fn recovering(query: &str) -> (CqlNode, Vec<ParseError>) {
+        let mut my = Parser::new();
+        my.parse_recovering(query.chars().borrow_mut())
+    }
+
+    #[test]
+    fn recover_ok() {
+        let (node, diagnostics) = recovering("ti = x and y");
+        assert!(diagnostics.is_empty());
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(_));
+        });
+    }
+
+    #[test]
+    fn recover_missing_terms() {
+        let (node, diagnostics) = recovering("a and");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::MissingSearchTerm);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.left(), CqlNode::St(st) => {
+                    assert_eq!(st.term(), Some("a"));
+                });
+                assert_matches!(bo.right(), CqlNode::Error(e) => {
+                    assert_eq!(e.message(), "expected search term after boolean operator");
+                    assert_eq!(e.span().start.offset, 5);
+                });
+            });
+        });
+
+        let (node, diagnostics) = recovering("ti = or b");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::UnexpectedToken);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                assert_eq!(st.term(), Some("or"));
+            });
+        });
+
+        let (node, diagnostics) = recovering("ti = ) or b");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::MissingSearchTerm);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.left(), CqlNode::Error(_));
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert_eq!(st.term(), Some("b"));
+                });
+            });
+        });
+
+        let (node, diagnostics) = recovering(" ");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::EmptyQuery);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Error(_));
+        });
+    }
+
+    #[test]
+    fn recover_parentheses() {
+        let (node, diagnostics) = recovering("(a or b");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::UnbalancedParenthesis);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.value(), "or");
+            });
+        });
+
+        let (node, diagnostics) = recovering("a) and b)");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].position().offset, 1);
+        assert_eq!(diagnostics[1].position().offset, 8);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.value(), "and");
+            });
+        });
+
+        let (node, diagnostics) = recovering("(a b and c) or d");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].position().offset, 3);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(bo.value(), "or");
+                assert_matches!(bo.left(), CqlNode::Boolean(bo) => {
+                    assert_eq!(bo.value(), "and");
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn recover_modifiers() {
+        let (node, diagnostics) = recovering("ti =/ /x=/y x sortby t/");
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::MissingModifierName);
+        assert_eq!(diagnostics[1].kind(), ErrorKind::MissingModifierValue);
+        assert_eq!(diagnostics[2].kind(), ErrorKind::MissingModifierName);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::St(st) => {
+                let m = st.modifiers();
+                assert_eq!(m.len(), 2);
+                assert_eq!(m[0].name(), "x");
+                assert!(m[0].value().is_none());
+                assert_eq!(m[1].name(), "y");
+                assert_eq!(st.term(), Some("x"));
+            });
+            assert_eq!(root.sort().len(), 1);
+        });
+
+        let (node, diagnostics) = recovering("> = a sortby b ( c");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].kind(), ErrorKind::InvalidPrefixAssignment);
+        assert_eq!(diagnostics[1].kind(), ErrorKind::UnexpectedToken);
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Error(_));
+            assert_eq!(root.sort().len(), 2);
+        });
+    }
+
+    fn source(span: Span, query: &str) -> &str {
+        span.source(query).unwrap()
+    }
+
+    #[test]
+    fn spans() {
+        let query = " >dc=\"http://dc\" (dc.ti =/a=1 \"ä b\" or ti = (c)) and/x d sortby t/y ";
+        let mut my = Parser::new();
+        let res = my.parse(query.chars().borrow_mut());
+        assert_matches!(res, Ok(CqlNode::Root(root)) => {
+            assert_eq!(source(root.span(), query), &query[1..query.len() - 1]);
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_eq!(source(bo.span(), query), "(dc.ti =/a=1 \"ä b\" or ti = (c)) and/x d");
+                assert_eq!(source(bo.prefixes()[0].span(), query), ">dc=\"http://dc\"");
+                assert_eq!(source(bo.modifiers()[0].span(), query), "/x");
+                assert_matches!(bo.left(), CqlNode::Boolean(bo) => {
+                    assert_eq!(source(bo.span(), query), "dc.ti =/a=1 \"ä b\" or ti = (c)");
+                    assert_matches!(bo.left(), CqlNode::St(st) => {
+                        assert_eq!(source(st.span(), query), "dc.ti =/a=1 \"ä b\"");
+                        assert_eq!(source(st.index_span().unwrap(), query), "dc.ti");
+                        assert_eq!(source(st.relation_span().unwrap(), query), "=");
+                        assert_eq!(source(st.term_span().unwrap(), query), "\"ä b\"");
+                        assert_eq!(source(st.modifiers()[0].span(), query), "/a=1");
+                    });
+                    assert_matches!(bo.right(), CqlNode::St(st) => {
+                        assert_eq!(source(st.span(), query), "c");
+                        assert_eq!(source(st.index_span().unwrap(), query), "ti");
+                    });
+                });
+                assert_matches!(bo.right(), CqlNode::St(st) => {
+                    assert_eq!(source(st.span(), query), "d");
+                    assert!(st.index_span().is_none());
+                    assert!(st.relation_span().is_none());
+                });
+            });
+            assert_eq!(source(root.sort()[0].span(), query), "t/y");
+            assert_eq!(source(root.sort()[0].index_span().unwrap(), query), "t");
+            assert!(root.sort()[0].term_span().is_none());
+        });
+
+        let (node, _) = my.parse_recovering("a and ) ".chars().borrow_mut());
+        assert_matches!(node, CqlNode::Root(root) => {
+            assert_matches!(root.search(), CqlNode::Boolean(bo) => {
+                assert_matches!(bo.right(), CqlNode::Error(e) => {
+                    assert_eq!(e.span().start.offset, 6);
+                    assert_eq!(e.span().end.offset, 7);
+                });
+            });
+        });
+    }
 }
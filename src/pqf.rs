@@ -0,0 +1,964 @@
+//! Conversion of CQL to PQF, the prefix query format of YAZ, configured
+//! by a properties file in the format of YAZ's `cql2pqf`:
+//!
+//! ```text
+//! set.dc                = info:srw/cql-context-set/1/dc-v1.1
+//! set                   = info:srw/cql-context-set/1/dc-v1.1
+//! index.cql.serverChoice = 1=1016
+//! index.dc.title        = 1=4
+//! relation.eq           = 2=3
+//! relation.<            = 2=1
+//! relationModifier.stem = 2=101
+//! position.first        = 3=1 6=1
+//! position.any          = 3=3 6=1
+//! structure.exact       = 4=108
+//! structure.*           = 4=1
+//! truncation.right      = 5=1
+//! truncation.z3958      = 5=104
+//! ```
+//!
+//! `set.<name>` gives the URI of a context set, and `set` the set that
+//! unqualified indexes belong to when the query has no default. Indexes
+//! are looked up as `index.<set name>.<index>`, relations by name, with
+//! `=` as `eq`, `==` as `exact` and `<=`, `>=` as `le`, `ge`. Structure
+//! falls back to `structure.*`. Terms anchored with `^` use `position.first`,
+//! `position.last` or `position.firstAndLast`, others `position.any` if
+//! given. Masking is mapped to `truncation.right`, `left` or `both` when
+//! only leading or trailing `*` is used, otherwise to Z39.58 truncation
+//! or regular expressions. Property names are case insensitive. Sort keys
+//! have no PQF equivalent and are ignored.
+//!
+//! The same properties convert Type-1 queries, or PQF text, back to CQL
+//! with [`PqfTransform::rpn_to_cql`] and [`PqfTransform::pqf_to_cql`].
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::St;
+use crate::rpn::AttributeElement;
+use crate::rpn::AttributeValue;
+use crate::rpn::AttributesPlusTerm;
+use crate::rpn::Operand;
+use crate::rpn::Operator;
+use crate::rpn::ProximityOperator;
+use crate::rpn::ProximityUnit;
+use crate::rpn::RpnQuery;
+use crate::rpn::RpnStructure;
+use crate::rpn::StringOrNumeric;
+use crate::rpn::Term as RpnTerm;
+use crate::rpn::BIB1;
+use crate::term::is_masked;
+use crate::term::regex;
+use crate::term::text;
+use crate::term::trim_stars;
+use crate::term::TermChar;
+use crate::translate::unqualified;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::QueryTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+use std::fmt;
+use std::str::FromStr;
+
+mod reverse;
+
+/// Error from reading a properties file or converting a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PqfError {
+    /// A properties line without `=`.
+    InvalidProperty { line: usize, text: String },
+    /// A property value that is not a list of `type=value` attributes.
+    InvalidAttribute(String),
+    /// PQF text that cannot be parsed.
+    Syntax { offset: usize, message: String },
+    /// The query cannot be converted: its index, relation, relation
+    /// modifier, anchoring or masking has no matching property, or it
+    /// has errors.
+    Translation(TranslationError),
+    /// An attribute, or attribute set, with no matching property when
+    /// converting back to CQL.
+    UnsupportedAttribute(String),
+    /// An operator with no CQL equivalent.
+    UnsupportedOperator(String),
+    /// A term with no CQL equivalent.
+    UnsupportedTerm(String),
+}
+
+impl fmt::Display for PqfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PqfError::InvalidProperty { line, text } => {
+                write!(f, "expected key = value at line {}: {}", line, text)
+            }
+            PqfError::InvalidAttribute(s) => write!(f, "invalid attribute {}", s),
+            PqfError::Syntax { offset, message } => write!(f, "{} at offset {}", message, offset),
+            PqfError::Translation(e) => e.fmt(f),
+            PqfError::UnsupportedAttribute(s) => write!(f, "no mapping for attribute {}", s),
+            PqfError::UnsupportedOperator(s) => write!(f, "unsupported operator {}", s),
+            PqfError::UnsupportedTerm(s) => write!(f, "unsupported term {}", s),
+        }
+    }
+}
+
+impl std::error::Error for PqfError {}
+
+impl From<TranslationError> for PqfError {
+    fn from(e: TranslationError) -> PqfError {
+        PqfError::Translation(e)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PqfTransform {
+    /// In the order they were given, which decides between properties
+    /// with the same attributes when converting back to CQL.
+    properties: Vec<(String, String)>,
+}
+
+impl PqfTransform {
+    pub fn new() -> PqfTransform {
+        PqfTransform::default()
+    }
+
+    /// Read properties in YAZ format: `key = value` lines, with `#`
+    /// starting a comment line.
+    pub fn from_properties(text: &str) -> Result<PqfTransform, PqfError> {
+        let mut res = PqfTransform::new();
+        for (no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(PqfError::InvalidProperty {
+                    line: no + 1,
+                    text: String::from(line),
+                });
+            };
+            res.set(key.trim(), value.trim());
+        }
+        Ok(res)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self
+            .properties
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some((_, v)) => *v = String::from(value),
+            None => self
+                .properties
+                .push((String::from(key), String::from(value))),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Properties whose keys start with `prefix`, with the rest of the
+    /// key, in order.
+    pub(crate) fn with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.properties.iter().filter_map(move |(key, value)| {
+            let head = key.get(..prefix.len())?;
+            head.eq_ignore_ascii_case(prefix)
+                .then(|| (&key[prefix.len()..], value.as_str()))
+        })
+    }
+
+    /// Name of the context set with `uri`, from the `set.*` properties.
+    pub(crate) fn set_name(&self, uri: &str) -> Option<&str> {
+        self.with_prefix("set.")
+            .find(|(_, value)| *value == uri)
+            .map(|(name, _)| name)
+    }
+
+    /// Add the attributes in property `value`, such as `3=1 6=1`.
+    /// Values that are not numbers are kept as strings.
+    pub(crate) fn attributes(value: &str, out: &mut Vec<AttributeElement>) -> Result<(), PqfError> {
+        for attr in value.split_whitespace() {
+            let invalid = || PqfError::InvalidAttribute(String::from(attr));
+            let (attribute_type, value) = attr.split_once('=').ok_or_else(invalid)?;
+            let attribute_type = attribute_type.parse().map_err(|_| invalid())?;
+            let value = match value.parse() {
+                Ok(n) => AttributeValue::Numeric(n),
+                Err(_) if !value.is_empty() => {
+                    AttributeValue::Complex(vec![StringOrNumeric::String(String::from(value))])
+                }
+                Err(_) => return Err(invalid()),
+            };
+            out.push(AttributeElement {
+                attribute_set: None,
+                attribute_type,
+                value,
+            });
+        }
+        Ok(())
+    }
+
+    fn index(&self, st: &St, out: &mut Vec<AttributeElement>) -> Result<(), PqfError> {
+        let unsupported = || PqfError::from(TranslationError::index(st));
+        let (qualifier, name) = match st.index().split_once('.') {
+            Some((qualifier, name)) => (Some(qualifier), name),
+            None => (None, st.index()),
+        };
+        let uri = st.index_uri().or_else(|| match qualifier {
+            Some(_) => None,
+            None => self.get("set"),
+        });
+        let set = match uri.and_then(|uri| self.set_name(uri)) {
+            Some(set) => set,
+            None => qualifier.ok_or_else(unsupported)?,
+        };
+        let value = self
+            .get(&format!("index.{}.{}", set, name))
+            .ok_or_else(unsupported)?;
+        PqfTransform::attributes(value, out)
+    }
+
+    /// Name of the relation in `relation.*` properties.
+    fn relation_name(st: &St) -> String {
+        let relation = st.relation();
+        let relation = match relation {
+            "=" => "eq",
+            "==" => "exact",
+            "<=" => "le",
+            ">=" => "ge",
+            _ => relation,
+        };
+        match relation.split_once('.') {
+            Some((prefix, name)) if prefix.eq_ignore_ascii_case("cql") => name.to_ascii_lowercase(),
+            _ => relation.to_ascii_lowercase(),
+        }
+    }
+
+    fn relation(
+        &self,
+        clause: &Clause<'_>,
+        relation: &str,
+        out: &mut Vec<AttributeElement>,
+    ) -> Result<(), PqfError> {
+        let value = self
+            .get(&format!("relation.{}", relation))
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedRelation))?;
+        PqfTransform::attributes(value, out)?;
+        for m in clause.st().modifiers() {
+            let name = match m.name().split_once('.') {
+                Some((_, name)) => name,
+                None => m.name(),
+            };
+            let value = self
+                .get(&format!("relationModifier.{}", name))
+                .ok_or_else(|| {
+                    let kind = TranslationErrorKind::UnsupportedRelationModifier;
+                    TranslationError::modifier(kind, m)
+                })?;
+            PqfTransform::attributes(value, out)?;
+        }
+        Ok(())
+    }
+
+    fn position(
+        &self,
+        clause: &Clause<'_>,
+        out: &mut Vec<AttributeElement>,
+    ) -> Result<(), PqfError> {
+        let term = clause.term();
+        let key = match (term.first, term.last) {
+            (true, true) => "position.firstAndLast",
+            (true, false) => "position.first",
+            (false, true) => "position.last",
+            (false, false) => {
+                if let Some(value) = self.get("position.any") {
+                    PqfTransform::attributes(value, out)?;
+                }
+                return Ok(());
+            }
+        };
+        let value = self
+            .get(key)
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedAnchoring))?;
+        PqfTransform::attributes(value, out)
+    }
+
+    fn structure(&self, relation: &str, out: &mut Vec<AttributeElement>) -> Result<(), PqfError> {
+        let value = self
+            .get(&format!("structure.{}", relation))
+            .or_else(|| self.get("structure.*"));
+        match value {
+            Some(value) => PqfTransform::attributes(value, out),
+            None => Ok(()),
+        }
+    }
+
+    /// Emit the truncation attribute and return the term to search for.
+    fn truncation(
+        &self,
+        clause: &Clause<'_>,
+        out: &mut Vec<AttributeElement>,
+    ) -> Result<String, PqfError> {
+        let term = clause.term();
+        if !is_masked(&term.chars) {
+            if let Some(value) = self.get("truncation.none") {
+                PqfTransform::attributes(value, out)?;
+            }
+            return Ok(term.text());
+        }
+        let (body, start, end) = trim_stars(&term.chars, true, true);
+        if !is_masked(body) {
+            let key = match (start, end) {
+                (false, false) => "truncation.both",
+                (false, true) => "truncation.left",
+                _ => "truncation.right",
+            };
+            if let Some(value) = self.get(key) {
+                PqfTransform::attributes(value, out)?;
+                return Ok(text(body));
+            }
+        }
+        if let Some(value) = self.get("truncation.z3958") {
+            PqfTransform::attributes(value, out)?;
+            let res = term
+                .chars
+                .iter()
+                .map(|ch| match ch {
+                    TermChar::Star => '?',
+                    TermChar::Question => '#',
+                    TermChar::Literal(ch) => *ch,
+                })
+                .collect();
+            return Ok(res);
+        }
+        if let Some(value) = self.get("truncation.regexp") {
+            PqfTransform::attributes(value, out)?;
+            return Ok(regex(&term.chars, false, false));
+        }
+        Err(clause
+            .error(TranslationErrorKind::UnsupportedMasking)
+            .into())
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<RpnStructure, PqfError> {
+        let st = clause.st();
+        let relation = PqfTransform::relation_name(st);
+        let mut attributes = Vec::new();
+        self.index(st, &mut attributes)?;
+        self.relation(clause, &relation, &mut attributes)?;
+        self.position(clause, &mut attributes)?;
+        self.structure(&relation, &mut attributes)?;
+        let term = self.truncation(clause, &mut attributes)?;
+        Ok(RpnStructure::Operand(Operand::AttrTerm(
+            AttributesPlusTerm {
+                attributes,
+                term: RpnTerm::General(term.into_bytes()),
+            },
+        )))
+    }
+
+    /// Proximity operator from the modifiers of `prox`. The defaults
+    /// are unordered words at a distance of at most one.
+    fn prox(modifiers: &[Modifier]) -> Result<Operator, PqfError> {
+        let mut prox = ProximityOperator {
+            exclusion: Some(false),
+            distance: 1,
+            ordered: false,
+            relation_type: 2,
+            unit: ProximityUnit::Known(2),
+        };
+        for m in modifiers {
+            let unsupported = || {
+                let kind = TranslationErrorKind::UnsupportedProxModifier;
+                PqfError::from(TranslationError::modifier(kind, m))
+            };
+            match (unqualified(m.name()).as_str(), m.relation(), m.value()) {
+                ("distance", Some(rel), Some(value)) => {
+                    prox.relation_type = match rel {
+                        "<" => 1,
+                        "<=" => 2,
+                        "=" => 3,
+                        ">=" => 4,
+                        ">" => 5,
+                        "<>" => 6,
+                        _ => return Err(unsupported()),
+                    };
+                    prox.distance = value.parse::<u32>().map_err(|_| unsupported())?.into();
+                }
+                ("unit", Some("="), Some(value)) => {
+                    let unit = match value.to_ascii_lowercase().as_str() {
+                        "character" => 1,
+                        "word" => 2,
+                        "sentence" => 3,
+                        "paragraph" => 4,
+                        "element" => 8,
+                        _ => return Err(unsupported()),
+                    };
+                    prox.unit = ProximityUnit::Known(unit);
+                }
+                ("ordered", None, None) => prox.ordered = true,
+                ("unordered", None, None) => prox.ordered = false,
+                _ => return Err(unsupported()),
+            }
+        }
+        Ok(Operator::Prox(prox))
+    }
+
+    /// Convert a query to a Type-1 query with the Bib-1 attribute set.
+    pub fn to_rpn(&self, node: &CqlNode) -> Result<RpnQuery, PqfError> {
+        let rpn = Translator { transform: self }.translate(node)?;
+        Ok(RpnQuery::new(rpn))
+    }
+
+    /// Convert a query to PQF.
+    pub fn to_pqf(&self, node: &CqlNode) -> Result<String, PqfError> {
+        Ok(self.to_rpn(node)?.to_string())
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a PqfTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = RpnStructure;
+    type Error = PqfError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<RpnStructure, PqfError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, RpnStructure)>,
+    ) -> Result<RpnStructure, PqfError> {
+        let op = match op {
+            BooleanOp::And => Operator::And,
+            BooleanOp::Or => Operator::Or,
+            BooleanOp::Not => Operator::AndNot,
+            BooleanOp::Prox => PqfTransform::prox(bo.modifiers())?,
+        };
+        let mut operands = operands.into_iter().map(|(_, rpn)| Box::new(rpn));
+        match (operands.next(), operands.next()) {
+            (Some(left), Some(right)) => Ok(RpnStructure::RpnOp { left, right, op }),
+            _ => Err(TranslationError::boolean(bo).into()),
+        }
+    }
+
+    /// Modifiers of `and`, `or` and `not` are ignored.
+    fn accepts(&self, _op: BooleanOp, _modified: bool) -> bool {
+        true
+    }
+}
+
+impl FromStr for PqfTransform {
+    type Err = PqfError;
+
+    fn from_str(s: &str) -> Result<PqfTransform, PqfError> {
+        PqfTransform::from_properties(s)
+    }
+}
+
+struct PqfToken {
+    offset: usize,
+    text: String,
+    quoted: bool,
+}
+
+/// Parser of PQF text, as written by `Display` for [`RpnQuery`] and by
+/// YAZ. Attributes given before an operator apply to all its operands.
+struct PqfReader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl PqfReader<'_> {
+    fn syntax(offset: usize, message: &str) -> PqfError {
+        PqfError::Syntax {
+            offset,
+            message: String::from(message),
+        }
+    }
+
+    /// Next word or quoted string, with escapes removed from strings.
+    fn token(&mut self) -> Result<Option<PqfToken>, PqfError> {
+        let rest = &self.text[self.pos..];
+        let skipped = rest.len() - rest.trim_start().len();
+        self.pos += skipped;
+        let offset = self.pos;
+        let mut chars = self.text[offset..].char_indices();
+        let Some((_, first)) = chars.next() else {
+            return Ok(None);
+        };
+        let mut text = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some((i, '"')) => {
+                        self.pos = offset + i + 1;
+                        break;
+                    }
+                    Some((_, '\\')) => {
+                        if let Some((_, ch)) = chars.next() {
+                            text.push(ch);
+                        }
+                    }
+                    Some((_, ch)) => text.push(ch),
+                    None => return Err(PqfReader::syntax(offset, "unterminated string")),
+                }
+            }
+            return Ok(Some(PqfToken {
+                offset,
+                text,
+                quoted: true,
+            }));
+        }
+        text.push(first);
+        self.pos = self.text.len();
+        for (i, ch) in chars {
+            if ch.is_whitespace() {
+                self.pos = offset + i;
+                break;
+            }
+            text.push(ch);
+        }
+        Ok(Some(PqfToken {
+            offset,
+            text,
+            quoted: false,
+        }))
+    }
+
+    fn expect(&mut self, what: &str) -> Result<PqfToken, PqfError> {
+        match self.token()? {
+            Some(token) => Ok(token),
+            None => Err(PqfReader::syntax(self.pos, &format!("expected {}", what))),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<i64, PqfError> {
+        let token = self.expect(what)?;
+        token
+            .text
+            .parse()
+            .map_err(|_| PqfReader::syntax(token.offset, &format!("expected {}", what)))
+    }
+
+    fn flag(&mut self, what: &str) -> Result<bool, PqfError> {
+        let token = self.expect(what)?;
+        match token.text.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(PqfReader::syntax(
+                token.offset,
+                &format!("expected {}", what),
+            )),
+        }
+    }
+
+    fn attribute_set(token: &PqfToken) -> Result<Vec<u32>, PqfError> {
+        if token.text.eq_ignore_ascii_case("bib-1") {
+            return Ok(BIB1.to_vec());
+        }
+        let arcs: Result<Vec<u32>, _> = token.text.split('.').map(str::parse).collect();
+        match arcs {
+            Ok(arcs) if arcs.len() >= 2 => Ok(arcs),
+            _ => Err(PqfReader::syntax(token.offset, "unknown attribute set")),
+        }
+    }
+
+    fn attribute(&mut self) -> Result<AttributeElement, PqfError> {
+        let mut token = self.expect("attribute")?;
+        let mut attribute_set = None;
+        if !token.text.contains('=') {
+            attribute_set = Some(PqfReader::attribute_set(&token)?);
+            token = self.expect("attribute")?;
+        }
+        let invalid = || PqfReader::syntax(token.offset, "expected attribute type=value");
+        let (attribute_type, value) = token.text.split_once('=').ok_or_else(invalid)?;
+        let attribute_type = attribute_type.parse().map_err(|_| invalid())?;
+        let value = match value.parse() {
+            Ok(n) => AttributeValue::Numeric(n),
+            Err(_) if !value.is_empty() => {
+                AttributeValue::Complex(vec![StringOrNumeric::String(String::from(value))])
+            }
+            Err(_) => return Err(invalid()),
+        };
+        Ok(AttributeElement {
+            attribute_set,
+            attribute_type,
+            value,
+        })
+    }
+
+    fn operand(attributes: &[AttributeElement], term: RpnTerm) -> RpnStructure {
+        RpnStructure::Operand(Operand::AttrTerm(AttributesPlusTerm {
+            attributes: attributes.to_vec(),
+            term,
+        }))
+    }
+
+    fn rpn(&mut self, attributes: &[AttributeElement]) -> Result<RpnStructure, PqfError> {
+        let token = self.expect("query")?;
+        if token.quoted || !token.text.starts_with('@') {
+            let term = RpnTerm::General(token.text.into_bytes());
+            return Ok(PqfReader::operand(attributes, term));
+        }
+        let op = match token.text.to_ascii_lowercase().as_str() {
+            "@and" => Operator::And,
+            "@or" => Operator::Or,
+            "@not" => Operator::AndNot,
+            "@prox" => {
+                let exclusion = Some(self.flag("exclusion flag")?);
+                let distance = self.number("distance")?;
+                let ordered = self.flag("ordered flag")?;
+                let relation_type = self.number("relation type")?;
+                let which = self.expect("unit type")?;
+                let unit = match which.text.to_ascii_lowercase().as_str() {
+                    "k" | "known" => ProximityUnit::Known(self.number("unit")?),
+                    "p" | "private" => ProximityUnit::Private(self.number("unit")?),
+                    _ => return Err(PqfReader::syntax(which.offset, "expected unit type")),
+                };
+                Operator::Prox(ProximityOperator {
+                    exclusion,
+                    distance,
+                    ordered,
+                    relation_type,
+                    unit,
+                })
+            }
+            "@set" => {
+                let name = self.expect("result set name")?;
+                return Ok(RpnStructure::Operand(Operand::ResultSet(name.text)));
+            }
+            "@attr" => {
+                let mut attributes = attributes.to_vec();
+                attributes.push(self.attribute()?);
+                return self.rpn(&attributes);
+            }
+            "@term" => {
+                let kind = self.expect("term type")?;
+                let text = self.expect("term")?;
+                let term = match kind.text.to_ascii_lowercase().as_str() {
+                    "general" => RpnTerm::General(text.text.into_bytes()),
+                    "string" => RpnTerm::CharacterString(text.text),
+                    "null" => RpnTerm::Null,
+                    "numeric" => {
+                        let n = text
+                            .text
+                            .parse()
+                            .map_err(|_| PqfReader::syntax(text.offset, "expected number"))?;
+                        RpnTerm::Numeric(n)
+                    }
+                    _ => return Err(PqfReader::syntax(kind.offset, "unknown term type")),
+                };
+                return Ok(PqfReader::operand(attributes, term));
+            }
+            _ => {
+                let message = format!("unknown operator {}", token.text);
+                return Err(PqfReader::syntax(token.offset, &message));
+            }
+        };
+        let left = self.rpn(attributes)?;
+        let right = self.rpn(attributes)?;
+        Ok(RpnStructure::RpnOp {
+            left: Box::new(left),
+            right: Box::new(right),
+            op,
+        })
+    }
+
+    fn query(&mut self) -> Result<RpnQuery, PqfError> {
+        let start = self.pos;
+        let mut attribute_set = BIB1.to_vec();
+        match self.token()? {
+            Some(token) if !token.quoted && token.text.eq_ignore_ascii_case("@attrset") => {
+                let token = self.expect("attribute set")?;
+                attribute_set = PqfReader::attribute_set(&token)?;
+            }
+            _ => self.pos = start,
+        }
+        let rpn = self.rpn(&[])?;
+        if let Some(token) = self.token()? {
+            return Err(PqfReader::syntax(token.offset, "expected end of query"));
+        }
+        Ok(RpnQuery { attribute_set, rpn })
+    }
+}
+
+/// Parse PQF text.
+impl FromStr for RpnQuery {
+    type Err = PqfError;
+
+    fn from_str(s: &str) -> Result<RpnQuery, PqfError> {
+        PqfReader { text: s, pos: 0 }.query()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) const PROPERTIES: &str = "
+# test properties
+set.cql                = info:srw/cql-context-set/1/cql-v1.2
+set.dc                 = info:srw/cql-context-set/1/dc-v1.1
+set                    = info:srw/cql-context-set/1/dc-v1.1
+
+index.cql.serverChoice = 1=1016
+index.dc.title         = 1=4
+index.dc.creator       = 1=1003
+index.dc.date          = 1=30
+
+relation.<             = 2=1
+relation.le            = 2=2
+relation.eq            = 2=3
+relation.exact         = 2=3
+relation.ge            = 2=4
+relation.>             = 2=5
+relation.<>            = 2=6
+relation.adj           = 2=3
+relationModifier.stem  = 2=101
+
+position.first         = 3=1 6=1
+position.any           = 3=3 6=1
+position.last          = 3=4 6=1
+position.firstAndLast  = 3=3 6=3
+
+structure.exact        = 4=108
+structure.*            = 4=1
+
+truncation.right       = 5=1
+truncation.left        = 5=2
+truncation.both        = 5=3
+truncation.z3958       = 5=104
+";
+
+    fn pqf(query: &str) -> Result<String, PqfError> {
+        let transform: PqfTransform = PROPERTIES.parse().unwrap();
+        transform.to_pqf(&crate::parse(query).unwrap())
+    }
+
+    /// The error of a query that cannot be converted.
+    fn translation<T: fmt::Debug>(res: Result<T, PqfError>) -> TranslationError {
+        match res {
+            Err(PqfError::Translation(e)) => e,
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            pqf("house"),
+            Ok(String::from(
+                "@attr 1=1016 @attr 2=3 @attr 3=3 @attr 6=1 @attr 4=1 \"house\""
+            ))
+        );
+        assert_eq!(
+            pqf("dc.title == \"a \\\"b\\\"\""),
+            Ok(String::from(
+                "@attr 1=4 @attr 2=3 @attr 3=3 @attr 6=1 @attr 4=108 \"a \\\"b\\\"\""
+            ))
+        );
+        assert_eq!(
+            pqf("title <= 2000"),
+            Ok(String::from(
+                "@attr 1=4 @attr 2=2 @attr 3=3 @attr 6=1 @attr 4=1 \"2000\""
+            ))
+        );
+        assert_eq!(
+            pqf(">d=\"info:srw/cql-context-set/1/dc-v1.1\" d.creator =/stem ^smith^"),
+            Ok(String::from(
+                "@attr 1=1003 @attr 2=3 @attr 2=101 @attr 3=3 @attr 6=3 @attr 4=1 \"smith\""
+            ))
+        );
+        assert_eq!(
+            pqf("title = ^tolkien"),
+            Ok(String::from(
+                "@attr 1=4 @attr 2=3 @attr 3=1 @attr 6=1 @attr 4=1 \"tolkien\""
+            ))
+        );
+    }
+
+    #[test]
+    fn masking() {
+        let attrs = "@attr 1=4 @attr 2=3 @attr 3=3 @attr 6=1 @attr 4=1";
+        assert_eq!(
+            pqf("title = hous*"),
+            Ok(format!("{} @attr 5=1 \"hous\"", attrs))
+        );
+        assert_eq!(
+            pqf("title = *ouse"),
+            Ok(format!("{} @attr 5=2 \"ouse\"", attrs))
+        );
+        assert_eq!(
+            pqf("title = *ous*"),
+            Ok(format!("{} @attr 5=3 \"ous\"", attrs))
+        );
+        assert_eq!(
+            pqf("title = h?us*"),
+            Ok(format!("{} @attr 5=104 \"h#us?\"", attrs))
+        );
+        assert_eq!(pqf("title = 5\\*"), Ok(format!("{} \"5*\"", attrs)));
+
+        let properties = PROPERTIES.replace("truncation.z3958", "truncation.regexp");
+        let transform = PqfTransform::from_properties(&properties).unwrap();
+        let node = crate::parse("title = h?u.*").unwrap();
+        assert_eq!(
+            transform.to_pqf(&node),
+            Ok(format!("{} @attr 5=104 \"h.u\\\\..*\"", attrs))
+        );
+
+        let transform =
+            PqfTransform::from_properties("index.dc.title = 1=4\nrelation.eq = 2=3").unwrap();
+        let node = crate::parse("dc.title = a*b").unwrap();
+        assert_eq!(
+            translation(transform.to_pqf(&node)).parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a*b")
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        let transform = PqfTransform::from_properties(
+            "set.cql = info:srw/cql-context-set/1/cql-v1.2\n\
+             index.cql.serverChoice = 1=1\nrelation.eq = 2=3",
+        )
+        .unwrap();
+        let pqf = |query: &str| transform.to_pqf(&crate::parse(query).unwrap());
+        assert_eq!(
+            pqf("a and (b or c) not d"),
+            Ok(String::from(
+                "@not @and @attr 1=1 @attr 2=3 \"a\" @or @attr 1=1 @attr 2=3 \"b\" \
+                 @attr 1=1 @attr 2=3 \"c\" @attr 1=1 @attr 2=3 \"d\""
+            ))
+        );
+        assert_eq!(
+            pqf("a prox b"),
+            Ok(String::from(
+                "@prox 0 1 0 2 k 2 @attr 1=1 @attr 2=3 \"a\" @attr 1=1 @attr 2=3 \"b\""
+            ))
+        );
+        assert_eq!(
+            pqf("a prox/unit=sentence/distance>3/ordered b"),
+            Ok(String::from(
+                "@prox 0 3 1 5 k 3 @attr 1=1 @attr 2=3 \"a\" @attr 1=1 @attr 2=3 \"b\""
+            ))
+        );
+        assert_eq!(
+            translation(pqf("a prox/unit=page b")).parts(),
+            (TranslationErrorKind::UnsupportedProxModifier, "unit")
+        );
+    }
+
+    #[test]
+    fn rpn() {
+        let transform = PqfTransform::from_properties("index.dc.title = 1=4").unwrap();
+        let node = crate::parse("dc.title = a").unwrap();
+        assert_eq!(
+            translation(transform.to_rpn(&node)).parts(),
+            (TranslationErrorKind::UnsupportedRelation, "=")
+        );
+
+        let transform =
+            PqfTransform::from_properties("index.dc.title = 1=4\nrelation.eq = 2=3 4=phrase")
+                .unwrap();
+        let query = transform.to_rpn(&node).unwrap();
+        let RpnStructure::Operand(Operand::AttrTerm(at)) = &query.rpn else {
+            panic!("{:?}", query.rpn);
+        };
+        assert_eq!(at.attributes[1], AttributeElement::numeric(2, 3));
+        assert_eq!(
+            at.attributes[2].value,
+            AttributeValue::Complex(vec![StringOrNumeric::String(String::from("phrase"))])
+        );
+        assert_eq!(at.term, RpnTerm::General(b"a".to_vec()));
+        assert_eq!(RpnQuery::decode(&query.encode()), Ok(query));
+
+        let transform = PqfTransform::from_properties("index.dc.title = 1=").unwrap();
+        assert_eq!(
+            transform.to_rpn(&node),
+            Err(PqfError::InvalidAttribute(String::from("1=")))
+        );
+    }
+
+    #[test]
+    fn parse_pqf() {
+        for pqf in [
+            "@attr 1=4 \"a \\\"b\\\"\"",
+            "@not @and @attr 1=1 @attr 2=3 \"a\" @set \"s1\" @attr 1=1 \"d\"",
+            "@prox 0 3 1 5 k 3 @attr 1=title \"a\" @attr 1.2.3 1=4 \"b\"",
+            "@attrset 1.2.840.10003.3.5 @or \"a\" @term numeric 5",
+        ] {
+            let query: RpnQuery = pqf.parse().unwrap();
+            assert_eq!(query.to_string(), pqf);
+        }
+        let query: RpnQuery = "@attrset bib-1 @attr 1=4 @or a @attr bib-1 2=3 b"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            query.to_string(),
+            "@or @attr 1=4 \"a\" @attr 1=4 @attr 1.2.840.10003.3.1 2=3 \"b\""
+        );
+        let e = "@and a".parse::<RpnQuery>().unwrap_err();
+        assert_eq!(e.to_string(), "expected query at offset 6");
+        let e = "@attr x a".parse::<RpnQuery>().unwrap_err();
+        assert_eq!(e.to_string(), "unknown attribute set at offset 6");
+        let e = "@attr 1=4 \"a".parse::<RpnQuery>().unwrap_err();
+        assert_eq!(e.to_string(), "unterminated string at offset 10");
+        let e = "@xor a b".parse::<RpnQuery>().unwrap_err();
+        assert_eq!(e.to_string(), "unknown operator @xor at offset 0");
+        let e = "a b".parse::<RpnQuery>().unwrap_err();
+        assert_eq!(e.to_string(), "expected end of query at offset 2");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            translation(pqf("dc.subject = x")).parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            translation(pqf("foo.title = x")).parts(),
+            (TranslationErrorKind::UnsupportedIndex, "foo.title")
+        );
+        assert_eq!(
+            pqf("title cql.within x").unwrap_err().to_string(),
+            "unsupported relation cql.within"
+        );
+        assert_eq!(
+            translation(pqf("title =/fuzzy x")).parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "fuzzy")
+        );
+        let transform =
+            PqfTransform::from_properties("index.dc.title = 1=4\nrelation.eq = 2=3").unwrap();
+        assert_eq!(
+            translation(transform.to_pqf(&crate::parse("dc.title = ^a").unwrap())).parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            PqfTransform::from_properties("# x\nindex.dc.title").unwrap_err(),
+            PqfError::InvalidProperty {
+                line: 2,
+                text: String::from("index.dc.title")
+            }
+        );
+
+        let mut my = crate::parser::Parser::new();
+        let (node, _) = my.parse_recovering(&mut "title =".chars());
+        let transform: PqfTransform = PROPERTIES.parse().unwrap();
+        assert_eq!(
+            translation(transform.to_pqf(&node)).kind(),
+            TranslationErrorKind::InvalidQuery
+        );
+    }
+}
@@ -0,0 +1,419 @@
+//! Conversion of Type-1 queries back to CQL, reading the properties in
+//! reverse like YAZ's `rpn2cql`.
+//!
+//! The attributes of an operand are matched against the properties in
+//! the order they were given: the first `index.*` property whose
+//! attributes are all present gives the index, and likewise for
+//! `relation.*`, `relationModifier.*`, `position.*`, `structure.*` and
+//! `truncation.*`. Matched attributes are used up, and any attribute
+//! left over has no CQL equivalent. A `structure.*` property named after
+//! a relation, such as `structure.exact`, selects that relation. Operands
+//! without an index attribute search `cql.serverChoice`, and result sets
+//! become `cql.resultSetId` clauses.
+
+use super::PqfError;
+use super::PqfTransform;
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::Prefix;
+use crate::node::St;
+use crate::parser::modifier_uri;
+use crate::parser::resolve;
+use crate::rpn::AttributeElement;
+use crate::rpn::AttributesPlusTerm;
+use crate::rpn::Operand;
+use crate::rpn::Operator;
+use crate::rpn::ProximityOperator;
+use crate::rpn::ProximityUnit;
+use crate::rpn::RpnQuery;
+use crate::rpn::RpnStructure;
+use crate::rpn::Term as RpnTerm;
+use crate::rpn::BIB1;
+
+const POSITIONS: [&str; 4] = ["first", "last", "firstAndLast", "any"];
+const TRUNCATIONS: [&str; 6] = ["none", "right", "left", "both", "z3958", "regexp"];
+
+/// Same attribute type and value; the attribute set is checked before.
+fn same(a: &AttributeElement, b: &AttributeElement) -> bool {
+    a.attribute_type == b.attribute_type && a.value == b.value
+}
+
+/// CQL relation for the name of a `relation.*` property.
+fn relation_symbol(name: &str) -> String {
+    let symbol = match name.to_ascii_lowercase().as_str() {
+        "eq" | "scr" => "=",
+        "exact" => "==",
+        "le" => "<=",
+        "ge" => ">=",
+        "lt" => "<",
+        "gt" => ">",
+        "ne" => "<>",
+        "all" | "any" | "adj" | "=" | "==" | "<" | ">" | "<=" | ">=" | "<>" => name,
+        _ if name.contains('.') => name,
+        _ => return format!("cql.{}", name),
+    };
+    String::from(symbol)
+}
+
+fn escape(ch: char, out: &mut String) {
+    if "\\\"*?^".contains(ch) {
+        out.push('\\');
+    }
+    out.push(ch);
+}
+
+/// CQL term for `term` searched with the `truncation.*` property
+/// `truncation`, anchored as given.
+fn cql_term(
+    term: &str,
+    truncation: Option<&str>,
+    first: bool,
+    last: bool,
+) -> Result<String, PqfError> {
+    let unsupported = || PqfError::UnsupportedTerm(String::from(term));
+    let mut res = String::new();
+    if first {
+        res.push('^');
+    }
+    let truncation = truncation.map(str::to_ascii_lowercase);
+    if let Some("left" | "both") = truncation.as_deref() {
+        res.push('*');
+    }
+    match truncation.as_deref() {
+        Some("z3958") => {
+            for ch in term.chars() {
+                match ch {
+                    '?' => res.push('*'),
+                    '#' => res.push('?'),
+                    _ => escape(ch, &mut res),
+                }
+            }
+        }
+        Some("regexp") => {
+            let mut it = term.chars().peekable();
+            while let Some(ch) = it.next() {
+                match ch {
+                    '.' if it.peek() == Some(&'*') => {
+                        it.next();
+                        res.push('*');
+                    }
+                    '.' => res.push('?'),
+                    '\\' => escape(it.next().ok_or_else(unsupported)?, &mut res),
+                    _ if "^$*+?()[]{}|".contains(ch) => return Err(unsupported()),
+                    _ => escape(ch, &mut res),
+                }
+            }
+        }
+        _ => term.chars().for_each(|ch| escape(ch, &mut res)),
+    }
+    if let Some("right" | "both") = truncation.as_deref() {
+        res.push('*');
+    }
+    if last {
+        res.push('^');
+    }
+    Ok(res)
+}
+
+impl PqfTransform {
+    /// Name of the first property starting with `prefix`, and named one
+    /// of `names` if not empty, whose attributes are all in `attributes`.
+    /// Its attributes are removed.
+    fn take<'a>(
+        &'a self,
+        prefix: &'a str,
+        names: &[&str],
+        attributes: &mut Vec<AttributeElement>,
+    ) -> Option<&'a str> {
+        self.with_prefix(prefix).find_map(|(name, value)| {
+            if !names.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                return None;
+            }
+            let mut wanted = Vec::new();
+            PqfTransform::attributes(value, &mut wanted).ok()?;
+            let found =
+                !wanted.is_empty() && wanted.iter().all(|w| attributes.iter().any(|a| same(a, w)));
+            if !found {
+                return None;
+            }
+            attributes.retain(|a| !wanted.iter().any(|w| same(a, w)));
+            Some(name)
+        })
+    }
+
+    /// The `set.*` and `set` properties as prefix assignments, to give
+    /// the context set URIs of the converted query.
+    fn prefixes(&self) -> Vec<Prefix> {
+        let mut res: Vec<Prefix> = self
+            .with_prefix("set.")
+            .map(|(name, uri)| Prefix::new(Some(name), uri))
+            .collect();
+        if let Some(uri) = self.get("set") {
+            res.push(Prefix::new(None, uri));
+        }
+        res
+    }
+
+    fn attr_term(&self, prefixes: &[Prefix], at: &AttributesPlusTerm) -> Result<St, PqfError> {
+        let unsupported = |a: &AttributeElement| PqfError::UnsupportedAttribute(a.to_string());
+        let mut attributes = at.attributes.clone();
+        let foreign = attributes
+            .iter()
+            .find(|a| a.attribute_set.as_ref().is_some_and(|set| set != BIB1));
+        if let Some(a) = foreign {
+            return Err(unsupported(a));
+        }
+        let index = self
+            .take("index.", &[], &mut attributes)
+            .unwrap_or("cql.serverChoice");
+        let mut relation = match self.take("relation.", &[], &mut attributes) {
+            Some(name) => relation_symbol(name),
+            None => String::from("="),
+        };
+        let mut modifiers = Vec::new();
+        while let Some(name) = self.take("relationModifier.", &[], &mut attributes) {
+            let uri = modifier_uri(prefixes, name, "cql");
+            modifiers.push(Modifier::new(name, None, None).with_uri(uri));
+        }
+        let (first, last) = match self.take("position.", &POSITIONS, &mut attributes) {
+            Some(name) if name.eq_ignore_ascii_case("first") => (true, false),
+            Some(name) if name.eq_ignore_ascii_case("last") => (false, true),
+            Some(name) if name.eq_ignore_ascii_case("firstAndLast") => (true, true),
+            _ => (false, false),
+        };
+        if let Some(name) = self.take("structure.", &[], &mut attributes) {
+            if name != "*" && self.get(&format!("relation.{}", name)).is_some() {
+                relation = relation_symbol(name);
+            }
+        }
+        let truncation = self.take("truncation.", &TRUNCATIONS, &mut attributes);
+        if let Some(a) = attributes.first() {
+            return Err(unsupported(a));
+        }
+        let text = match &at.term {
+            RpnTerm::General(bytes) => String::from_utf8(bytes.clone()).map_err(|e| {
+                PqfError::UnsupportedTerm(String::from_utf8_lossy(e.as_bytes()).into_owned())
+            })?,
+            RpnTerm::CharacterString(s) => s.clone(),
+            RpnTerm::Numeric(n) => n.to_string(),
+            RpnTerm::Null => return Err(PqfError::UnsupportedTerm(String::from("null"))),
+        };
+        let term = cql_term(&text, truncation, first, last)?;
+        let st = CqlNode::mk_sc(index, &relation, Some(&term), modifiers);
+        Ok(resolve(prefixes, st, index, &relation))
+    }
+
+    /// Modifiers of `prox` for a proximity operator, leaving out the
+    /// defaults of unordered words at a distance of at most one.
+    fn prox_modifiers(
+        prefixes: &[Prefix],
+        prox: &ProximityOperator,
+    ) -> Result<Vec<Modifier>, PqfError> {
+        let unsupported = |what: String| PqfError::UnsupportedOperator(format!("prox {}", what));
+        if prox.exclusion == Some(true) {
+            return Err(unsupported(String::from("exclusion")));
+        }
+        let modifier = |name: &str, relation: Option<&str>, value: Option<&str>| {
+            Modifier::new(name, relation, value).with_uri(modifier_uri(prefixes, name, "cql"))
+        };
+        let mut res = Vec::new();
+        let unit = match prox.unit {
+            ProximityUnit::Known(2) => None,
+            ProximityUnit::Known(1) => Some("character"),
+            ProximityUnit::Known(3) => Some("sentence"),
+            ProximityUnit::Known(4) => Some("paragraph"),
+            ProximityUnit::Known(8) => Some("element"),
+            ProximityUnit::Known(n) => return Err(unsupported(format!("unit {}", n))),
+            ProximityUnit::Private(n) => return Err(unsupported(format!("private unit {}", n))),
+        };
+        if let Some(unit) = unit {
+            res.push(modifier("unit", Some("="), Some(unit)));
+        }
+        if prox.distance != 1 || prox.relation_type != 2 {
+            let relation = match prox.relation_type {
+                1 => "<",
+                2 => "<=",
+                3 => "=",
+                4 => ">=",
+                5 => ">",
+                6 => "<>",
+                n => return Err(unsupported(format!("relation {}", n))),
+            };
+            let distance = prox.distance.to_string();
+            res.push(modifier("distance", Some(relation), Some(&distance)));
+        }
+        if prox.ordered {
+            res.push(modifier("ordered", None, None));
+        }
+        Ok(res)
+    }
+
+    fn rpn(&self, prefixes: &[Prefix], rpn: &RpnStructure) -> Result<CqlNode, PqfError> {
+        match rpn {
+            RpnStructure::Operand(Operand::AttrTerm(at)) => {
+                Ok(CqlNode::St(self.attr_term(prefixes, at)?))
+            }
+            RpnStructure::Operand(Operand::ResultSet(id)) => {
+                let mut term = String::new();
+                id.chars().for_each(|ch| escape(ch, &mut term));
+                let (index, relation) = ("cql.resultSetId", "=");
+                let st = CqlNode::mk_sc(index, relation, Some(&term), Vec::new());
+                Ok(CqlNode::St(resolve(prefixes, st, index, relation)))
+            }
+            RpnStructure::RpnOp { left, right, op } => {
+                let (value, modifiers) = match op {
+                    Operator::And => ("and", Vec::new()),
+                    Operator::Or => ("or", Vec::new()),
+                    Operator::AndNot => ("not", Vec::new()),
+                    Operator::Prox(prox) => ("prox", PqfTransform::prox_modifiers(prefixes, prox)?),
+                };
+                let left = self.rpn(prefixes, left)?;
+                let right = self.rpn(prefixes, right)?;
+                Ok(CqlNode::mk_boolean(
+                    value,
+                    Box::new(left),
+                    Box::new(right),
+                    modifiers,
+                ))
+            }
+        }
+    }
+
+    /// Convert a Type-1 query with the Bib-1 attribute set to CQL.
+    ///
+    /// Context set URIs are filled in from the `set.*` properties, but
+    /// the tree has no prefix assignments.
+    pub fn rpn_to_cql(&self, query: &RpnQuery) -> Result<CqlNode, PqfError> {
+        if query.attribute_set != BIB1 {
+            let set: Vec<String> = query.attribute_set.iter().map(u32::to_string).collect();
+            return Err(PqfError::UnsupportedAttribute(format!(
+                "set {}",
+                set.join(".")
+            )));
+        }
+        let search = self.rpn(&self.prefixes(), &query.rpn)?;
+        Ok(CqlNode::mk_root(Box::new(search), Vec::new()))
+    }
+
+    /// Parse PQF and convert it to CQL.
+    pub fn pqf_to_cql(&self, pqf: &str) -> Result<CqlNode, PqfError> {
+        self.rpn_to_cql(&pqf.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::PROPERTIES;
+    use super::*;
+    use crate::node::CQL_CONTEXT_SET;
+
+    fn transform() -> PqfTransform {
+        PROPERTIES.parse().unwrap()
+    }
+
+    /// CQL to RPN and back to CQL.
+    fn round_trip(query: &str) -> String {
+        let transform = transform();
+        let rpn = transform.to_rpn(&crate::parse(query).unwrap()).unwrap();
+        transform.rpn_to_cql(&rpn).unwrap().to_cql()
+    }
+
+    fn cql(pqf: &str) -> Result<String, PqfError> {
+        Ok(transform().pqf_to_cql(pqf)?.to_cql())
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(round_trip("house"), "house");
+        assert_eq!(round_trip("dc.title = house"), "dc.title = house");
+        assert_eq!(round_trip("title == \"a b\""), "dc.title == \"a b\"");
+        assert_eq!(round_trip("dc.date <= 1990"), "dc.date <= 1990");
+        assert_eq!(round_trip("dc.title =/stem x"), "dc.title =/stem x");
+        assert_eq!(round_trip("dc.title = ^a*"), "dc.title = ^a*");
+        assert_eq!(round_trip("dc.title = \"a b^\""), "dc.title = \"a b^\"");
+        assert_eq!(round_trip("dc.title = a*b?"), "dc.title = a*b?");
+        assert_eq!(round_trip("dc.title = a\\*b"), "dc.title = a\\*b");
+        assert_eq!(round_trip("a and (b or c) not d"), "a and (b or c) not d");
+        assert_eq!(
+            round_trip("a prox/unit=sentence/distance<3/ordered b"),
+            "a prox/unit=sentence/distance<3/ordered b"
+        );
+    }
+
+    #[test]
+    fn context_sets() {
+        let node = transform().pqf_to_cql("@attr 1=4 x").unwrap();
+        let CqlNode::Root(root) = node else { panic!() };
+        let CqlNode::St(st) = root.search() else {
+            panic!()
+        };
+        assert_eq!(st.index_uri(), Some("info:srw/cql-context-set/1/dc-v1.1"));
+        assert_eq!(st.relation_uri(), Some(CQL_CONTEXT_SET));
+    }
+
+    #[test]
+    fn from_pqf() {
+        assert_eq!(
+            cql("@attr 1=4 @attr 5=1 hobbit").unwrap(),
+            "dc.title = hobbit*"
+        );
+        assert_eq!(cql("@attr 1=1003 \"a\\\\\"").unwrap(), "dc.creator = a\\\\");
+        assert_eq!(cql("@set s1").unwrap(), "cql.resultSetId = s1");
+        assert_eq!(
+            cql("@or @attr 1=4 a @attr 2=2 b").unwrap(),
+            "dc.title = a or cql.serverChoice <= b"
+        );
+        assert_eq!(cql("@attr 2=101 x").unwrap(), "cql.serverChoice =/stem x");
+        assert_eq!(cql("@term numeric 5").unwrap(), "5");
+
+        let mut transform = transform();
+        transform.set("truncation.regexp", "5=102");
+        let node = transform.pqf_to_cql("@attr 5=102 \"a.*b.c\\\\.\"").unwrap();
+        assert_eq!(node.to_cql(), "a*b?c.");
+        let e = transform.pqf_to_cql("@attr 5=102 \"a+\"").unwrap_err();
+        assert_eq!(e, PqfError::UnsupportedTerm(String::from("a+")));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            cql("@attr 1=9999 a"),
+            Err(PqfError::UnsupportedAttribute(String::from("@attr 1=9999")))
+        );
+        assert_eq!(
+            cql("@attr 5=101 a").unwrap_err().to_string(),
+            "no mapping for attribute @attr 5=101"
+        );
+        assert_eq!(
+            cql("@attr 1=4 @attr 1=1003 a"),
+            Err(PqfError::UnsupportedAttribute(String::from("@attr 1=1003")))
+        );
+        assert_eq!(
+            cql("@attr 1.2.3 1=4 a"),
+            Err(PqfError::UnsupportedAttribute(String::from(
+                "@attr 1.2.3 1=4"
+            )))
+        );
+        assert_eq!(
+            cql("@attrset 1.2.3 a"),
+            Err(PqfError::UnsupportedAttribute(String::from("set 1.2.3")))
+        );
+        assert_eq!(
+            cql("@prox 1 1 0 2 k 2 a b"),
+            Err(PqfError::UnsupportedOperator(String::from(
+                "prox exclusion"
+            )))
+        );
+        assert_eq!(
+            cql("@prox 0 1 0 2 p 7 a b"),
+            Err(PqfError::UnsupportedOperator(String::from(
+                "prox private unit 7"
+            )))
+        );
+        assert_eq!(
+            cql("@term null x"),
+            Err(PqfError::UnsupportedTerm(String::from("null")))
+        );
+        assert_matches!(cql("@and a"), Err(PqfError::Syntax { offset: 6, .. }));
+    }
+}
@@ -0,0 +1,900 @@
+//! Z39.50 Type-1 queries (RPN) and their BER encoding.
+//!
+//! The types follow the `RPNQuery` definitions of Z39.50-1995. A query
+//! is encoded as the `type-1` alternative of `Query`, ready to go into a
+//! searchRequest APDU. [`PqfTransform::to_rpn`](crate::pqf::PqfTransform::to_rpn)
+//! builds queries from CQL, and `Display` writes them as PQF.
+
+use std::fmt;
+
+/// Object identifier of the Bib-1 attribute set.
+pub const BIB1: &[u32] = &[1, 2, 840, 10003, 3, 1];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RpnQuery {
+    pub attribute_set: Vec<u32>,
+    pub rpn: RpnStructure,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RpnStructure {
+    Operand(Operand),
+    RpnOp {
+        left: Box<RpnStructure>,
+        right: Box<RpnStructure>,
+        op: Operator,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    AttrTerm(AttributesPlusTerm),
+    ResultSet(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AttributesPlusTerm {
+    pub attributes: Vec<AttributeElement>,
+    pub term: Term,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AttributeElement {
+    pub attribute_set: Option<Vec<u32>>,
+    pub attribute_type: i64,
+    pub value: AttributeValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AttributeValue {
+    Numeric(i64),
+    /// The list of a complex value, without semantic actions.
+    Complex(Vec<StringOrNumeric>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StringOrNumeric {
+    String(String),
+    Numeric(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    General(Vec<u8>),
+    Numeric(i64),
+    CharacterString(String),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operator {
+    And,
+    Or,
+    AndNot,
+    Prox(ProximityOperator),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProximityOperator {
+    pub exclusion: Option<bool>,
+    pub distance: i64,
+    pub ordered: bool,
+    /// 1 to 6 for `<`, `<=`, `=`, `>=`, `>` and `<>`.
+    pub relation_type: i64,
+    pub unit: ProximityUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProximityUnit {
+    /// A KnownProximityUnit, such as 2 for word.
+    Known(i64),
+    Private(i64),
+}
+
+impl AttributeElement {
+    /// Attribute `attribute_type=value` from the default attribute set.
+    pub fn numeric(attribute_type: i64, value: i64) -> AttributeElement {
+        AttributeElement {
+            attribute_set: None,
+            attribute_type,
+            value: AttributeValue::Numeric(value),
+        }
+    }
+}
+
+/// Error from decoding BER, with the offset of the offending element.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BerError {
+    message: String,
+    offset: usize,
+}
+
+impl BerError {
+    fn new(message: &str, offset: usize) -> BerError {
+        BerError {
+            message: String::from(message),
+            offset,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for BerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for BerError {}
+
+const UNIVERSAL: u8 = 0x00;
+const CONTEXT: u8 = 0x80;
+
+const OBJECT_IDENTIFIER: u32 = 6;
+const SEQUENCE: u32 = 16;
+
+fn put_tag(out: &mut Vec<u8>, class: u8, constructed: bool, number: u32) {
+    let first = class | if constructed { 0x20 } else { 0 };
+    if number < 31 {
+        out.push(first | number as u8);
+        return;
+    }
+    out.push(first | 0x1f);
+    put_base128(out, number);
+}
+
+fn put_base128(out: &mut Vec<u8>, value: u32) {
+    let mut shift = (32 - value.leading_zeros()).div_ceil(7).max(1) * 7;
+    while shift > 7 {
+        shift -= 7;
+        out.push(0x80 | ((value >> shift) & 0x7f) as u8);
+    }
+    out.push((value & 0x7f) as u8);
+}
+
+fn put_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+        return;
+    }
+    let bytes = len.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    out.push(0x80 | (bytes.len() - skip) as u8);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn primitive(out: &mut Vec<u8>, class: u8, number: u32, content: &[u8]) {
+    put_tag(out, class, false, number);
+    put_length(out, content.len());
+    out.extend_from_slice(content);
+}
+
+fn constructed(out: &mut Vec<u8>, class: u8, number: u32, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut content = Vec::new();
+    f(&mut content);
+    put_tag(out, class, true, number);
+    put_length(out, content.len());
+    out.extend_from_slice(&content);
+}
+
+fn integer_content(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut skip = 0;
+    // drop leading bytes that only repeat the sign bit
+    while skip < 7 {
+        let (b, next) = (bytes[skip], bytes[skip + 1]);
+        if (b == 0 && next & 0x80 == 0) || (b == 0xff && next & 0x80 != 0) {
+            skip += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[skip..].to_vec()
+}
+
+fn oid_content(oid: &[u32]) -> Vec<u8> {
+    let mut res = Vec::new();
+    if oid.len() >= 2 {
+        put_base128(&mut res, oid[0] * 40 + oid[1]);
+    }
+    for arc in oid.iter().skip(2) {
+        put_base128(&mut res, *arc);
+    }
+    res
+}
+
+fn integer(out: &mut Vec<u8>, class: u8, number: u32, value: i64) {
+    primitive(out, class, number, &integer_content(value));
+}
+
+fn boolean(out: &mut Vec<u8>, number: u32, value: bool) {
+    primitive(out, CONTEXT, number, &[if value { 0xff } else { 0 }]);
+}
+
+impl RpnQuery {
+    /// Query on `rpn` with the Bib-1 attribute set.
+    pub fn new(rpn: RpnStructure) -> RpnQuery {
+        RpnQuery {
+            attribute_set: BIB1.to_vec(),
+            rpn,
+        }
+    }
+
+    /// Encode as `Query`, that is `[1] IMPLICIT RPNQuery`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        constructed(&mut out, CONTEXT, 1, |out| {
+            primitive(
+                out,
+                UNIVERSAL,
+                OBJECT_IDENTIFIER,
+                &oid_content(&self.attribute_set),
+            );
+            self.rpn.encode(out);
+        });
+        out
+    }
+
+    /// Decode a `Query` holding a type-1 query.
+    pub fn decode(data: &[u8]) -> Result<RpnQuery, BerError> {
+        let mut reader = Reader::new(data, 0);
+        let query = reader.element()?;
+        reader.finish()?;
+        let mut reader = query.expect(CONTEXT, 1, "type-1 query")?;
+        let oid = reader.element()?;
+        let attribute_set = oid.expect_primitive(UNIVERSAL, OBJECT_IDENTIFIER, "attribute set")?;
+        let attribute_set = decode_oid(attribute_set, oid.offset)?;
+        let rpn = RpnStructure::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(RpnQuery { attribute_set, rpn })
+    }
+}
+
+impl RpnStructure {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            RpnStructure::Operand(operand) => {
+                constructed(out, CONTEXT, 0, |out| operand.encode(out));
+            }
+            RpnStructure::RpnOp { left, right, op } => {
+                constructed(out, CONTEXT, 1, |out| {
+                    left.encode(out);
+                    right.encode(out);
+                    op.encode(out);
+                });
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<RpnStructure, BerError> {
+        let e = reader.element()?;
+        match (e.class, e.number) {
+            (CONTEXT, 0) => {
+                let mut inner = e.expect(CONTEXT, 0, "operand")?;
+                let operand = Operand::decode(&mut inner)?;
+                inner.finish()?;
+                Ok(RpnStructure::Operand(operand))
+            }
+            (CONTEXT, 1) => {
+                let mut inner = e.expect(CONTEXT, 1, "rpnRpnOp")?;
+                let left = RpnStructure::decode(&mut inner)?;
+                let right = RpnStructure::decode(&mut inner)?;
+                let op = Operator::decode(&mut inner)?;
+                inner.finish()?;
+                Ok(RpnStructure::RpnOp {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    op,
+                })
+            }
+            _ => Err(BerError::new("expected RPN structure", e.offset)),
+        }
+    }
+}
+
+impl Operand {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Operand::AttrTerm(at) => {
+                constructed(out, CONTEXT, 102, |out| {
+                    constructed(out, CONTEXT, 44, |out| {
+                        for attr in &at.attributes {
+                            attr.encode(out);
+                        }
+                    });
+                    at.term.encode(out);
+                });
+            }
+            Operand::ResultSet(id) => primitive(out, CONTEXT, 31, id.as_bytes()),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Operand, BerError> {
+        let e = reader.element()?;
+        match (e.class, e.number) {
+            (CONTEXT, 102) => {
+                let mut inner = e.expect(CONTEXT, 102, "attributes plus term")?;
+                let list = inner.element()?;
+                let mut list = list.expect(CONTEXT, 44, "attribute list")?;
+                let mut attributes = Vec::new();
+                while !list.at_end() {
+                    attributes.push(AttributeElement::decode(&mut list)?);
+                }
+                let term = Term::decode(&mut inner)?;
+                inner.finish()?;
+                Ok(Operand::AttrTerm(AttributesPlusTerm { attributes, term }))
+            }
+            (CONTEXT, 31) => {
+                let id = e.expect_primitive(CONTEXT, 31, "result set")?;
+                Ok(Operand::ResultSet(decode_string(id, e.offset)?))
+            }
+            _ => Err(BerError::new("expected operand", e.offset)),
+        }
+    }
+}
+
+impl AttributeElement {
+    fn encode(&self, out: &mut Vec<u8>) {
+        constructed(out, UNIVERSAL, SEQUENCE, |out| {
+            if let Some(set) = &self.attribute_set {
+                primitive(out, CONTEXT, 1, &oid_content(set));
+            }
+            integer(out, CONTEXT, 120, self.attribute_type);
+            match &self.value {
+                AttributeValue::Numeric(value) => integer(out, CONTEXT, 121, *value),
+                AttributeValue::Complex(list) => {
+                    constructed(out, CONTEXT, 224, |out| {
+                        constructed(out, CONTEXT, 1, |out| {
+                            for item in list {
+                                match item {
+                                    StringOrNumeric::String(s) => {
+                                        primitive(out, CONTEXT, 1, s.as_bytes())
+                                    }
+                                    StringOrNumeric::Numeric(n) => integer(out, CONTEXT, 2, *n),
+                                }
+                            }
+                        });
+                    });
+                }
+            }
+        });
+    }
+
+    fn decode(reader: &mut Reader) -> Result<AttributeElement, BerError> {
+        let e = reader.element()?;
+        let mut inner = e.expect(UNIVERSAL, SEQUENCE, "attribute element")?;
+        let mut e = inner.element()?;
+        let attribute_set = if (e.class, e.number) == (CONTEXT, 1) {
+            let set = e.expect_primitive(CONTEXT, 1, "attribute set")?;
+            let set = decode_oid(set, e.offset)?;
+            e = inner.element()?;
+            Some(set)
+        } else {
+            None
+        };
+        let attribute_type = decode_integer(
+            e.expect_primitive(CONTEXT, 120, "attribute type")?,
+            e.offset,
+        )?;
+        let e = inner.element()?;
+        let value = match (e.class, e.number) {
+            (CONTEXT, 121) => {
+                let value = e.expect_primitive(CONTEXT, 121, "attribute value")?;
+                AttributeValue::Numeric(decode_integer(value, e.offset)?)
+            }
+            (CONTEXT, 224) => {
+                let mut complex = e.expect(CONTEXT, 224, "complex attribute value")?;
+                let list = complex.element()?;
+                let mut list = list.expect(CONTEXT, 1, "complex attribute list")?;
+                let mut items = Vec::new();
+                while !list.at_end() {
+                    let e = list.element()?;
+                    let item = match (e.class, e.number) {
+                        (CONTEXT, 1) => {
+                            let s = e.expect_primitive(CONTEXT, 1, "string")?;
+                            StringOrNumeric::String(decode_string(s, e.offset)?)
+                        }
+                        _ => {
+                            let n = e.expect_primitive(CONTEXT, 2, "string or numeric")?;
+                            StringOrNumeric::Numeric(decode_integer(n, e.offset)?)
+                        }
+                    };
+                    items.push(item);
+                }
+                // semantic actions are not kept
+                AttributeValue::Complex(items)
+            }
+            _ => return Err(BerError::new("expected attribute value", e.offset)),
+        };
+        inner.finish()?;
+        Ok(AttributeElement {
+            attribute_set,
+            attribute_type,
+            value,
+        })
+    }
+}
+
+impl Term {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Term::General(bytes) => primitive(out, CONTEXT, 45, bytes),
+            Term::Numeric(n) => integer(out, CONTEXT, 215, *n),
+            Term::CharacterString(s) => primitive(out, CONTEXT, 216, s.as_bytes()),
+            Term::Null => primitive(out, CONTEXT, 221, &[]),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Term, BerError> {
+        let e = reader.element()?;
+        match (e.class, e.number) {
+            (CONTEXT, 45) => Ok(Term::General(
+                e.expect_primitive(CONTEXT, 45, "term")?.to_vec(),
+            )),
+            (CONTEXT, 215) => {
+                let n = e.expect_primitive(CONTEXT, 215, "term")?;
+                Ok(Term::Numeric(decode_integer(n, e.offset)?))
+            }
+            (CONTEXT, 216) => {
+                let s = e.expect_primitive(CONTEXT, 216, "term")?;
+                Ok(Term::CharacterString(decode_string(s, e.offset)?))
+            }
+            (CONTEXT, 221) => Ok(Term::Null),
+            _ => Err(BerError::new("unsupported term type", e.offset)),
+        }
+    }
+}
+
+impl Operator {
+    fn encode(&self, out: &mut Vec<u8>) {
+        constructed(out, CONTEXT, 46, |out| match self {
+            Operator::And => primitive(out, CONTEXT, 0, &[]),
+            Operator::Or => primitive(out, CONTEXT, 1, &[]),
+            Operator::AndNot => primitive(out, CONTEXT, 2, &[]),
+            Operator::Prox(prox) => {
+                constructed(out, CONTEXT, 3, |out| {
+                    if let Some(exclusion) = prox.exclusion {
+                        boolean(out, 1, exclusion);
+                    }
+                    integer(out, CONTEXT, 2, prox.distance);
+                    boolean(out, 3, prox.ordered);
+                    integer(out, CONTEXT, 4, prox.relation_type);
+                    constructed(out, CONTEXT, 5, |out| match prox.unit {
+                        ProximityUnit::Known(n) => integer(out, CONTEXT, 1, n),
+                        ProximityUnit::Private(n) => integer(out, CONTEXT, 2, n),
+                    });
+                });
+            }
+        });
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Operator, BerError> {
+        let e = reader.element()?;
+        let mut inner = e.expect(CONTEXT, 46, "operator")?;
+        let e = inner.element()?;
+        let op = match (e.class, e.number, e.constructed) {
+            (CONTEXT, 0, false) => Operator::And,
+            (CONTEXT, 1, false) => Operator::Or,
+            (CONTEXT, 2, false) => Operator::AndNot,
+            (CONTEXT, 3, true) => {
+                let mut prox = e.expect(CONTEXT, 3, "proximity operator")?;
+                let mut e = prox.element()?;
+                let exclusion = if (e.class, e.number) == (CONTEXT, 1) {
+                    let b = e.expect_primitive(CONTEXT, 1, "exclusion")?;
+                    let exclusion = decode_boolean(b, e.offset)?;
+                    e = prox.element()?;
+                    Some(exclusion)
+                } else {
+                    None
+                };
+                let distance =
+                    decode_integer(e.expect_primitive(CONTEXT, 2, "distance")?, e.offset)?;
+                let e = prox.element()?;
+                let ordered = decode_boolean(e.expect_primitive(CONTEXT, 3, "ordered")?, e.offset)?;
+                let e = prox.element()?;
+                let relation_type =
+                    decode_integer(e.expect_primitive(CONTEXT, 4, "relation type")?, e.offset)?;
+                let e = prox.element()?;
+                let mut code = e.expect(CONTEXT, 5, "proximity unit code")?;
+                let e = code.element()?;
+                let unit = match e.number {
+                    1 => ProximityUnit::Known(decode_integer(
+                        e.expect_primitive(CONTEXT, 1, "known unit")?,
+                        e.offset,
+                    )?),
+                    _ => ProximityUnit::Private(decode_integer(
+                        e.expect_primitive(CONTEXT, 2, "private unit")?,
+                        e.offset,
+                    )?),
+                };
+                code.finish()?;
+                prox.finish()?;
+                Operator::Prox(ProximityOperator {
+                    exclusion,
+                    distance,
+                    ordered,
+                    relation_type,
+                    unit,
+                })
+            }
+            _ => return Err(BerError::new("unknown operator", e.offset)),
+        };
+        inner.finish()?;
+        Ok(op)
+    }
+}
+
+/// One BER element, with the offset of its tag in the whole input.
+struct Element<'a> {
+    class: u8,
+    constructed: bool,
+    number: u32,
+    content: &'a [u8],
+    offset: usize,
+    content_offset: usize,
+}
+
+impl<'a> Element<'a> {
+    fn check(&self, class: u8, number: u32, constructed: bool, what: &str) -> Result<(), BerError> {
+        if (self.class, self.number, self.constructed) != (class, number, constructed) {
+            return Err(BerError::new(&format!("expected {}", what), self.offset));
+        }
+        Ok(())
+    }
+
+    /// The contents of a constructed element, to read its elements from.
+    fn expect(&self, class: u8, number: u32, what: &str) -> Result<Reader<'a>, BerError> {
+        self.check(class, number, true, what)?;
+        Ok(Reader::new(self.content, self.content_offset))
+    }
+
+    fn expect_primitive(&self, class: u8, number: u32, what: &str) -> Result<&'a [u8], BerError> {
+        self.check(class, number, false, what)?;
+        Ok(self.content)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], base: usize) -> Reader<'a> {
+        Reader { data, pos: 0, base }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, BerError> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| BerError::new("unexpected end of data", self.base + self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn element(&mut self) -> Result<Element<'a>, BerError> {
+        let offset = self.base + self.pos;
+        let first = self.byte()?;
+        let mut number = u32::from(first & 0x1f);
+        if number == 0x1f {
+            number = 0;
+            loop {
+                let b = self.byte()?;
+                if number > u32::MAX >> 7 {
+                    return Err(BerError::new("tag number too large", offset));
+                }
+                number = (number << 7) | u32::from(b & 0x7f);
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+        }
+        let b = self.byte()?;
+        let len = if b < 0x80 {
+            usize::from(b)
+        } else if b == 0x80 {
+            return Err(BerError::new("indefinite length not supported", offset));
+        } else {
+            let mut len: usize = 0;
+            for _ in 0..(b & 0x7f) {
+                if len > usize::MAX >> 8 {
+                    return Err(BerError::new("length too large", offset));
+                }
+                len = (len << 8) | usize::from(self.byte()?);
+            }
+            len
+        };
+        let start = self.pos;
+        if len > self.data.len() - start {
+            return Err(BerError::new("length exceeds data", offset));
+        }
+        self.pos += len;
+        Ok(Element {
+            class: first & 0xc0,
+            constructed: first & 0x20 != 0,
+            number,
+            content: &self.data[start..start + len],
+            offset,
+            content_offset: self.base + start,
+        })
+    }
+
+    fn finish(&self) -> Result<(), BerError> {
+        if !self.at_end() {
+            return Err(BerError::new("unexpected element", self.base + self.pos));
+        }
+        Ok(())
+    }
+}
+
+fn decode_integer(content: &[u8], offset: usize) -> Result<i64, BerError> {
+    if content.is_empty() || content.len() > 8 {
+        return Err(BerError::new("invalid integer", offset));
+    }
+    let mut value = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    for b in content {
+        value = (value << 8) | i64::from(*b);
+    }
+    Ok(value)
+}
+
+fn decode_boolean(content: &[u8], offset: usize) -> Result<bool, BerError> {
+    match content {
+        [b] => Ok(*b != 0),
+        _ => Err(BerError::new("invalid boolean", offset)),
+    }
+}
+
+fn decode_string(content: &[u8], offset: usize) -> Result<String, BerError> {
+    String::from_utf8(content.to_vec()).map_err(|_| BerError::new("invalid UTF-8 string", offset))
+}
+
+fn decode_oid(content: &[u8], offset: usize) -> Result<Vec<u32>, BerError> {
+    let mut arcs = Vec::new();
+    let mut value: u32 = 0;
+    for b in content {
+        if value > u32::MAX >> 7 {
+            return Err(BerError::new("invalid object identifier", offset));
+        }
+        value = (value << 7) | u32::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    if arcs.is_empty() || content.last().is_some_and(|b| b & 0x80 != 0) {
+        return Err(BerError::new("invalid object identifier", offset));
+    }
+    Ok(arcs)
+}
+
+fn write_pqf_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for ch in s.chars() {
+        if ch == '"' || ch == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", ch)?;
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for AttributeElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("@attr ")?;
+        if let Some(set) = &self.attribute_set {
+            let set: Vec<String> = set.iter().map(u32::to_string).collect();
+            write!(f, "{} ", set.join("."))?;
+        }
+        write!(f, "{}=", self.attribute_type)?;
+        match &self.value {
+            AttributeValue::Numeric(n) => write!(f, "{}", n),
+            AttributeValue::Complex(list) => {
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    match item {
+                        StringOrNumeric::String(s) => f.write_str(s)?,
+                        StringOrNumeric::Numeric(n) => write!(f, "{}", n)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for RpnStructure {
+    /// Write as PQF, without the attribute set.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpnStructure::Operand(Operand::AttrTerm(at)) => {
+                for attr in &at.attributes {
+                    write!(f, "{} ", attr)?;
+                }
+                match &at.term {
+                    Term::General(bytes) => write_pqf_string(f, &String::from_utf8_lossy(bytes)),
+                    Term::CharacterString(s) => write_pqf_string(f, s),
+                    Term::Numeric(n) => write!(f, "@term numeric {}", n),
+                    Term::Null => f.write_str("@term null \"\""),
+                }
+            }
+            RpnStructure::Operand(Operand::ResultSet(id)) => {
+                f.write_str("@set ")?;
+                write_pqf_string(f, id)
+            }
+            RpnStructure::RpnOp { left, right, op } => {
+                match op {
+                    Operator::And => f.write_str("@and ")?,
+                    Operator::Or => f.write_str("@or ")?,
+                    Operator::AndNot => f.write_str("@not ")?,
+                    Operator::Prox(prox) => {
+                        let unit = match prox.unit {
+                            ProximityUnit::Known(n) => format!("k {}", n),
+                            ProximityUnit::Private(n) => format!("p {}", n),
+                        };
+                        write!(
+                            f,
+                            "@prox {} {} {} {} {} ",
+                            u8::from(prox.exclusion.unwrap_or(false)),
+                            prox.distance,
+                            u8::from(prox.ordered),
+                            prox.relation_type,
+                            unit
+                        )?;
+                    }
+                }
+                write!(f, "{} {}", left, right)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RpnQuery {
+    /// Write as PQF, with `@attrset` unless the attribute set is Bib-1.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.attribute_set != BIB1 {
+            let set: Vec<String> = self.attribute_set.iter().map(u32::to_string).collect();
+            write!(f, "@attrset {} ", set.join("."))?;
+        }
+        write!(f, "{}", self.rpn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn title(term: &str) -> RpnStructure {
+        RpnStructure::Operand(Operand::AttrTerm(AttributesPlusTerm {
+            attributes: vec![AttributeElement::numeric(1, 4)],
+            term: Term::General(term.as_bytes().to_vec()),
+        }))
+    }
+
+    #[test]
+    fn encode_operand() {
+        let query = RpnQuery::new(title("a"));
+        let expected = [
+            0xa1, 0x1f, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x13, 0x03, 0x01, 0xa0, 0x14, 0xbf,
+            0x66, 0x11, 0xbf, 0x2c, 0x0a, 0x30, 0x08, 0x9f, 0x78, 0x01, 0x01, 0x9f, 0x79, 0x01,
+            0x04, 0x9f, 0x2d, 0x01, 0x61,
+        ];
+        assert_eq!(query.encode(), expected);
+        assert_eq!(RpnQuery::decode(&expected), Ok(query));
+    }
+
+    #[test]
+    fn encode_prox() {
+        let op = Operator::Prox(ProximityOperator {
+            exclusion: Some(false),
+            distance: 1,
+            ordered: false,
+            relation_type: 2,
+            unit: ProximityUnit::Known(2),
+        });
+        let mut out = Vec::new();
+        op.encode(&mut out);
+        let expected = [
+            0xbf, 0x2e, 0x13, 0xa3, 0x11, 0x81, 0x01, 0x00, 0x82, 0x01, 0x01, 0x83, 0x01, 0x00,
+            0x84, 0x01, 0x02, 0xa5, 0x03, 0x81, 0x01, 0x02,
+        ];
+        assert_eq!(out, expected);
+        assert_eq!(Operator::decode(&mut Reader::new(&expected, 0)), Ok(op));
+    }
+
+    #[test]
+    fn round_trip() {
+        let long = "x".repeat(300);
+        let complex = AttributeElement {
+            attribute_set: Some(vec![1, 2, 840, 10003, 3, 2]),
+            attribute_type: 1,
+            value: AttributeValue::Complex(vec![
+                StringOrNumeric::String(String::from("title")),
+                StringOrNumeric::Numeric(-200),
+            ]),
+        };
+        let right = RpnStructure::Operand(Operand::AttrTerm(AttributesPlusTerm {
+            attributes: vec![complex, AttributeElement::numeric(2, 1000)],
+            term: Term::General(long.into_bytes()),
+        }));
+        let or = RpnStructure::RpnOp {
+            left: Box::new(RpnStructure::Operand(Operand::ResultSet(String::from(
+                "s1",
+            )))),
+            right: Box::new(right),
+            op: Operator::Or,
+        };
+        let rpn = RpnStructure::RpnOp {
+            left: Box::new(title("a")),
+            right: Box::new(or),
+            op: Operator::AndNot,
+        };
+        let query = RpnQuery::new(rpn);
+        assert_eq!(RpnQuery::decode(&query.encode()), Ok(query));
+    }
+
+    #[test]
+    fn decode_errors() {
+        let good = RpnQuery::new(title("a")).encode();
+        let e = RpnQuery::decode(&good[..good.len() - 1]).unwrap_err();
+        assert_eq!(e.to_string(), "length exceeds data at offset 0");
+
+        let mut bad = good.clone();
+        bad[22] = 0x77;
+        let e = RpnQuery::decode(&bad).unwrap_err();
+        assert_eq!(e.message(), "expected attribute type");
+        assert_eq!(e.offset(), 21);
+
+        let mut trailing = good;
+        trailing.push(0);
+        assert_eq!(RpnQuery::decode(&trailing).unwrap_err().offset(), 33);
+    }
+
+    #[test]
+    fn pqf() {
+        let prox = Operator::Prox(ProximityOperator {
+            exclusion: None,
+            distance: 2,
+            ordered: true,
+            relation_type: 3,
+            unit: ProximityUnit::Known(2),
+        });
+        let rpn = RpnStructure::RpnOp {
+            left: Box::new(title("a \"b\"")),
+            right: Box::new(RpnStructure::Operand(Operand::ResultSet(String::from(
+                "s1",
+            )))),
+            op: prox,
+        };
+        let mut query = RpnQuery::new(rpn);
+        assert_eq!(
+            query.to_string(),
+            "@prox 0 2 1 3 k 2 @attr 1=4 \"a \\\"b\\\"\" @set \"s1\""
+        );
+        query.attribute_set = vec![1, 2, 840, 10003, 3, 5];
+        assert!(query
+            .to_string()
+            .starts_with("@attrset 1.2.840.10003.3.5 @prox"));
+    }
+}
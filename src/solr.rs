@@ -0,0 +1,412 @@
+//! Translation of CQL to the Solr standard query parser.
+//!
+//! Indexes are mapped to Solr fields with [`SolrTransform::field`], and
+//! `cql.serverChoice` searches the fields given to
+//! [`SolrTransform::default_fields`], or Solr's default field if none.
+//! Relations translate as follows:
+//!
+//! | CQL                 | Solr                          |
+//! |---------------------|-------------------------------|
+//! | `ti = "a b"`        | `title:"a b"`                 |
+//! | `ti adj "a b"`      | `title:"a b"`                 |
+//! | `ti = a*`           | `title:a*`                    |
+//! | `ti == "a b"`       | `title:"a b"`                 |
+//! | `ti all "a b"`      | `title:(a AND b)`             |
+//! | `ti any "a b"`      | `title:(a OR b)`              |
+//! | `year < 2000`       | `year:{* TO 2000}`            |
+//! | `year >= 2000`      | `year:[2000 TO *]`            |
+//! | `ti <> a`           | `(*:* -title:a)`              |
+//! | `a not b`           | `a AND -b`                    |
+//!
+//! Masking with `*` and `?` becomes Solr wildcards in single words;
+//! phrases and ranges cannot be masked. Other Solr special characters are
+//! escaped with a backslash, as are words that Solr reads as the
+//! operators `AND`, `OR` and `NOT`. Sort keys are returned as the `sort`
+//! parameter, such as `date desc, title asc`.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::term::TermChar;
+use crate::translate::descending;
+use crate::translate::infix;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// Solr request parameters for a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SolrQuery {
+    /// The `q` parameter.
+    pub q: String,
+    /// The `sort` parameter, if the query has sort keys.
+    pub sort: Option<String>,
+}
+
+fn escape(ch: char, out: &mut String) {
+    if ch.is_whitespace() || "+-&|!(){}[]^\"~*?:\\/".contains(ch) {
+        out.push('\\');
+    }
+    out.push(ch);
+}
+
+/// A word, with masking as wildcards.
+fn word(chars: &[TermChar]) -> String {
+    let mut res = String::new();
+    for ch in chars {
+        match ch {
+            TermChar::Literal(ch) => escape(*ch, &mut res),
+            _ => res.push(ch.as_char()),
+        }
+    }
+    // the query parser reads these as operators, not words
+    if ["AND", "OR", "NOT"].contains(&res.as_str()) {
+        res.insert(0, '\\');
+    }
+    res
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SolrTransform {
+    fields: Mapping<String>,
+}
+
+impl SolrTransform {
+    pub fn new() -> SolrTransform {
+        SolrTransform::default()
+    }
+
+    /// Search `index` in Solr field `field`. `index` is matched as by
+    /// [`Mapping`].
+    pub fn field(&mut self, index: &str, field: &str) {
+        self.fields.insert(index, String::from(field));
+    }
+
+    /// Fields searched, with `OR`, for `cql.serverChoice` unless it is
+    /// mapped with [`SolrTransform::field`].
+    pub fn default_fields(&mut self, fields: &[&str]) {
+        self.fields
+            .server_choice(fields.iter().map(|f| String::from(*f)).collect());
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.fields.insert_relation(name, relation);
+    }
+
+    /// Fields to search for the index of `clause`; empty for Solr's
+    /// default field.
+    fn fields_of(&self, clause: &Clause<'_>) -> Result<Vec<&str>, TranslationError> {
+        let fields = self
+            .fields
+            .resolve(clause.index())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        Ok(fields.into_iter().map(String::as_str).collect())
+    }
+
+    /// The term as a quoted phrase, which cannot be masked.
+    fn phrase(clause: &Clause<'_>) -> Result<String, TranslationError> {
+        clause.unmasked()?;
+        let mut res = String::from("\"");
+        for ch in clause.term().text().chars() {
+            if ch == '"' || ch == '\\' {
+                res.push('\\');
+            }
+            res.push(ch);
+        }
+        res.push('"');
+        Ok(res)
+    }
+
+    /// The query on a single field, or the default field if `None`.
+    fn clause(
+        field: Option<&str>,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<String, TranslationError> {
+        let term = clause.term();
+        let words = term.words();
+        let prefix = match field {
+            Some(field) => format!("{}:", field),
+            None => String::new(),
+        };
+        let bound = || {
+            clause.unmasked()?;
+            if term.chars.is_empty() {
+                return Ok(String::from("\"\""));
+            }
+            Ok(word(&term.chars))
+        };
+        let res = match relation {
+            "=" | "adj" | "scr" if words.len() == 1 => format!("{}{}", prefix, word(words[0])),
+            "=" | "adj" | "scr" | "==" | "exact" => {
+                format!("{}{}", prefix, SolrTransform::phrase(clause)?)
+            }
+            relation @ ("all" | "any") => {
+                let words = clause.words()?;
+                let op = if relation == "all" { " AND " } else { " OR " };
+                let words: Vec<String> = words.iter().map(|w| word(w)).collect();
+                if words.len() == 1 {
+                    format!("{}{}", prefix, words[0])
+                } else {
+                    format!("{}({})", prefix, words.join(op))
+                }
+            }
+            "<" => format!("{}{{* TO {}}}", prefix, bound()?),
+            "<=" => format!("{}[* TO {}]", prefix, bound()?),
+            ">" => format!("{}{{{} TO *}}", prefix, bound()?),
+            ">=" => format!("{}[{} TO *]", prefix, bound()?),
+            "<>" => format!("(*:* -{}{})", prefix, bound()?),
+            _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        };
+        Ok(res)
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        clause.no_modifiers()?;
+        clause.unanchored()?;
+        let fields = self.fields_of(clause)?;
+        let relation = self.fields.relation(clause)?;
+        if fields.len() < 2 {
+            return SolrTransform::clause(fields.first().copied(), &relation, clause);
+        }
+        let clauses: Result<Vec<String>, TranslationError> = fields
+            .iter()
+            .map(|field| SolrTransform::clause(Some(field), &relation, clause))
+            .collect();
+        Ok(format!("({})", clauses?.join(" OR ")))
+    }
+
+    /// Translate a query to Solr `q` and `sort` parameters.
+    pub fn to_solr(&self, node: &CqlNode) -> Result<SolrQuery, TranslationError> {
+        let mut translator = Translator { transform: self };
+        let sort = translator.translate_sort(node)?;
+        Ok(SolrQuery {
+            q: translator.translate(node)?,
+            sort: (!sort.is_empty()).then(|| sort.join(", ")),
+        })
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a SolrTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        let op = match op {
+            BooleanOp::And => " AND ",
+            BooleanOp::Or => " OR ",
+            BooleanOp::Not => " AND -",
+            BooleanOp::Prox => return Err(TranslationError::boolean(bo)),
+        };
+        Ok(infix(operands, op))
+    }
+}
+
+impl SortTranslator for Translator<'_> {
+    type SortKey = String;
+
+    fn sort_key(&mut self, key: &St) -> Result<String, TranslationError> {
+        let field = self
+            .transform
+            .fields
+            .get(key.index())
+            .ok_or_else(|| TranslationError::index(key))?;
+        let mut direction = "asc";
+        for m in key.modifiers() {
+            direction = if descending(m)? { "desc" } else { "asc" };
+        }
+        Ok(format!("{} {}", field, direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform() -> SolrTransform {
+        let mut transform = SolrTransform::new();
+        transform.field("dc.title", "title");
+        transform.field("creator", "author_s");
+        transform.field("date", "year_i");
+        transform.relation("bib.contains", "any");
+        transform
+    }
+
+    fn q(query: &str) -> Result<String, TranslationError> {
+        let node = crate::parse(query).unwrap();
+        Ok(transform().to_solr(&node)?.q)
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(q("dc.title = house").unwrap(), "title:house");
+        assert_eq!(q("dc.title = \"a house\"").unwrap(), "title:\"a house\"");
+        assert_eq!(q("dc.title adj \"a house\"").unwrap(), "title:\"a house\"");
+        assert_eq!(q("dc.title == house").unwrap(), "title:\"house\"");
+        assert_eq!(q("dc.creator = smith").unwrap(), "author_s:smith");
+        assert_eq!(q("x.creator = smith").unwrap(), "author_s:smith");
+        assert_eq!(
+            q("dc.title all \"a b c\"").unwrap(),
+            "title:(a AND b AND c)"
+        );
+        assert_eq!(q("dc.title any \"a b\"").unwrap(), "title:(a OR b)");
+        assert_eq!(
+            q("dc.title bib.contains \"a b\"").unwrap(),
+            "title:(a OR b)"
+        );
+        assert_eq!(q("dc.title cql.any a").unwrap(), "title:a");
+        assert_eq!(q("date < 2000").unwrap(), "year_i:{* TO 2000}");
+        assert_eq!(q("date <= 2000").unwrap(), "year_i:[* TO 2000]");
+        assert_eq!(q("date > 2000").unwrap(), "year_i:{2000 TO *}");
+        assert_eq!(q("date >= 2000").unwrap(), "year_i:[2000 TO *]");
+        assert_eq!(q("date <> 2000").unwrap(), "(*:* -year_i:2000)");
+        assert_eq!(q("date > \"\"").unwrap(), "year_i:{\"\" TO *}");
+    }
+
+    #[test]
+    fn escaping_and_masking() {
+        assert_eq!(q("dc.title = hou*e?").unwrap(), "title:hou*e?");
+        assert_eq!(q("dc.title = a\\*b").unwrap(), "title:a\\*b");
+        assert_eq!(q("dc.title = \"c++:x\"").unwrap(), "title:c\\+\\+\\:x");
+        assert_eq!(q("dc.title = \"1/2-3\"").unwrap(), "title:1\\/2\\-3");
+        assert_eq!(
+            q("dc.title = \"say \\\"hi\\\" \\\\ now\"").unwrap(),
+            "title:\"say \\\"hi\\\" \\\\ now\""
+        );
+        assert_eq!(
+            q("dc.title any \"a* (b)\"").unwrap(),
+            "title:(a* OR \\(b\\))"
+        );
+        assert_eq!(q("dc.title = AND").unwrap(), "title:\\AND");
+        assert_eq!(
+            q("dc.title any \"cats OR dogs\"").unwrap(),
+            "title:(cats OR \\OR OR dogs)"
+        );
+        assert_eq!(
+            q("dc.title all \"a NOT b\"").unwrap(),
+            "title:(a AND \\NOT AND b)"
+        );
+        assert_eq!(
+            q("dc.title all \"and Or NOT*\"").unwrap(),
+            "title:(and AND Or AND NOT*)"
+        );
+        assert_eq!(q("date < OR").unwrap(), "year_i:{* TO \\OR}");
+        assert_eq!(
+            q("dc.title = \"a* b\"").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a* b")
+        );
+        assert_eq!(
+            q("date < 20*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "20*")
+        );
+        assert_eq!(
+            q("dc.title = ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            q("dc.title = a and dc.creator = b").unwrap(),
+            "title:a AND author_s:b"
+        );
+        assert_eq!(
+            q("dc.title = a or (dc.title = b and dc.title = c)").unwrap(),
+            "title:a OR (title:b AND title:c)"
+        );
+        assert_eq!(
+            q("dc.title = a not dc.creator = b").unwrap(),
+            "title:a AND -author_s:b"
+        );
+        assert_eq!(
+            q("dc.title = a not (dc.title = b or dc.title = c)").unwrap(),
+            "title:a AND -(title:b OR title:c)"
+        );
+        assert_eq!(
+            q("dc.title = a prox dc.title = b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn server_choice() {
+        assert_eq!(q("house").unwrap(), "house");
+        assert_eq!(q("\"a house\"").unwrap(), "\"a house\"");
+        let mut transform = transform();
+        transform.default_fields(&["title", "text"]);
+        let node = crate::parse("a and \"b c\"").unwrap();
+        assert_eq!(
+            transform.to_solr(&node).unwrap().q,
+            "(title:a OR text:a) AND (title:\"b c\" OR text:\"b c\")"
+        );
+        transform.field("cql.serverChoice", "all");
+        let node = crate::parse("a").unwrap();
+        assert_eq!(transform.to_solr(&node).unwrap().q, "all:a");
+    }
+
+    #[test]
+    fn sort() {
+        let node = crate::parse("a sortby dc.date/sort.descending dc.title").unwrap();
+        assert_eq!(
+            transform().to_solr(&node).unwrap(),
+            SolrQuery {
+                q: String::from("a"),
+                sort: Some(String::from("year_i desc, title asc")),
+            }
+        );
+        let node = crate::parse("a sortby dc.title/missingValue=x").unwrap();
+        assert_eq!(
+            transform().to_solr(&node).unwrap_err().parts(),
+            (
+                TranslationErrorKind::UnsupportedSortModifier,
+                "missingValue"
+            )
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            q("dc.title all \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            q("dc.subject = x").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            q("dc.title cql.within x").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "cql.within")
+        );
+        assert_eq!(
+            q("dc.title =/stem x").unwrap_err().to_string(),
+            "unsupported relation modifier stem"
+        );
+        let mut parser = crate::parser::Parser::new();
+        let (node, _) = parser.parse_recovering(&mut "a and".chars());
+        assert_eq!(
+            transform().to_solr(&node).unwrap_err().kind(),
+            TranslationErrorKind::InvalidQuery
+        );
+    }
+}
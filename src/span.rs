@@ -0,0 +1,126 @@
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// A location in the query text.
+///
+/// `offset` counts characters and `byte` counts bytes from the start of
+/// the query. `line` and `column` are 1-based, with columns counted in
+/// characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub offset: usize,
+    pub byte: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            byte: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    /// Move past character `ch`.
+    pub(crate) fn advance(&mut self, ch: char) {
+        self.offset += 1;
+        self.byte += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+/// The part of the query text between two positions, `end` exclusive.
+///
+/// Nodes built programmatically rather than parsed have an empty span
+/// at the start of the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span { start, end }
+    }
+
+    /// The text covered by the span, if `query` is the text it was
+    /// parsed from.
+    pub fn source<'a>(&self, query: &'a str) -> Option<&'a str> {
+        query.get(self.start.byte..self.end.byte)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.offset == self.end.offset
+    }
+}
+
+/// Wrapper that is always equal to any other and hashes to nothing, so
+/// that tree equality compares what a query means, not where it was
+/// written.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Ignored<T>(pub(crate) T);
+
+impl<T> PartialEq for Ignored<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Ignored<T> {}
+
+impl<T> Hash for Ignored<T> {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+impl<T: fmt::Debug> fmt::Debug for Ignored<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance() {
+        let mut pos = Position::default();
+        for ch in "aé\nb".chars() {
+            pos.advance(ch);
+        }
+        assert_eq!(pos.offset, 4);
+        assert_eq!(pos.byte, 5);
+        assert_eq!(pos.line, 2);
+        assert_eq!(pos.column, 2);
+    }
+
+    #[test]
+    fn source() {
+        let query = "tï = \"x y\"";
+        let mut start = Position::default();
+        for ch in "tï = ".chars() {
+            start.advance(ch);
+        }
+        let mut end = start;
+        for ch in "\"x y\"".chars() {
+            end.advance(ch);
+        }
+        let span = Span::new(start, end);
+        assert_eq!(span.source(query), Some("\"x y\""));
+        assert!(!span.is_empty());
+        assert!(Span::default().is_empty());
+        assert_eq!(Span::default().source(query), Some(""));
+    }
+}
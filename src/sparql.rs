@@ -0,0 +1,529 @@
+//! Translation of CQL to SPARQL `SELECT` queries.
+//!
+//! Indexes are looked up by the URI of their context set and their name,
+//! so `dc.title` matches whichever prefix the query binds to the Dublin
+//! Core set. Prefixes the query does not bind are taken from
+//! [`SparqlTransform::context_set`]. Each index is mapped to a property
+//! path, such as `bf:title/bf:mainTitle`, and each search clause becomes
+//! a graph pattern binding a fresh variable, with a `FILTER`:
+//!
+//! | CQL             | Filter                                          |
+//! |-----------------|-------------------------------------------------|
+//! | `ti = a`        | `contains(lcase(str(?v1)), "a")`                |
+//! | `ti = ^a*`      | `strstarts(lcase(str(?v1)), "a")`               |
+//! | `ti = a?c`      | `regex(str(?v1), "a.c", "i")`                   |
+//! | `ti == a`       | `str(?v1) = "a"`                                |
+//! | `ti all "a b"`  | `(contains(...) && contains(...))`              |
+//! | `year < 2000`   | `?v1 < "2000"^^xsd:integer`                     |
+//!
+//! Range relations compare typed literals when the property is given a
+//! datatype, and strings otherwise. `and` joins patterns, `or` becomes
+//! `UNION` and `not` becomes `MINUS`. Sort keys become `ORDER BY`, over
+//! `OPTIONAL` patterns.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::term::is_masked;
+use crate::term::regex;
+use crate::term::text;
+use crate::term::trim_stars;
+use crate::term::TermChar;
+use crate::translate::missing_value;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::QueryTranslator;
+use crate::translate::Relations;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// Append `s` as a string literal.
+fn literal(s: &str, out: &mut String) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+/// Append a filter matching `chars` in the string value of `var`,
+/// anchored at the start and end as given by [`trim_stars`].
+fn matches(
+    var: &str,
+    (chars, start, end): (&[TermChar], bool, bool),
+    ignore_case: bool,
+    out: &mut String,
+) {
+    if is_masked(chars) {
+        out.push_str(&format!("regex(str({}), ", var));
+        literal(&regex(chars, start, end), out);
+        out.push_str(if ignore_case { ", \"i\")" } else { ")" });
+        return;
+    }
+    let mut text = text(chars);
+    let value = if ignore_case {
+        text = text.to_lowercase();
+        format!("lcase(str({}))", var)
+    } else {
+        format!("str({})", var)
+    };
+    match (start, end) {
+        (true, true) => out.push_str(&format!("{} = ", value)),
+        (true, false) => out.push_str(&format!("strstarts({}, ", value)),
+        (false, true) => out.push_str(&format!("strends({}, ", value)),
+        (false, false) => out.push_str(&format!("contains({}, ", value)),
+    }
+    literal(&text, out);
+    if !(start && end) {
+        out.push(')');
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    set: String,
+    name: String,
+    path: String,
+    datatype: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SparqlTransform {
+    namespaces: Vec<(String, String)>,
+    sets: Vec<(Option<String>, String)>,
+    properties: Vec<Property>,
+    relations: Relations,
+}
+
+/// Query being written, numbering the variables.
+struct Writer<'a> {
+    transform: &'a SparqlTransform,
+    vars: usize,
+}
+
+impl Writer<'_> {
+    fn var(&mut self, prefix: &str) -> String {
+        self.vars += 1;
+        format!("?{}{}", prefix, self.vars)
+    }
+
+    fn filter(
+        &self,
+        property: &Property,
+        relation: &str,
+        clause: &Clause<'_>,
+        var: &str,
+        out: &mut String,
+    ) -> Result<(), TranslationError> {
+        let term = clause.term();
+        let op = match relation {
+            "=" | "==" | "exact" | "scr" => "=",
+            "<" => "<",
+            "<=" => "<=",
+            ">" => ">",
+            ">=" => ">=",
+            "<>" => "!=",
+            _ => "",
+        };
+        if let Some(datatype) = &property.datatype {
+            if op.is_empty() {
+                return Err(clause.error(TranslationErrorKind::UnsupportedRelation));
+            }
+            clause.unanchored()?;
+            clause.unmasked()?;
+            out.push_str(&format!("{} {} ", var, op));
+            literal(&term.text(), out);
+            out.push_str("^^");
+            out.push_str(datatype);
+            return Ok(());
+        }
+        match relation {
+            "=" | "adj" | "scr" => matches(var, term.trim_stars(), true, out),
+            "all" | "any" => {
+                clause.unanchored()?;
+                let words = clause.words()?;
+                if words.len() > 1 {
+                    out.push('(');
+                }
+                for (i, word) in words.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(if relation == "all" { " && " } else { " || " });
+                    }
+                    matches(var, trim_stars(word, false, false), true, out);
+                }
+                if words.len() > 1 {
+                    out.push(')');
+                }
+            }
+            _ if op.is_empty() => {
+                return Err(clause.error(TranslationErrorKind::UnsupportedRelation))
+            }
+            "==" | "exact" => {
+                clause.unanchored()?;
+                matches(var, trim_stars(&term.chars, true, true), false, out);
+            }
+            _ => {
+                clause.unanchored()?;
+                clause.unmasked()?;
+                out.push_str(&format!("str({}) {} ", var, op));
+                literal(&term.text(), out);
+            }
+        }
+        Ok(())
+    }
+
+    /// A group graph pattern for `clause`.
+    fn search_clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.transform.relations.get(clause)?;
+        let property = self
+            .transform
+            .lookup(clause.st())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        let var = self.var("v");
+        let mut out = format!("{{ ?s {} {} . FILTER(", property.path, var);
+        self.filter(property, &relation, clause, &var, &mut out)?;
+        out.push_str(") }");
+        Ok(out)
+    }
+}
+
+impl QueryTranslator for Writer<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        let sep = match op {
+            BooleanOp::And => " ",
+            BooleanOp::Or => " UNION ",
+            BooleanOp::Not => " MINUS ",
+            BooleanOp::Prox => return Err(TranslationError::boolean(bo)),
+        };
+        let operands: Vec<String> = operands.into_iter().map(|(_, s)| s).collect();
+        Ok(format!("{{ {} }}", operands.join(sep)))
+    }
+
+    fn flatten(&self, op: BooleanOp) -> bool {
+        op != BooleanOp::Not
+    }
+}
+
+impl SortTranslator for Writer<'_> {
+    /// The `OPTIONAL` pattern for the key and its orderings.
+    type SortKey = (String, Vec<String>);
+
+    fn sort_key(&mut self, key: &St) -> Result<(String, Vec<String>), TranslationError> {
+        let property = self
+            .transform
+            .lookup(key)
+            .ok_or_else(|| TranslationError::index(key))?;
+        let mut descending = false;
+        let mut high = None;
+        for m in key.modifiers() {
+            match missing_value(m) {
+                Some(value) => high = Some(value),
+                None => descending = crate::translate::descending(m)?,
+            }
+        }
+        let var = self.var("o");
+        let pattern = format!(" OPTIONAL {{ ?s {} {} }}", property.path, var);
+        let mut order = Vec::new();
+        // unbound values sort lowest, so last when descending
+        match high.map(|high| high != descending) {
+            Some(true) if !descending => order.push(format!("(!bound({}))", var)),
+            Some(false) if descending => order.push(format!("(bound({}))", var)),
+            _ => {}
+        }
+        order.push(format!(
+            "{}({})",
+            if descending { "DESC" } else { "ASC" },
+            var
+        ));
+        Ok((pattern, order))
+    }
+}
+
+impl SparqlTransform {
+    pub fn new() -> SparqlTransform {
+        SparqlTransform::default()
+    }
+
+    /// Declare `prefix` for `iri` in the query, for use in property
+    /// paths and datatypes.
+    pub fn namespace(&mut self, prefix: &str, iri: &str) {
+        self.namespaces
+            .push((String::from(prefix), String::from(iri)));
+    }
+
+    /// Bind the context set prefix `name` to `uri` for queries that do
+    /// not bind it themselves. `None` gives the set of unqualified
+    /// indexes.
+    pub fn context_set(&mut self, name: Option<&str>, uri: &str) {
+        self.sets.push((name.map(String::from), String::from(uri)));
+    }
+
+    /// Search index `name` of the context set `set` in the values of the
+    /// property path `path`. Values are compared as literals of
+    /// `datatype`, such as `xsd:integer`, if given, and as strings if
+    /// not.
+    pub fn property(&mut self, set: &str, name: &str, path: &str, datatype: Option<&str>) {
+        self.properties.push(Property {
+            set: String::from(set),
+            name: String::from(name),
+            path: String::from(path),
+            datatype: datatype.map(String::from),
+        });
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`](crate::translate::Mapping::insert_relation).
+    /// Unlike indexes, relations go by their prefix, not the URI of their
+    /// context set.
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.relations.insert(name, relation);
+    }
+
+    /// The property of the index of `st`. Unlike a
+    /// [`Mapping`](crate::translate::Mapping), this goes by the URI of the
+    /// context set, which the query may bind to any prefix.
+    fn lookup(&self, st: &St) -> Option<&Property> {
+        let (prefix, name) = match st.index().split_once('.') {
+            Some((prefix, name)) => (Some(prefix), name),
+            None => (None, st.index()),
+        };
+        let set = st.index_uri().or_else(|| {
+            let bound = self.sets.iter().find(|(n, _)| match (n, prefix) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                (None, None) => true,
+                _ => false,
+            });
+            bound.map(|(_, uri)| uri.as_str())
+        })?;
+        self.properties
+            .iter()
+            .find(|p| p.set == set && p.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Translate a query to a `SELECT` of the matching subjects, `?s`.
+    pub fn to_sparql(&self, node: &CqlNode) -> Result<String, TranslationError> {
+        let mut writer = Writer {
+            transform: self,
+            vars: 0,
+        };
+        let mut out = String::new();
+        for (prefix, iri) in &self.namespaces {
+            out.push_str(&format!("PREFIX {}: <{}>\n", prefix, iri));
+        }
+        out.push_str("SELECT DISTINCT ?s WHERE { ");
+        out.push_str(&writer.translate(node)?);
+        let mut order = Vec::new();
+        for (pattern, key) in writer.translate_sort(node)? {
+            out.push_str(&pattern);
+            order.extend(key);
+        }
+        out.push_str(" }");
+        if !order.is_empty() {
+            out.push_str("\nORDER BY ");
+            out.push_str(&order.join(" "));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DC: &str = "info:srw/cql-context-set/1/dc-v1.1";
+    const BIB: &str = "info:srw/cql-context-set/1/bib-v1";
+
+    fn transform() -> SparqlTransform {
+        let mut transform = SparqlTransform::new();
+        transform.namespace("bf", "http://id.loc.gov/ontologies/bibframe/");
+        transform.context_set(Some("dc"), DC);
+        transform.context_set(None, DC);
+        transform.property(DC, "title", "bf:title/bf:mainTitle", None);
+        transform.property(DC, "date", "bf:originDate", Some("xsd:integer"));
+        transform.property(BIB, "name", "bf:contribution/bf:agent/rdfs:label", None);
+        transform.property(
+            crate::node::CQL_CONTEXT_SET,
+            "serverChoice",
+            "rdfs:label",
+            None,
+        );
+        transform.relation("bib.contains", "all");
+        transform
+    }
+
+    /// The WHERE clause of the query.
+    fn filter(query: &str) -> Result<String, TranslationError> {
+        let sparql = transform().to_sparql(&crate::parse(query).unwrap())?;
+        let (_, body) = sparql.split_once("WHERE { ").unwrap();
+        Ok(String::from(body.strip_suffix(" }").unwrap()))
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            filter("dc.title = Hobbit").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(contains(lcase(str(?v1)), \"hobbit\")) }"
+        );
+        assert_eq!(
+            filter("title = ^the*").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(strstarts(lcase(str(?v1)), \"the\")) }"
+        );
+        assert_eq!(
+            filter("title = *ring^").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(strends(lcase(str(?v1)), \"ring\")) }"
+        );
+        assert_eq!(
+            filter("title = \"a.?\\\"b\"").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(regex(str(?v1), \"a\\\\..\\\"b\", \"i\")) }"
+        );
+        assert_eq!(
+            filter("title == \"The Hobbit\"").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(str(?v1) = \"The Hobbit\") }"
+        );
+        assert_eq!(
+            filter("title == The*Hobbit").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(regex(str(?v1), \"^The.*Hobbit$\")) }"
+        );
+        assert_eq!(
+            filter("title bib.contains \"lord ring*\"").unwrap(),
+            filter("title all \"lord ring*\"").unwrap()
+        );
+        assert_eq!(
+            filter("title all \"lord ring*\"").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER((contains(lcase(str(?v1)), \"lord\") \
+             && contains(lcase(str(?v1)), \"ring\"))) }"
+        );
+        assert_eq!(
+            filter("title >= m").unwrap(),
+            "{ ?s bf:title/bf:mainTitle ?v1 . FILTER(str(?v1) >= \"m\") }"
+        );
+        assert_eq!(
+            filter("dc.date < 1950").unwrap(),
+            "{ ?s bf:originDate ?v1 . FILTER(?v1 < \"1950\"^^xsd:integer) }"
+        );
+        assert_eq!(
+            filter("dc.date <> 1950").unwrap(),
+            "{ ?s bf:originDate ?v1 . FILTER(?v1 != \"1950\"^^xsd:integer) }"
+        );
+        assert_eq!(
+            filter("hobbit").unwrap(),
+            "{ ?s rdfs:label ?v1 . FILTER(contains(lcase(str(?v1)), \"hobbit\")) }"
+        );
+    }
+
+    #[test]
+    fn context_sets() {
+        let bib = format!(">b=\"{}\" >d=\"{}\" ", BIB, DC);
+        assert_eq!(
+            filter(&(bib.clone() + "b.name = tolkien and d.title = x")).unwrap(),
+            "{ { ?s bf:contribution/bf:agent/rdfs:label ?v1 . \
+             FILTER(contains(lcase(str(?v1)), \"tolkien\")) } \
+             { ?s bf:title/bf:mainTitle ?v2 . FILTER(contains(lcase(str(?v2)), \"x\")) } }"
+        );
+        assert_eq!(
+            filter(">\"http://other\" title = x").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "title")
+        );
+        assert_eq!(
+            filter("bib.name = x").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "bib.name")
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            filter("title == a or title == b or title == c").unwrap(),
+            "{ { ?s bf:title/bf:mainTitle ?v1 . FILTER(str(?v1) = \"a\") } \
+             UNION { ?s bf:title/bf:mainTitle ?v2 . FILTER(str(?v2) = \"b\") } \
+             UNION { ?s bf:title/bf:mainTitle ?v3 . FILTER(str(?v3) = \"c\") } }"
+        );
+        assert_eq!(
+            filter("title == a not title == b").unwrap(),
+            "{ { ?s bf:title/bf:mainTitle ?v1 . FILTER(str(?v1) = \"a\") } \
+             MINUS { ?s bf:title/bf:mainTitle ?v2 . FILTER(str(?v2) = \"b\") } }"
+        );
+        assert_eq!(
+            filter("a prox b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn sort() {
+        let sparql = transform()
+            .to_sparql(
+                &crate::parse("a sortby dc.date/sort.descending/sort.missingValue=highValue title")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            sparql,
+            "PREFIX bf: <http://id.loc.gov/ontologies/bibframe/>\n\
+             SELECT DISTINCT ?s WHERE { \
+             { ?s rdfs:label ?v1 . FILTER(contains(lcase(str(?v1)), \"a\")) } \
+             OPTIONAL { ?s bf:originDate ?o2 } OPTIONAL { ?s bf:title/bf:mainTitle ?o3 } }\n\
+             ORDER BY (bound(?o2)) DESC(?o2) ASC(?o3)"
+        );
+        assert_eq!(
+            transform()
+                .to_sparql(&crate::parse("a sortby title/ignoreCase").unwrap())
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::UnsupportedSortModifier, "ignoreCase")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            filter("dc.date = 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        assert_eq!(
+            filter("dc.date any 1950").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "any")
+        );
+        assert_eq!(
+            filter("title all \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            filter("title == ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            filter("title < a*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a*")
+        );
+        assert_eq!(
+            filter("title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+        assert_eq!(
+            filter("title cql.within a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "cql.within")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+//! Translation of CQL to SQL `WHERE` clauses with bind parameters.
+//!
+//! Indexes are mapped to columns with [`SqlTransform::column`], and
+//! `cql.serverChoice` searches the columns given to
+//! [`SqlTransform::default_columns`]. Terms are always passed as
+//! parameters, never written into the SQL. On text columns:
+//!
+//! | CQL              | SQL (PostgreSQL)                             |
+//! |------------------|----------------------------------------------|
+//! | `ti = "a b"`     | `"title" ILIKE $1 ESCAPE '\'` with `%a b%`   |
+//! | `ti = ^a*`       | `"title" ILIKE $1 ESCAPE '\'` with `a%`      |
+//! | `ti == a`        | `"title" = $1`                               |
+//! | `ti == a?`       | `"title" LIKE $1 ESCAPE '\'` with `a_`       |
+//! | `ti all "a b"`   | `("title" ILIKE $1 ... AND "title" ILIKE $2 ...)` |
+//! | `ti any "a b"`   | `("title" ILIKE $1 ... OR "title" ILIKE $2 ...)`  |
+//! | `ti < m`         | `"title" < $1`                               |
+//!
+//! So `=` and `adj` match the term anywhere in the column unless it is
+//! anchored with `^`, and `*` and `?` become `%` and `_`. PostgreSQL uses
+//! `ILIKE` to ignore case; SQLite's and MySQL's `LIKE` do so already.
+//! On integer and real columns the term is converted to a number, and
+//! `=`, `==`, `<>`, `<`, `<=`, `>` and `>=` compare it. Booleans become
+//! `AND`, `OR` and `AND NOT COALESCE(.., FALSE)`, so `not` keeps rows
+//! where the right operand is NULL, and sort keys an `ORDER BY` list.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::term::is_masked;
+use crate::term::TermChar;
+use crate::translate::infix;
+use crate::translate::missing_value;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// The SQL dialect, deciding placeholders, identifier quoting and `LIKE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Dialect {
+    /// `$1` placeholders, `"` quoting and `ILIKE`.
+    #[default]
+    Postgres,
+    /// `?` placeholders and `"` quoting.
+    Sqlite,
+    /// `?` placeholders and `` ` `` quoting.
+    MySql,
+}
+
+/// Type of a column, deciding how terms are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColumnType {
+    #[default]
+    Text,
+    Integer,
+    Real,
+}
+
+/// A bind parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Text(String),
+    Integer(i64),
+    Real(f64),
+}
+
+/// A translated query: the `WHERE` condition, its parameters in order,
+/// and the `ORDER BY` list if the query has sort keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlQuery {
+    pub where_clause: String,
+    pub params: Vec<SqlValue>,
+    pub order_by: Option<String>,
+}
+
+/// `LIKE` pattern for `chars`, escaping with `\`.
+fn like_pattern(chars: &[TermChar], out: &mut String) {
+    for ch in chars {
+        match ch {
+            TermChar::Literal(ch) => {
+                if "%_\\".contains(*ch) {
+                    out.push('\\');
+                }
+                out.push(*ch);
+            }
+            TermChar::Star => out.push('%'),
+            TermChar::Question => out.push('_'),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    column_type: ColumnType,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SqlTransform {
+    dialect: Dialect,
+    columns: Mapping<Column>,
+}
+
+/// SQL being written, with its parameters.
+struct Writer<'a> {
+    dialect: Dialect,
+    transform: &'a SqlTransform,
+    out: String,
+    params: Vec<SqlValue>,
+}
+
+impl Writer<'_> {
+    fn param(&mut self, value: SqlValue) {
+        self.params.push(value);
+        match self.dialect {
+            Dialect::Postgres => self.out.push_str(&format!("${}", self.params.len())),
+            Dialect::Sqlite | Dialect::MySql => self.out.push('?'),
+        }
+    }
+
+    fn identifier(&mut self, name: &str) {
+        let quote = match self.dialect {
+            Dialect::Postgres | Dialect::Sqlite => '"',
+            Dialect::MySql => '`',
+        };
+        for (i, part) in name.split('.').enumerate() {
+            if i > 0 {
+                self.out.push('.');
+            }
+            self.out.push(quote);
+            for ch in part.chars() {
+                if ch == quote {
+                    self.out.push(quote);
+                }
+                self.out.push(ch);
+            }
+            self.out.push(quote);
+        }
+    }
+
+    /// `column LIKE pattern`, ignoring case if `ignore_case`.
+    fn like(&mut self, column: &str, pattern: String, ignore_case: bool) {
+        self.identifier(column);
+        let op = match self.dialect {
+            Dialect::Postgres if ignore_case => " ILIKE ",
+            _ => " LIKE ",
+        };
+        self.out.push_str(op);
+        self.param(SqlValue::Text(pattern));
+        match self.dialect {
+            Dialect::Postgres | Dialect::Sqlite => self.out.push_str(" ESCAPE '\\'"),
+            Dialect::MySql => self.out.push_str(" ESCAPE '\\\\'"),
+        }
+    }
+
+    fn compare(&mut self, column: &str, op: &str, value: SqlValue) {
+        self.identifier(column);
+        self.out.push(' ');
+        self.out.push_str(op);
+        self.out.push(' ');
+        self.param(value);
+    }
+
+    fn text_clause(
+        &mut self,
+        column: &str,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<(), TranslationError> {
+        let term = clause.term();
+        match relation {
+            "=" | "adj" | "scr" => {
+                let (chars, start, end) = term.trim_stars();
+                let mut pattern = String::new();
+                if !start {
+                    pattern.push('%');
+                }
+                like_pattern(chars, &mut pattern);
+                if !end {
+                    pattern.push('%');
+                }
+                self.like(column, pattern, true);
+            }
+            "all" | "any" => {
+                clause.unanchored()?;
+                let words = clause.words()?;
+                let op = if relation == "all" { " AND " } else { " OR " };
+                if words.len() > 1 {
+                    self.out.push('(');
+                }
+                for (i, word) in words.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(op);
+                    }
+                    let mut pattern = String::from("%");
+                    like_pattern(word, &mut pattern);
+                    pattern.push('%');
+                    self.like(column, pattern, true);
+                }
+                if words.len() > 1 {
+                    self.out.push(')');
+                }
+            }
+            "==" | "exact" => {
+                clause.unanchored()?;
+                if is_masked(&term.chars) {
+                    let mut pattern = String::new();
+                    like_pattern(&term.chars, &mut pattern);
+                    self.like(column, pattern, false);
+                } else {
+                    self.compare(column, "=", SqlValue::Text(term.text()));
+                }
+            }
+            "<>" | "<" | "<=" | ">" | ">=" => {
+                clause.unanchored()?;
+                clause.unmasked()?;
+                self.compare(column, relation, SqlValue::Text(term.text()))
+            }
+            _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+        Ok(())
+    }
+
+    fn column_clause(
+        &mut self,
+        column: &Column,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<(), TranslationError> {
+        let term = clause.term();
+        let number = || {
+            clause.unanchored()?;
+            clause.unmasked()?;
+            let literal = term.text();
+            let value = match column.column_type {
+                ColumnType::Integer => literal.trim().parse().map(SqlValue::Integer).ok(),
+                _ => literal.trim().parse().map(SqlValue::Real).ok(),
+            };
+            value.ok_or_else(|| clause.error(TranslationErrorKind::InvalidNumber))
+        };
+        match (column.column_type, relation) {
+            (ColumnType::Text, _) => self.text_clause(&column.name, relation, clause)?,
+            (_, "=" | "==" | "exact" | "scr") => self.compare(&column.name, "=", number()?),
+            (_, "<>" | "<" | "<=" | ">" | ">=") => self.compare(&column.name, relation, number()?),
+            _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+        Ok(())
+    }
+
+    fn search_clause(&mut self, clause: &Clause<'_>) -> Result<(), TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.transform.columns.relation(clause)?;
+        let columns = self.transform.columns_of(clause)?;
+        if columns.len() > 1 {
+            self.out.push('(');
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(" OR ");
+            }
+            self.column_clause(column, &relation, clause)?;
+        }
+        if columns.len() > 1 {
+            self.out.push(')');
+        }
+        Ok(())
+    }
+}
+
+impl QueryTranslator for Writer<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.search_clause(clause)?;
+        Ok(std::mem::take(&mut self.out))
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        mut operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        let op = match op {
+            BooleanOp::And => " AND ",
+            BooleanOp::Or => " OR ",
+            BooleanOp::Not => {
+                // NOT of a NULL comparison is NULL, which would drop the row
+                let right = operands.pop().map(|(_, s)| s).unwrap_or_default();
+                return Ok(format!(
+                    "{} AND NOT COALESCE({}, FALSE)",
+                    infix(operands, ""),
+                    right
+                ));
+            }
+            BooleanOp::Prox => return Err(TranslationError::boolean(bo)),
+        };
+        Ok(infix(operands, op))
+    }
+}
+
+impl SortTranslator for Writer<'_> {
+    type SortKey = String;
+
+    fn sort_key(&mut self, key: &St) -> Result<String, TranslationError> {
+        let column = self
+            .transform
+            .columns
+            .get(key.index())
+            .ok_or_else(|| TranslationError::index(key))?;
+        let mut descending = false;
+        let mut nulls = None;
+        for m in key.modifiers() {
+            match missing_value(m) {
+                Some(high) if self.dialect != Dialect::MySql => nulls = Some(high),
+                _ => descending = crate::translate::descending(m)?,
+            }
+        }
+        self.identifier(&column.name);
+        self.out.push_str(if descending { " DESC" } else { " ASC" });
+        if let Some(high) = nulls {
+            self.out.push_str(if high != descending {
+                " NULLS LAST"
+            } else {
+                " NULLS FIRST"
+            });
+        }
+        Ok(std::mem::take(&mut self.out))
+    }
+}
+
+impl SqlTransform {
+    pub fn new(dialect: Dialect) -> SqlTransform {
+        SqlTransform {
+            dialect,
+            ..SqlTransform::default()
+        }
+    }
+
+    /// Search `index` in `column`, which may be qualified by a table name.
+    /// `index` is matched as by [`Mapping`].
+    pub fn column(&mut self, index: &str, column: &str, column_type: ColumnType) {
+        let column = Column {
+            name: String::from(column),
+            column_type,
+        };
+        self.columns.insert(index, column);
+    }
+
+    /// Text columns searched, with `OR`, for `cql.serverChoice` unless
+    /// it is mapped with [`SqlTransform::column`].
+    pub fn default_columns(&mut self, columns: &[&str]) {
+        let columns = columns
+            .iter()
+            .map(|c| Column {
+                name: String::from(*c),
+                column_type: ColumnType::Text,
+            })
+            .collect();
+        self.columns.server_choice(columns);
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.columns.insert_relation(name, relation);
+    }
+
+    fn columns_of(&self, clause: &Clause<'_>) -> Result<Vec<&Column>, TranslationError> {
+        self.columns
+            .resolve(clause.index())
+            .filter(|columns| !columns.is_empty())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))
+    }
+
+    /// Translate a query to a `WHERE` condition and `ORDER BY` list.
+    pub fn to_sql(&self, node: &CqlNode) -> Result<SqlQuery, TranslationError> {
+        let mut writer = Writer {
+            dialect: self.dialect,
+            transform: self,
+            out: String::new(),
+            params: Vec::new(),
+        };
+        let where_clause = writer.translate(node)?;
+        let order_by = writer.translate_sort(node)?;
+        Ok(SqlQuery {
+            where_clause,
+            params: writer.params,
+            order_by: (!order_by.is_empty()).then(|| order_by.join(", ")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::types::Value;
+    use rusqlite::Connection;
+
+    fn transform(dialect: Dialect) -> SqlTransform {
+        let mut transform = SqlTransform::new(dialect);
+        transform.column("dc.title", "books.title", ColumnType::Text);
+        transform.column("dc.creator", "author", ColumnType::Text);
+        transform.column("date", "year", ColumnType::Integer);
+        transform.column("price", "price", ColumnType::Real);
+        transform.default_columns(&["title", "author"]);
+        transform
+    }
+
+    fn sql(dialect: Dialect, query: &str) -> Result<SqlQuery, TranslationError> {
+        transform(dialect).to_sql(&crate::parse(query).unwrap())
+    }
+
+    fn text(s: &str) -> SqlValue {
+        SqlValue::Text(String::from(s))
+    }
+
+    #[test]
+    fn dialects() {
+        let query = sql(Dialect::Postgres, "dc.title = \"50% off\" and date >= 2000").unwrap();
+        assert_eq!(
+            query.where_clause,
+            "\"books\".\"title\" ILIKE $1 ESCAPE '\\' AND \"year\" >= $2"
+        );
+        assert_eq!(query.params, [text("%50\\% off%"), SqlValue::Integer(2000)]);
+        let query = sql(Dialect::Sqlite, "dc.title = a_b").unwrap();
+        assert_eq!(query.where_clause, "\"books\".\"title\" LIKE ? ESCAPE '\\'");
+        assert_eq!(query.params, [text("%a\\_b%")]);
+        let query = sql(Dialect::MySql, "dc.creator == \"o'brien\" or price < 9.5").unwrap();
+        assert_eq!(query.where_clause, "`author` = ? OR `price` < ?");
+        assert_eq!(query.params, [text("o'brien"), SqlValue::Real(9.5)]);
+        let query = sql(Dialect::MySql, "date > 1950 not price > 9").unwrap();
+        assert_eq!(
+            query.where_clause,
+            "`year` > ? AND NOT COALESCE(`price` > ?, FALSE)"
+        );
+        let query = sql(Dialect::MySql, "dc.title == a*\\\\").unwrap();
+        assert_eq!(query.where_clause, "`books`.`title` LIKE ? ESCAPE '\\\\'");
+        assert_eq!(query.params, [text("a%\\\\")]);
+    }
+
+    #[test]
+    fn clauses() {
+        let query = sql(Dialect::Postgres, "dc.title any \"a b\" not x").unwrap();
+        assert_eq!(
+            query.where_clause,
+            "(\"books\".\"title\" ILIKE $1 ESCAPE '\\' OR \"books\".\"title\" ILIKE $2 ESCAPE '\\') \
+             AND NOT COALESCE((\"title\" ILIKE $3 ESCAPE '\\' \
+             OR \"author\" ILIKE $4 ESCAPE '\\'), FALSE)"
+        );
+        assert_eq!(
+            query.params,
+            [text("%a%"), text("%b%"), text("%x%"), text("%x%")]
+        );
+        let query = sql(
+            Dialect::Postgres,
+            "dc.title = ^the* and (date < 1 or date <> 2)",
+        )
+        .unwrap();
+        assert_eq!(
+            query.where_clause,
+            "\"books\".\"title\" ILIKE $1 ESCAPE '\\' AND (\"year\" < $2 OR \"year\" <> $3)"
+        );
+        assert_eq!(query.params[0], text("the%"));
+        assert_eq!(
+            sql(Dialect::Postgres, "date = 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        let e = sql(Dialect::Postgres, "date = abc").unwrap_err();
+        assert_eq!(e.parts(), (TranslationErrorKind::InvalidNumber, "abc"));
+        assert_eq!(e.span().source("date = abc"), Some("abc"));
+        assert_eq!(e.to_string(), "invalid number abc");
+        assert_eq!(
+            sql(Dialect::Postgres, "date any 1").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "any")
+        );
+        assert_eq!(
+            sql(Dialect::Postgres, "dc.title any \" \"")
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            sql(Dialect::Postgres, "dc.title < ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            sql(Dialect::Postgres, "dc.subject = a")
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            sql(Dialect::Postgres, "a prox b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn order_by() {
+        let query = sql(
+            Dialect::Postgres,
+            "a sortby date/sort.descending/sort.missingValue=highValue dc.title",
+        )
+        .unwrap();
+        assert_eq!(
+            query.order_by.as_deref(),
+            Some("\"year\" DESC NULLS FIRST, \"books\".\"title\" ASC")
+        );
+        assert_eq!(
+            sql(Dialect::MySql, "a sortby date/missingValue=lowValue")
+                .unwrap_err()
+                .parts(),
+            (
+                TranslationErrorKind::UnsupportedSortModifier,
+                "missingValue"
+            )
+        );
+        assert_eq!(sql(Dialect::MySql, "a").unwrap().order_by, None);
+    }
+
+    fn search(db: &Connection, query: &str) -> Vec<i64> {
+        let mut transform = SqlTransform::new(Dialect::Sqlite);
+        transform.column("dc.title", "books.title", ColumnType::Text);
+        transform.column("dc.creator", "author", ColumnType::Text);
+        transform.column("date", "year", ColumnType::Integer);
+        transform.column("price", "price", ColumnType::Real);
+        transform.column("id", "id", ColumnType::Integer);
+        transform.default_columns(&["title", "author"]);
+        let query = transform.to_sql(&crate::parse(query).unwrap()).unwrap();
+        let order_by = query.order_by.unwrap_or_else(|| String::from("id"));
+        let sql = format!(
+            "SELECT id FROM books WHERE {} ORDER BY {}",
+            query.where_clause, order_by
+        );
+        let params = query.params.into_iter().map(|p| match p {
+            SqlValue::Text(s) => Value::Text(s),
+            SqlValue::Integer(n) => Value::Integer(n),
+            SqlValue::Real(n) => Value::Real(n),
+        });
+        let mut stmt = db.prepare(&sql).unwrap();
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| row.get(0))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn sqlite() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE books (id INTEGER, title TEXT, author TEXT, year INTEGER, price REAL);
+             INSERT INTO books VALUES
+                 (1, 'The Hobbit', 'Tolkien', 1937, 8.99),
+                 (2, 'The Lord of the Rings', 'Tolkien', 1954, 25.0),
+                 (3, 'Dune', 'Herbert', 1965, 9.5),
+                 (4, '100% Tolkien_Fan', 'O''Brien', 2001, NULL);",
+        )
+        .unwrap();
+        assert_eq!(search(&db, "hobbit"), [1]);
+        assert_eq!(search(&db, "tolkien"), [1, 2, 4]);
+        assert_eq!(search(&db, "dc.creator == Tolkien"), [1, 2]);
+        assert_eq!(search(&db, "dc.creator == tolkien"), Vec::<i64>::new());
+        assert_eq!(search(&db, "dc.title = ^the"), [1, 2]);
+        assert_eq!(search(&db, "dc.title = ^th?*s^"), [2]);
+        assert_eq!(search(&db, "dc.title = \"100%\""), [4]);
+        assert_eq!(search(&db, "dc.title = \"0% t\""), [4]);
+        assert_eq!(search(&db, "dc.title = \"n_F\""), [4]);
+        assert_eq!(search(&db, "dc.title = \"in_\""), Vec::<i64>::new());
+        assert_eq!(search(&db, "dc.title all \"lord rings\""), [2]);
+        assert_eq!(search(&db, "dc.title any \"dune hobbit\""), [1, 3]);
+        assert_eq!(search(&db, "date < 1960 and price > 9"), [2]);
+        assert_eq!(search(&db, "dc.creator = o'brien"), [4]);
+        assert_eq!(search(&db, "tolkien not date >= 1950"), [1]);
+        assert_eq!(search(&db, "id > 0 not price > 9"), [1, 4]);
+        assert_eq!(
+            search(&db, "dc.creator = \"'; DROP TABLE books; --\""),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            search(&db, "id > 0 sortby date/sort.descending"),
+            [4, 3, 2, 1]
+        );
+        assert_eq!(
+            search(&db, "id > 0 sortby price/sort.missingValue=lowValue id"),
+            [4, 1, 3, 2]
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Translation of CQL to tantivy queries.
+//!
+//! Indexes are mapped to fields of the schema with
+//! [`TantivyTransform::field`], and `cql.serverChoice` searches the fields
+//! given to [`TantivyTransform::default_fields`]. Terms for text fields
+//! are split into tokens by the field's analyzer, as when indexing:
+//!
+//! | CQL              | Query                                          |
+//! |------------------|------------------------------------------------|
+//! | `ti = a`         | `TermQuery`                                    |
+//! | `ti = "a b"`     | `PhraseQuery`                                  |
+//! | `ti = a*`        | `RegexQuery` for `a.*`                         |
+//! | `ti all "a b"`   | `BooleanQuery` of `Must` clauses               |
+//! | `ti any "a b"`   | `BooleanQuery` of `Should` clauses             |
+//! | `year < 2000`    | `RangeQuery`                                   |
+//! | `ti <> a`        | `BooleanQuery` of `AllQuery` and `MustNot`     |
+//! | `a not b`        | `BooleanQuery` of `Must` and `MustNot`         |
+//!
+//! `=` and `==` are the same, as the analyzer decides how terms match.
+//! Masking is limited to single words, each part of which must be a
+//! single token. Numeric fields take `u64`, `i64` or `f64` terms. Sort
+//! keys on fast fields become orderings for
+//! `TopDocs::order_by_fast_field`.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::St;
+use crate::span::Span;
+use crate::term::is_masked;
+use crate::term::text;
+use crate::term::TermChar;
+use crate::translate::descending;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::SortTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+use ::tantivy::query::AllQuery;
+use ::tantivy::query::BooleanQuery;
+use ::tantivy::query::EmptyQuery;
+use ::tantivy::query::Occur;
+use ::tantivy::query::PhraseQuery;
+use ::tantivy::query::Query;
+use ::tantivy::query::RangeQuery;
+use ::tantivy::query::RegexQuery;
+use ::tantivy::query::TermQuery;
+use ::tantivy::schema::Field;
+use ::tantivy::schema::FieldType;
+use ::tantivy::schema::IndexRecordOption;
+use ::tantivy::schema::Schema;
+use ::tantivy::tokenizer::TextAnalyzer;
+use ::tantivy::tokenizer::TokenStream;
+use ::tantivy::tokenizer::TokenizerManager;
+use ::tantivy::Index;
+use ::tantivy::Order;
+use std::ops::Bound;
+
+/// `BooleanQuery` of `queries`, or the query itself if only one.
+fn combine(occur: Occur, mut queries: Vec<Box<dyn Query>>) -> Box<dyn Query> {
+    if queries.len() == 1 {
+        return queries.remove(0);
+    }
+    let clauses = queries.into_iter().map(|q| (occur, q)).collect();
+    Box::new(BooleanQuery::new(clauses))
+}
+
+/// All documents except those matching `query`.
+fn exclude(query: Box<dyn Query>) -> Box<dyn Query> {
+    Box::new(BooleanQuery::new(vec![
+        (Occur::Must, Box::new(AllQuery)),
+        (Occur::MustNot, query),
+    ]))
+}
+
+/// A text field with its analyzer.
+struct TextField<'a> {
+    field: Field,
+    name: &'a str,
+    /// The index searched in the field, for errors.
+    span: Span,
+    option: IndexRecordOption,
+    analyzer: TextAnalyzer,
+}
+
+impl TextField<'_> {
+    /// Terms for the tokens of `text`, with their positions.
+    fn tokens(&mut self, text: &str) -> Vec<(usize, ::tantivy::Term)> {
+        let field = self.field;
+        self.words(text)
+            .into_iter()
+            .map(|(position, word)| (position, ::tantivy::Term::from_field_text(field, &word)))
+            .collect()
+    }
+
+    fn words(&mut self, text: &str) -> Vec<(usize, String)> {
+        let mut res = Vec::new();
+        let mut stream = self.analyzer.token_stream(text);
+        stream.process(&mut |token| res.push((token.position, token.text.clone())));
+        res
+    }
+
+    /// Query for the tokens of `text`, as a phrase if more than one.
+    fn phrase(&mut self, text: &str) -> Result<Box<dyn Query>, TranslationError> {
+        let mut tokens = self.tokens(text);
+        match tokens.len() {
+            0 => Ok(Box::new(EmptyQuery)),
+            1 => Ok(Box::new(TermQuery::new(tokens.remove(0).1, self.option))),
+            _ if !self.option.has_positions() => {
+                let kind = TranslationErrorKind::PositionsNotIndexed;
+                Err(TranslationError::new(kind, self.name, self.span))
+            }
+            _ => Ok(Box::new(PhraseQuery::new_with_offset(tokens))),
+        }
+    }
+
+    /// Query for a word, which may be masked. Each run of literal
+    /// characters is analyzed on its own and must give a single token.
+    fn word(
+        &mut self,
+        chars: &[TermChar],
+        clause: &Clause<'_>,
+    ) -> Result<Box<dyn Query>, TranslationError> {
+        if !is_masked(chars) {
+            return self.phrase(&text(chars));
+        }
+        let mut pattern = String::new();
+        for run in chars.split_inclusive(|ch| !matches!(ch, TermChar::Literal(_))) {
+            let (mask, literal) = match run.split_last() {
+                Some((TermChar::Literal(_), _)) => (None, run),
+                Some((mask, literal)) => (Some(mask), literal),
+                None => (None, run),
+            };
+            let words = self.words(&text(literal));
+            if words.len() > 1 {
+                return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+            }
+            if let Some((_, word)) = words.first() {
+                for ch in word.chars() {
+                    if "\\.+*?()|[]{}^$#&-~".contains(ch) {
+                        pattern.push('\\');
+                    }
+                    pattern.push(ch);
+                }
+            }
+            match mask {
+                Some(TermChar::Star) => pattern.push_str(".*"),
+                Some(TermChar::Question) => pattern.push('.'),
+                _ => {}
+            }
+        }
+        let query = RegexQuery::from_pattern(&pattern, self.field)
+            .map_err(|_| clause.error(TranslationErrorKind::UnsupportedMasking))?;
+        Ok(Box::new(query))
+    }
+
+    fn query(
+        &mut self,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<Box<dyn Query>, TranslationError> {
+        clause.unanchored()?;
+        let term = clause.term();
+        let masked = is_masked(&term.chars);
+        let bound = |field: &mut TextField, f: fn(::tantivy::Term) -> Bound<::tantivy::Term>| {
+            clause.unmasked()?;
+            let mut tokens = field.tokens(&term.text());
+            if tokens.len() != 1 {
+                return Err(clause.error(TranslationErrorKind::UnsupportedRelation));
+            }
+            Ok(f(tokens.remove(0).1))
+        };
+        match relation {
+            "=" | "adj" | "scr" | "==" | "exact" => {
+                let words = term.words();
+                match words.len() {
+                    _ if !masked => self.phrase(&term.text()),
+                    1 => self.word(words[0], clause),
+                    _ => Err(clause.error(TranslationErrorKind::UnsupportedMasking)),
+                }
+            }
+            "all" | "any" => {
+                let queries: Result<Vec<Box<dyn Query>>, TranslationError> = clause
+                    .words()?
+                    .iter()
+                    .map(|w| self.word(w, clause))
+                    .collect();
+                let occur = if relation == "all" {
+                    Occur::Must
+                } else {
+                    Occur::Should
+                };
+                Ok(combine(occur, queries?))
+            }
+            "<" => {
+                let upper = bound(self, Bound::Excluded)?;
+                Ok(Box::new(RangeQuery::new(Bound::Unbounded, upper)))
+            }
+            "<=" => {
+                let upper = bound(self, Bound::Included)?;
+                Ok(Box::new(RangeQuery::new(Bound::Unbounded, upper)))
+            }
+            ">" => {
+                let lower = bound(self, Bound::Excluded)?;
+                Ok(Box::new(RangeQuery::new(lower, Bound::Unbounded)))
+            }
+            ">=" => {
+                let lower = bound(self, Bound::Included)?;
+                Ok(Box::new(RangeQuery::new(lower, Bound::Unbounded)))
+            }
+            "<>" if !masked => Ok(exclude(self.phrase(&term.text())?)),
+            "<>" => Err(clause.error(TranslationErrorKind::UnsupportedMasking)),
+            _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+    }
+}
+
+/// Query on a numeric field.
+fn numeric(
+    field: Field,
+    field_type: &FieldType,
+    relation: &str,
+    clause: &Clause<'_>,
+) -> Result<Box<dyn Query>, TranslationError> {
+    clause.unanchored()?;
+    clause.unmasked()?;
+    let value = clause.term().text();
+    let value = value.trim();
+    let invalid = || clause.error(TranslationErrorKind::InvalidNumber);
+    let value = match field_type {
+        FieldType::U64(_) => {
+            ::tantivy::Term::from_field_u64(field, value.parse().map_err(|_| invalid())?)
+        }
+        FieldType::I64(_) => {
+            ::tantivy::Term::from_field_i64(field, value.parse().map_err(|_| invalid())?)
+        }
+        _ => ::tantivy::Term::from_field_f64(field, value.parse().map_err(|_| invalid())?),
+    };
+    let query: Box<dyn Query> = match relation {
+        "=" | "==" | "exact" | "scr" => Box::new(TermQuery::new(value, IndexRecordOption::Basic)),
+        "<" => Box::new(RangeQuery::new(Bound::Unbounded, Bound::Excluded(value))),
+        "<=" => Box::new(RangeQuery::new(Bound::Unbounded, Bound::Included(value))),
+        ">" => Box::new(RangeQuery::new(Bound::Excluded(value), Bound::Unbounded)),
+        ">=" => Box::new(RangeQuery::new(Bound::Included(value), Bound::Unbounded)),
+        "<>" => exclude(Box::new(TermQuery::new(value, IndexRecordOption::Basic))),
+        _ => return Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+    };
+    Ok(query)
+}
+
+#[derive(Clone)]
+pub struct TantivyTransform {
+    schema: Schema,
+    tokenizers: TokenizerManager,
+    fields: Mapping<Field>,
+}
+
+impl TantivyTransform {
+    /// Transform for `schema`, with analyzers from `tokenizers`.
+    pub fn new(schema: Schema, tokenizers: TokenizerManager) -> TantivyTransform {
+        TantivyTransform {
+            schema,
+            tokenizers,
+            fields: Mapping::new(),
+        }
+    }
+
+    /// Transform for the schema and analyzers of `index`.
+    pub fn for_index(index: &Index) -> TantivyTransform {
+        TantivyTransform::new(index.schema(), index.tokenizers().clone())
+    }
+
+    /// Search `index` in `field`. `index` is matched as by [`Mapping`].
+    pub fn field(&mut self, index: &str, field: Field) {
+        self.fields.insert(index, field);
+    }
+
+    /// Fields searched, with `Should`, for `cql.serverChoice` unless it
+    /// is mapped with [`TantivyTransform::field`].
+    pub fn default_fields(&mut self, fields: &[Field]) {
+        self.fields.server_choice(fields.to_vec());
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.fields.insert_relation(name, relation);
+    }
+
+    fn field_query(
+        &self,
+        field: Field,
+        relation: &str,
+        clause: &Clause<'_>,
+    ) -> Result<Box<dyn Query>, TranslationError> {
+        let unsupported = || clause.error(TranslationErrorKind::UnsupportedIndex);
+        let st = clause.st();
+        let span = st.index_span().unwrap_or(st.span());
+        let entry = self.schema.get_field_entry(field);
+        if !entry.is_indexed() {
+            return Err(unsupported());
+        }
+        match entry.field_type() {
+            FieldType::Str(options) => {
+                let indexing = options.get_indexing_options().ok_or_else(unsupported)?;
+                let analyzer = self.tokenizers.get(indexing.tokenizer()).ok_or_else(|| {
+                    let kind = TranslationErrorKind::UnknownTokenizer;
+                    TranslationError::new(kind, indexing.tokenizer(), span)
+                })?;
+                let mut text = TextField {
+                    field,
+                    name: entry.name(),
+                    span,
+                    option: indexing.index_option(),
+                    analyzer,
+                };
+                text.query(relation, clause)
+            }
+            field_type @ (FieldType::U64(_) | FieldType::I64(_) | FieldType::F64(_)) => {
+                numeric(field, field_type, relation, clause)
+            }
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<Box<dyn Query>, TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.fields.relation(clause)?;
+        let fields = self
+            .fields
+            .resolve(clause.index())
+            .filter(|fields| !fields.is_empty())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        let queries: Result<Vec<Box<dyn Query>>, TranslationError> = fields
+            .into_iter()
+            .map(|field| self.field_query(*field, &relation, clause))
+            .collect();
+        Ok(combine(Occur::Should, queries?))
+    }
+
+    /// Translate the search part of a query to a tantivy query.
+    pub fn to_query(&self, node: &CqlNode) -> Result<Box<dyn Query>, TranslationError> {
+        Translator { transform: self }.translate(node)
+    }
+
+    /// Translate the sort keys of a query to fast field names and
+    /// orders, most significant first.
+    pub fn to_order(&self, node: &CqlNode) -> Result<Vec<(String, Order)>, TranslationError> {
+        Translator { transform: self }.translate_sort(node)
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a TantivyTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = Box<dyn Query>;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<Box<dyn Query>, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, Box<dyn Query>)>,
+    ) -> Result<Box<dyn Query>, TranslationError> {
+        let occur = match op {
+            BooleanOp::And | BooleanOp::Not => Occur::Must,
+            BooleanOp::Or => Occur::Should,
+            BooleanOp::Prox => return Err(TranslationError::boolean(bo)),
+        };
+        let clauses = operands
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_, query))| match op {
+                BooleanOp::Not if i > 0 => (Occur::MustNot, query),
+                _ => (occur, query),
+            })
+            .collect();
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    fn flatten(&self, _op: BooleanOp) -> bool {
+        true
+    }
+}
+
+impl SortTranslator for Translator<'_> {
+    type SortKey = (String, Order);
+
+    fn sort_key(&mut self, key: &St) -> Result<(String, Order), TranslationError> {
+        let unsupported = || TranslationError::index(key);
+        let field = self
+            .transform
+            .fields
+            .get(key.index())
+            .ok_or_else(unsupported)?;
+        let entry = self.transform.schema.get_field_entry(*field);
+        if !entry.is_fast() {
+            return Err(unsupported());
+        }
+        let mut order = Order::Asc;
+        for m in key.modifiers() {
+            order = if descending(m)? {
+                Order::Desc
+            } else {
+                Order::Asc
+            };
+        }
+        Ok((String::from(entry.name()), order))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tantivy::collector::DocSetCollector;
+    use ::tantivy::collector::TopDocs;
+    use ::tantivy::doc;
+    use ::tantivy::schema::FAST;
+    use ::tantivy::schema::INDEXED;
+    use ::tantivy::schema::STORED;
+    use ::tantivy::schema::STRING;
+    use ::tantivy::schema::TEXT;
+    use ::tantivy::IndexWriter;
+
+    struct Fixture {
+        index: Index,
+        transform: TantivyTransform,
+    }
+
+    fn fixture() -> Fixture {
+        let mut builder = Schema::builder();
+        let id = builder.add_u64_field("id", STORED | FAST);
+        let title = builder.add_text_field("title", TEXT);
+        let author = builder.add_text_field("author", STRING);
+        let year = builder.add_i64_field("year", INDEXED | FAST);
+        let index = Index::create_in_ram(builder.build());
+        let mut writer: IndexWriter = index.writer_with_num_threads(1, 20_000_000).unwrap();
+        let docs = [
+            (1, "The Hobbit", "Tolkien", 1937),
+            (2, "The Lord of the Rings", "Tolkien", 1954),
+            (3, "The Silmarillion", "Tolkien", 1977),
+            (4, "A Wizard of Earthsea", "Le Guin", 1968),
+        ];
+        for (i, t, a, y) in docs {
+            writer
+                .add_document(doc!(id => i as u64, title => t, author => a, year => y as i64))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        let mut transform = TantivyTransform::for_index(&index);
+        transform.field("dc.title", title);
+        transform.field("dc.creator", author);
+        transform.field("year", year);
+        transform.default_fields(&[title, author]);
+        Fixture { index, transform }
+    }
+
+    impl Fixture {
+        fn search(&self, query: &str) -> Result<Vec<u64>, TranslationError> {
+            let query = self.transform.to_query(&crate::parse(query).unwrap())?;
+            let searcher = self.index.reader().unwrap().searcher();
+            let mut ids: Vec<u64> = searcher
+                .search(&*query, &DocSetCollector)
+                .unwrap()
+                .into_iter()
+                .map(|address| {
+                    let reader = searcher.segment_reader(address.segment_ord);
+                    let column = reader.fast_fields().u64("id").unwrap();
+                    column.first(address.doc_id).unwrap()
+                })
+                .collect();
+            ids.sort();
+            Ok(ids)
+        }
+    }
+
+    #[test]
+    fn search_clauses() {
+        let f = fixture();
+        assert_eq!(f.search("dc.title = HOBBIT").unwrap(), [1]);
+        assert_eq!(f.search("dc.title = \"lord of the rings\"").unwrap(), [2]);
+        assert_eq!(
+            f.search("dc.title = \"rings lord\"").unwrap(),
+            Vec::<u64>::new()
+        );
+        assert_eq!(f.search("dc.title = hob*").unwrap(), [1]);
+        assert_eq!(f.search("dc.title = s?lmar*").unwrap(), [3]);
+        assert_eq!(f.search("dc.title all \"the ring*\"").unwrap(), [2]);
+        assert_eq!(f.search("dc.title any \"hobbit wizard\"").unwrap(), [1, 4]);
+        assert_eq!(f.search("dc.creator == \"Le Guin\"").unwrap(), [4]);
+        assert_eq!(
+            f.search("dc.creator == \"le guin\"").unwrap(),
+            Vec::<u64>::new()
+        );
+        assert_eq!(f.search("dc.creator <> Tolkien").unwrap(), [4]);
+        assert_eq!(f.search("year < 1954").unwrap(), [1]);
+        assert_eq!(f.search("year >= 1954").unwrap(), [2, 3, 4]);
+        assert_eq!(f.search("year = 1977").unwrap(), [3]);
+        assert_eq!(f.search("dc.title > v").unwrap(), [4]);
+        assert_eq!(f.search("tolkien").unwrap(), Vec::<u64>::new());
+        assert_eq!(f.search("Tolkien").unwrap(), [1, 2, 3]);
+        assert_eq!(f.search("earthsea").unwrap(), [4]);
+    }
+
+    #[test]
+    fn booleans() {
+        let f = fixture();
+        assert_eq!(
+            f.search("dc.title = the and year > 1950 and dc.creator == Tolkien")
+                .unwrap(),
+            [2, 3]
+        );
+        assert_eq!(
+            f.search("dc.title = hobbit or dc.title = wizard or year = 1977")
+                .unwrap(),
+            [1, 3, 4]
+        );
+        assert_eq!(
+            f.search("dc.creator == Tolkien not (dc.title = hobbit or year > 1970)")
+                .unwrap(),
+            [2]
+        );
+        assert_eq!(
+            f.search("a prox b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn order() {
+        let f = fixture();
+        let node = crate::parse("dc.title = the sortby year/sort.descending").unwrap();
+        let order = f.transform.to_order(&node).unwrap();
+        assert_eq!(order, [(String::from("year"), Order::Desc)]);
+        let (field, order) = order.into_iter().next().unwrap();
+        let query = f.transform.to_query(&node).unwrap();
+        let searcher = f.index.reader().unwrap().searcher();
+        let top = searcher
+            .search(
+                &*query,
+                &TopDocs::with_limit(10).order_by_fast_field::<i64>(field, order),
+            )
+            .unwrap();
+        let years: Vec<i64> = top.into_iter().map(|(year, _)| year).collect();
+        assert_eq!(years, [1977, 1954, 1937]);
+        assert_eq!(
+            f.transform
+                .to_order(&crate::parse("a sortby dc.title").unwrap())
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.title")
+        );
+        assert_eq!(
+            f.transform
+                .to_order(&crate::parse("a sortby year/missingValue=lowValue").unwrap())
+                .unwrap_err()
+                .parts(),
+            (
+                TranslationErrorKind::UnsupportedSortModifier,
+                "missingValue"
+            )
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let f = fixture();
+        assert_eq!(
+            f.search("dc.title = ^hobbit").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^hobbit")
+        );
+        assert_eq!(
+            f.search("dc.title = \"the hob*\"").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "the hob*")
+        );
+        assert_eq!(
+            f.search("year = 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        assert_eq!(
+            f.search("year = soon").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidNumber, "soon")
+        );
+        assert_eq!(
+            f.search("year any 1937").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "any")
+        );
+        assert_eq!(
+            f.search("dc.title all \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            f.search("dc.subject = a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            f.search("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Search terms split into literal characters and masking, for the
+//! translators to other query languages.
+
+/// A character of a search term, after removing escapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermChar {
+    Literal(char),
+    /// `*`, any number of characters.
+    Star,
+    /// `?`, a single character.
+    Question,
+}
+
+impl TermChar {
+    /// The character, with masking as `*` and `?`.
+    pub fn as_char(self) -> char {
+        match self {
+            TermChar::Literal(ch) => ch,
+            TermChar::Star => '*',
+            TermChar::Question => '?',
+        }
+    }
+}
+
+/// Term split into characters, with anchors removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub chars: Vec<TermChar>,
+    /// Anchored at the start with `^`.
+    pub first: bool,
+    /// Anchored at the end with `^`.
+    pub last: bool,
+}
+
+impl Term {
+    /// Split a term as written in a query, with escapes and masking.
+    pub fn new(term: &str) -> Term {
+        let mut chars = Vec::new();
+        let mut first = false;
+        let mut last = false;
+        let mut it = term.chars().peekable();
+        while let Some(ch) = it.next() {
+            match ch {
+                '\\' => chars.push(TermChar::Literal(it.next().unwrap_or('\\'))),
+                '*' => chars.push(TermChar::Star),
+                '?' => chars.push(TermChar::Question),
+                '^' if chars.is_empty() && !first => first = true,
+                '^' if it.peek().is_none() => last = true,
+                _ => chars.push(TermChar::Literal(ch)),
+            }
+        }
+        Term { chars, first, last }
+    }
+
+    /// The characters without leading and trailing `*`, and whether the
+    /// term is anchored at the start and end, as by [`trim_stars`].
+    pub fn trim_stars(&self) -> (&[TermChar], bool, bool) {
+        trim_stars(&self.chars, self.first, self.last)
+    }
+
+    /// The words of the term, separated by whitespace.
+    pub fn words(&self) -> Vec<&[TermChar]> {
+        self.chars
+            .split(|ch| matches!(ch, TermChar::Literal(ch) if ch.is_whitespace()))
+            .filter(|word| !word.is_empty())
+            .collect()
+    }
+
+    /// The term without escapes, with masking as `*` and `?`.
+    pub fn text(&self) -> String {
+        text(&self.chars)
+    }
+}
+
+/// `chars` as a string, with masking as `*` and `?`.
+pub fn text(chars: &[TermChar]) -> String {
+    chars.iter().map(|ch| ch.as_char()).collect()
+}
+
+/// Whether `chars` include masking.
+pub fn is_masked(chars: &[TermChar]) -> bool {
+    chars.iter().any(|ch| !matches!(ch, TermChar::Literal(_)))
+}
+
+/// `chars` without leading and trailing `*`, and whether they must match
+/// at the start and at the end of a value: as given by `start` and `end`,
+/// unless a `*` was removed there.
+pub fn trim_stars(chars: &[TermChar], start: bool, end: bool) -> (&[TermChar], bool, bool) {
+    let mut res = chars;
+    while let Some((TermChar::Star, rest)) = res.split_first() {
+        res = rest;
+    }
+    let start = start && res.len() == chars.len();
+    let len = res.len();
+    while let Some((TermChar::Star, rest)) = res.split_last() {
+        res = rest;
+    }
+    (res, start, end && res.len() == len)
+}
+
+/// Regular expression for `chars`, with masking as `.*` and `.`, and
+/// anchored with `^` and `$` if `start` and `end`.
+pub fn regex(chars: &[TermChar], start: bool, end: bool) -> String {
+    let mut res = String::new();
+    if start {
+        res.push('^');
+    }
+    for ch in chars {
+        match ch {
+            TermChar::Literal(ch) => {
+                if "\\^$.|?*+()[]{}".contains(*ch) {
+                    res.push('\\');
+                }
+                res.push(*ch);
+            }
+            TermChar::Star => res.push_str(".*"),
+            TermChar::Question => res.push('.'),
+        }
+    }
+    if end {
+        res.push('$');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms() {
+        let term = Term::new("^a\\*b* c?^");
+        assert!(term.first);
+        assert!(term.last);
+        assert_eq!(term.text(), "a*b* c?");
+        assert!(!is_masked(&term.chars[..3]));
+        assert!(is_masked(&term.chars[..4]));
+        let words: Vec<String> = term.words().iter().map(|w| text(w)).collect();
+        assert_eq!(words, ["a*b*", "c?"]);
+        assert_eq!(Term::new("  ").words().len(), 0);
+        assert_eq!(Term::new("^").chars, []);
+    }
+
+    #[test]
+    fn masking() {
+        let term = Term::new("**a?b*");
+        let (chars, start, end) = term.trim_stars();
+        assert_eq!(chars, Term::new("a?b").chars);
+        assert_eq!((start, end), (false, false));
+        let term = Term::new("^a*");
+        assert_eq!(term.trim_stars(), (&term.chars[..1], true, false));
+        let term = Term::new("*");
+        assert_eq!(term.trim_stars(), (&[][..], false, false));
+        assert!(is_masked(&term.chars));
+        assert!(!is_masked(&Term::new("a\\*").chars));
+        assert_eq!(regex(&Term::new("a.b?*").chars, false, false), "a\\.b..*");
+        assert_eq!(regex(&Term::new("(a)").chars, true, true), "^\\(a\\)$");
+    }
+}
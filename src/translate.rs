@@ -0,0 +1,829 @@
+//! Framework for translating CQL to other query languages.
+//!
+//! A backend implements [`QueryTranslator`], with callbacks for search
+//! clauses and booleans, and [`SortTranslator`] if it has sort keys.
+//! [`QueryTranslator::translate`] walks the tree bottom up, so a boolean
+//! gets its operands already translated, each with the node it came
+//! from. Error nodes, unknown operators and modifiers the backend does
+//! not accept are reported by the walk.
+//!
+//! [`Clause`] gives the callback a search clause with its term split
+//! into literal characters and masking, its relation without the `cql`
+//! context set, and whether it is negated, and checks for anchoring and
+//! masking the backend cannot express. [`Clause::words`] splits the
+//! term for `all` and `any`, and is the one place a term without words
+//! is rejected. [`crate::term`] has the helpers for masking the backend
+//! can express: as regular expressions, or with leading and trailing `*`
+//! trimmed for prefix and suffix matches. [`Mapping`] holds the backend
+//! fields, columns or paths of indexes, and the relations to search
+//! others as. Problems are reported as a [`TranslationError`] with the
+//! span of the index, relation, term or modifier at fault, which the
+//! built-in translators return as is; PQF wraps it in
+//! [`PqfError`](crate::pqf::PqfError) with its other errors.
+//!
+//! ```
+//! use cql_rust::node::Boolean;
+//! use cql_rust::node::CqlNode;
+//! use cql_rust::translate::*;
+//!
+//! /// Lisp-like expressions, such as `(and (= title "a") (= year "b"))`.
+//! struct Lisp {
+//!     fields: Mapping<String>,
+//! }
+//!
+//! impl QueryTranslator for Lisp {
+//!     type Output = String;
+//!     type Error = TranslationError;
+//!
+//!     fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+//!         let field = self
+//!             .fields
+//!             .get(clause.index())
+//!             .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+//!         let relation = clause.relation()?;
+//!         Ok(format!("({} {} {:?})", relation, field, clause.term().text()))
+//!     }
+//!
+//!     fn boolean(
+//!         &mut self,
+//!         _bo: &Boolean,
+//!         op: BooleanOp,
+//!         operands: Vec<(&CqlNode, String)>,
+//!     ) -> Result<String, TranslationError> {
+//!         let operands: Vec<String> = operands.into_iter().map(|(_, s)| s).collect();
+//!         Ok(format!("({} {})", op, operands.join(" ")))
+//!     }
+//!
+//!     fn flatten(&self, op: BooleanOp) -> bool {
+//!         op == BooleanOp::And
+//!     }
+//! }
+//!
+//! let mut lisp = Lisp { fields: Mapping::new() };
+//! lisp.fields.insert("title", String::from("ti"));
+//! let node = cql_rust::parse("title = a and title = b* and title = c").unwrap();
+//! assert_eq!(
+//!     lisp.translate(&node).unwrap(),
+//!     "(and (= ti \"a\") (= ti \"b*\") (= ti \"c\"))"
+//! );
+//! let node = cql_rust::parse("title = a or year = 1999").unwrap();
+//! let e = lisp.translate(&node).unwrap_err();
+//! assert_eq!(e.kind(), TranslationErrorKind::UnsupportedIndex);
+//! assert_eq!(e.span().start.offset, 13);
+//! ```
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::St;
+use crate::span::Span;
+use crate::term::is_masked;
+use crate::term::Term;
+use crate::term::TermChar;
+use std::fmt;
+
+/// The kind of a [`TranslationError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TranslationErrorKind {
+    /// The index is not mapped.
+    UnsupportedIndex,
+    UnsupportedRelation,
+    UnsupportedRelationModifier,
+    /// The term is anchored with `^` where the backend cannot express it.
+    UnsupportedAnchoring,
+    /// The term is masked where the backend cannot express it.
+    UnsupportedMasking,
+    /// The term is not a number, but the field is numeric.
+    InvalidNumber,
+    /// The term is not a literal of the type of the field.
+    InvalidLiteral,
+    /// The term has no words to search for.
+    EmptyTerm,
+    /// The term is a phrase, but the field has no positions indexed.
+    PositionsNotIndexed,
+    /// The tokenizer of the field is not registered.
+    UnknownTokenizer,
+    /// An unknown boolean operator, or one the backend cannot express.
+    UnsupportedBoolean,
+    UnsupportedProxModifier,
+    UnsupportedSortModifier,
+    /// The tree contains an error node from a recovering parse.
+    InvalidQuery,
+}
+
+/// Error from translating a query, with the part of the query at fault.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TranslationError {
+    kind: TranslationErrorKind,
+    text: String,
+    span: Span,
+}
+
+impl TranslationError {
+    pub fn new(kind: TranslationErrorKind, text: &str, span: Span) -> TranslationError {
+        TranslationError {
+            kind,
+            text: String::from(text),
+            span,
+        }
+    }
+
+    /// Error of `kind` for modifier `m`.
+    pub fn modifier(kind: TranslationErrorKind, m: &Modifier) -> TranslationError {
+        TranslationError::new(kind, m.name(), m.span())
+    }
+
+    /// Unsupported index of a search clause or sort key.
+    pub fn index(st: &St) -> TranslationError {
+        let span = st.index_span().unwrap_or(st.span());
+        TranslationError::new(TranslationErrorKind::UnsupportedIndex, st.index(), span)
+    }
+
+    /// Unsupported boolean operator of `bo`.
+    pub fn boolean(bo: &Boolean) -> TranslationError {
+        TranslationError::new(
+            TranslationErrorKind::UnsupportedBoolean,
+            bo.value(),
+            bo.span(),
+        )
+    }
+
+    pub fn kind(&self) -> TranslationErrorKind {
+        self.kind
+    }
+
+    /// The index, relation, term, modifier or operator at fault, or the
+    /// message of an error node.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.text;
+        match self.kind {
+            TranslationErrorKind::UnsupportedIndex => write!(f, "unsupported index {}", s),
+            TranslationErrorKind::UnsupportedRelation => write!(f, "unsupported relation {}", s),
+            TranslationErrorKind::UnsupportedRelationModifier => {
+                write!(f, "unsupported relation modifier {}", s)
+            }
+            TranslationErrorKind::UnsupportedAnchoring => {
+                write!(f, "unsupported anchoring in {}", s)
+            }
+            TranslationErrorKind::UnsupportedMasking => write!(f, "unsupported masking in {}", s),
+            TranslationErrorKind::InvalidNumber => write!(f, "invalid number {}", s),
+            TranslationErrorKind::InvalidLiteral => write!(f, "invalid literal {}", s),
+            TranslationErrorKind::EmptyTerm => write!(f, "no words to search for in \"{}\"", s),
+            TranslationErrorKind::PositionsNotIndexed => {
+                write!(f, "field {} has no positions for phrases", s)
+            }
+            TranslationErrorKind::UnknownTokenizer => write!(f, "unknown tokenizer {}", s),
+            TranslationErrorKind::UnsupportedBoolean => {
+                write!(f, "unsupported boolean operator {}", s)
+            }
+            TranslationErrorKind::UnsupportedProxModifier => {
+                write!(f, "unsupported prox modifier {}", s)
+            }
+            TranslationErrorKind::UnsupportedSortModifier => {
+                write!(f, "unsupported sort modifier {}", s)
+            }
+            TranslationErrorKind::InvalidQuery => write!(f, "query has errors: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for TranslationError {}
+
+#[cfg(test)]
+impl TranslationError {
+    /// The kind and text, to compare errors without their spans.
+    pub(crate) fn parts(&self) -> (TranslationErrorKind, &str) {
+        (self.kind, &self.text)
+    }
+}
+
+/// Name without the `cql` context set, as lower case. `None` for names
+/// in other context sets.
+pub fn cql_name(name: &str) -> Option<String> {
+    match name.split_once('.') {
+        Some((prefix, name)) if prefix.eq_ignore_ascii_case("cql") => {
+            Some(name.to_ascii_lowercase())
+        }
+        Some(_) => None,
+        None => Some(name.to_ascii_lowercase()),
+    }
+}
+
+/// Name without the context set, as lower case.
+pub fn unqualified(name: &str) -> String {
+    match name.split_once('.') {
+        Some((_, name)) => name.to_ascii_lowercase(),
+        None => name.to_ascii_lowercase(),
+    }
+}
+
+/// The entry for `name`: an exact match, or else one given without a
+/// context set.
+fn lookup<'a, T>(entries: &'a [(String, T)], name: &str) -> Option<&'a T> {
+    let exact = entries.iter().find(|(n, _)| n.eq_ignore_ascii_case(name));
+    let res = exact.or_else(|| {
+        let name = unqualified(name);
+        entries
+            .iter()
+            .find(|(n, _)| !n.contains('.') && n.eq_ignore_ascii_case(&name))
+    });
+    res.map(|(_, value)| value)
+}
+
+/// Relations to search other relations as, looked up as the indexes of
+/// a [`Mapping`]. Held by every `Mapping`, and on their own by backends
+/// that look indexes up themselves.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Relations(Vec<(String, String)>);
+
+impl Relations {
+    pub(crate) fn insert(&mut self, name: &str, relation: &str) {
+        let relation = cql_name(relation).unwrap_or_else(|| relation.to_ascii_lowercase());
+        self.0.push((String::from(name), relation));
+    }
+
+    /// The relation to search for `clause`: its mapping, or else
+    /// [`Clause::relation`].
+    pub(crate) fn get(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        match lookup(&self.0, clause.st().relation()) {
+            Some(relation) => Ok(relation.clone()),
+            None => clause.relation(),
+        }
+    }
+}
+
+/// Values for index names, such as the fields they are searched in, and
+/// the relations to search other relations as.
+///
+/// A name given without a context set, such as `title`, matches it in
+/// any set, but qualified names take precedence. Names are case
+/// insensitive. `cql.serverChoice`, unless mapped itself, resolves to
+/// the values given to [`Mapping::server_choice`].
+///
+/// Relations are mapped to one of the `cql` relations the backend
+/// matches, such as `bib.contains` to `any` or `=` to `all`, and looked
+/// up by name in the same way. Those not mapped are normalized by
+/// [`Clause::relation`]. Backends that resolve context sets by URI
+/// rather than by prefix, such as SPARQL, look indexes up themselves.
+#[derive(Debug, Clone)]
+pub struct Mapping<T> {
+    entries: Vec<(String, T)>,
+    server_choice: Vec<T>,
+    relations: Relations,
+}
+
+impl<T> Default for Mapping<T> {
+    fn default() -> Self {
+        Mapping {
+            entries: Vec::new(),
+            server_choice: Vec::new(),
+            relations: Relations::default(),
+        }
+    }
+}
+
+impl<T> Mapping<T> {
+    pub fn new() -> Mapping<T> {
+        Mapping::default()
+    }
+
+    /// Map `name` to `value`. An earlier mapping of the same name takes
+    /// precedence.
+    pub fn insert(&mut self, name: &str, value: T) {
+        self.entries.push((String::from(name), value));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        lookup(&self.entries, name)
+    }
+
+    /// Values searched for `cql.serverChoice` unless it is mapped.
+    pub fn server_choice(&mut self, values: Vec<T>) {
+        self.server_choice = values;
+    }
+
+    /// The values for `name`: its mapping, or for `cql.serverChoice`
+    /// those given to [`Mapping::server_choice`], which may be none.
+    pub fn resolve(&self, name: &str) -> Option<Vec<&T>> {
+        if let Some(value) = self.get(name) {
+            return Some(vec![value]);
+        }
+        if name.eq_ignore_ascii_case("cql.serverChoice") {
+            return Some(self.server_choice.iter().collect());
+        }
+        None
+    }
+
+    /// Search relation `name` as `relation`, given with or without the
+    /// `cql` context set. An earlier mapping of the same name takes
+    /// precedence.
+    pub fn insert_relation(&mut self, name: &str, relation: &str) {
+        self.relations.insert(name, relation);
+    }
+
+    /// The relation to search for `clause`: its mapping, or else
+    /// [`Clause::relation`].
+    pub fn relation(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.relations.get(clause)
+    }
+
+    /// Whether no indexes are mapped.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A search clause being translated.
+#[derive(Debug, Clone)]
+pub struct Clause<'a> {
+    st: &'a St,
+    term: Term,
+    negated: bool,
+}
+
+impl<'a> Clause<'a> {
+    pub fn new(st: &'a St, negated: bool) -> Clause<'a> {
+        Clause {
+            st,
+            term: Term::new(st.term().unwrap_or("")),
+            negated,
+        }
+    }
+
+    pub fn st(&self) -> &'a St {
+        self.st
+    }
+
+    pub fn index(&self) -> &'a str {
+        self.st.index()
+    }
+
+    /// The term as written in the query.
+    pub fn text(&self) -> &'a str {
+        self.st.term().unwrap_or("")
+    }
+
+    /// The term split into literal characters and masking.
+    pub fn term(&self) -> &Term {
+        &self.term
+    }
+
+    /// Whether the clause is in the right operand of a `not`.
+    pub fn negated(&self) -> bool {
+        self.negated
+    }
+
+    /// The relation as lower case, without the `cql` context set. Other
+    /// context sets are unsupported.
+    pub fn relation(&self) -> Result<String, TranslationError> {
+        cql_name(self.st.relation())
+            .ok_or_else(|| self.error(TranslationErrorKind::UnsupportedRelation))
+    }
+
+    /// Fail if the term is anchored with `^`.
+    pub fn unanchored(&self) -> Result<(), TranslationError> {
+        if self.term.first || self.term.last {
+            return Err(self.error(TranslationErrorKind::UnsupportedAnchoring));
+        }
+        Ok(())
+    }
+
+    /// Fail if the term is masked.
+    pub fn unmasked(&self) -> Result<(), TranslationError> {
+        if is_masked(&self.term.chars) {
+            return Err(self.error(TranslationErrorKind::UnsupportedMasking));
+        }
+        Ok(())
+    }
+
+    /// The words of the term, as searched by `all` and `any`. A term of
+    /// only whitespace has nothing to search for, and is
+    /// [`TranslationErrorKind::EmptyTerm`] in every backend.
+    pub fn words(&self) -> Result<Vec<&[TermChar]>, TranslationError> {
+        let words = self.term.words();
+        if words.is_empty() {
+            return Err(self.error(TranslationErrorKind::EmptyTerm));
+        }
+        Ok(words)
+    }
+
+    /// Fail on the first relation modifier, if any.
+    pub fn no_modifiers(&self) -> Result<(), TranslationError> {
+        match self.st.modifiers().first() {
+            Some(m) => Err(TranslationError::modifier(
+                TranslationErrorKind::UnsupportedRelationModifier,
+                m,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Error of `kind` for the index, relation or term of the clause.
+    /// Other kinds of errors are given the term.
+    pub fn error(&self, kind: TranslationErrorKind) -> TranslationError {
+        let st = self.st;
+        let (text, span) = match kind {
+            TranslationErrorKind::UnsupportedIndex => return TranslationError::index(st),
+            TranslationErrorKind::UnsupportedRelation => (st.relation(), st.relation_span()),
+            _ => (self.text(), st.term_span()),
+        };
+        TranslationError::new(kind, text, span.unwrap_or(st.span()))
+    }
+}
+
+/// A boolean operator of CQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BooleanOp {
+    And,
+    Or,
+    Not,
+    Prox,
+}
+
+impl BooleanOp {
+    /// The operator of `bo`, unless unknown.
+    pub fn of(bo: &Boolean) -> Option<BooleanOp> {
+        match bo.value().to_ascii_lowercase().as_str() {
+            "and" => Some(BooleanOp::And),
+            "or" => Some(BooleanOp::Or),
+            "not" => Some(BooleanOp::Not),
+            "prox" => Some(BooleanOp::Prox),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BooleanOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BooleanOp::And => "and",
+            BooleanOp::Or => "or",
+            BooleanOp::Not => "not",
+            BooleanOp::Prox => "prox",
+        })
+    }
+}
+
+/// Translation of the search part of a query.
+pub trait QueryTranslator {
+    type Output;
+    type Error: From<TranslationError>;
+
+    /// Translate a search clause.
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<Self::Output, Self::Error>;
+
+    /// Combine the translated operands of `bo`, each given with its
+    /// node: the left and right operand, unless gathered as told by
+    /// [`QueryTranslator::flatten`].
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        operands: Vec<(&CqlNode, Self::Output)>,
+    ) -> Result<Self::Output, Self::Error>;
+
+    /// Whether booleans with `op`, and with modifiers if `modified`, are
+    /// passed to [`QueryTranslator::boolean`]; others are unsupported.
+    /// By default `and`, `or` and `not` without modifiers.
+    fn accepts(&self, op: BooleanOp, modified: bool) -> bool {
+        op != BooleanOp::Prox && !modified
+    }
+
+    /// Whether booleans with `op` gather the operands of nested booleans
+    /// with the same operator and no modifiers into one call. For `not`,
+    /// the left operand is followed by those gathered from the right one
+    /// as for `or`, as excluding `b or c` excludes each of them. By
+    /// default none are gathered.
+    fn flatten(&self, _op: BooleanOp) -> bool {
+        false
+    }
+
+    /// Translate the search part of `node`.
+    fn translate(&mut self, node: &CqlNode) -> Result<Self::Output, Self::Error>
+    where
+        Self: Sized,
+    {
+        search(self, node, false)
+    }
+}
+
+/// Translation of sort keys.
+pub trait SortTranslator: QueryTranslator {
+    type SortKey;
+
+    /// Translate a sort key, the index and modifiers of `key`.
+    fn sort_key(&mut self, key: &St) -> Result<Self::SortKey, Self::Error>;
+
+    /// Translate the sort keys of `node`, most significant first.
+    fn translate_sort(&mut self, node: &CqlNode) -> Result<Vec<Self::SortKey>, Self::Error> {
+        match node {
+            CqlNode::Root(root) => root.sort().iter().map(|key| self.sort_key(key)).collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Whether sort modifier `m` is `sort.descending` rather than
+/// `sort.ascending`. Other modifiers are unsupported.
+pub fn descending(m: &Modifier) -> Result<bool, TranslationError> {
+    match (unqualified(m.name()).as_str(), m.relation()) {
+        ("ascending", None) => Ok(false),
+        ("descending", None) => Ok(true),
+        _ => Err(TranslationError::modifier(
+            TranslationErrorKind::UnsupportedSortModifier,
+            m,
+        )),
+    }
+}
+
+/// Whether missing values sort high, for sort modifier
+/// `sort.missingValue=highValue` or `lowValue`. `None` for other
+/// modifiers.
+pub fn missing_value(m: &Modifier) -> Option<bool> {
+    if unqualified(m.name()) != "missingvalue" || m.relation() != Some("=") {
+        return None;
+    }
+    match m.value().map(str::to_ascii_lowercase).as_deref() {
+        Some("highvalue") => Some(true),
+        Some("lowvalue") => Some(false),
+        _ => None,
+    }
+}
+
+/// The translated operands joined by `op`, with those of booleans in
+/// parentheses, for backends with infix operators.
+pub fn infix(operands: Vec<(&CqlNode, String)>, op: &str) -> String {
+    let operands: Vec<String> = operands
+        .into_iter()
+        .map(|(node, s)| match node {
+            CqlNode::Boolean(_) => format!("({})", s),
+            _ => s,
+        })
+        .collect();
+    operands.join(op)
+}
+
+fn search<'a, T: QueryTranslator>(
+    translator: &mut T,
+    node: &'a CqlNode,
+    negated: bool,
+) -> Result<T::Output, T::Error> {
+    match node {
+        CqlNode::St(st) => translator.clause(&Clause::new(st, negated)),
+        CqlNode::Boolean(bo) => {
+            let op = BooleanOp::of(bo)
+                .filter(|op| translator.accepts(*op, !bo.modifiers().is_empty()))
+                .ok_or_else(|| TranslationError::boolean(bo))?;
+            let mut operands: Vec<(&'a CqlNode, T::Output)> = Vec::new();
+            match op {
+                BooleanOp::Not if translator.flatten(op) => {
+                    operands.push((bo.left(), search(translator, bo.left(), negated)?));
+                    gather(translator, bo.right(), BooleanOp::Or, true, &mut operands)?;
+                }
+                BooleanOp::And | BooleanOp::Or if translator.flatten(op) => {
+                    gather(translator, node, op, negated, &mut operands)?;
+                }
+                _ => {
+                    let right_negated = negated || op == BooleanOp::Not;
+                    operands.push((bo.left(), search(translator, bo.left(), negated)?));
+                    operands.push((bo.right(), search(translator, bo.right(), right_negated)?));
+                }
+            }
+            translator.boolean(bo, op, operands)
+        }
+        CqlNode::Root(root) => search(translator, root.search(), negated),
+        CqlNode::Error(e) => {
+            let kind = TranslationErrorKind::InvalidQuery;
+            Err(TranslationError::new(kind, e.message(), e.span()).into())
+        }
+    }
+}
+
+/// Add the operands of `node` to `out`, gathering nested booleans with
+/// `op`.
+fn gather<'a, T: QueryTranslator>(
+    translator: &mut T,
+    node: &'a CqlNode,
+    op: BooleanOp,
+    negated: bool,
+    out: &mut Vec<(&'a CqlNode, T::Output)>,
+) -> Result<(), T::Error> {
+    match node {
+        CqlNode::Boolean(bo) if BooleanOp::of(bo) == Some(op) && bo.modifiers().is_empty() => {
+            gather(translator, bo.left(), op, negated, out)?;
+            gather(translator, bo.right(), op, negated, out)
+        }
+        _ => {
+            out.push((node, search(translator, node, negated)?));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clauses as their term, `!` when negated, and booleans in prefix
+    /// form.
+    #[derive(Default)]
+    struct Prefix {
+        flatten: bool,
+    }
+
+    impl QueryTranslator for Prefix {
+        type Output = String;
+        type Error = TranslationError;
+
+        fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+            clause.no_modifiers()?;
+            if clause.relation()? != "=" {
+                return Err(clause.error(TranslationErrorKind::UnsupportedRelation));
+            }
+            let negated = if clause.negated() { "!" } else { "" };
+            Ok(format!("{}{}", negated, clause.term().text()))
+        }
+
+        fn boolean(
+            &mut self,
+            _bo: &Boolean,
+            op: BooleanOp,
+            operands: Vec<(&CqlNode, String)>,
+        ) -> Result<String, TranslationError> {
+            let operands: Vec<String> = operands.into_iter().map(|(_, s)| s).collect();
+            Ok(format!("({} {})", op, operands.join(" ")))
+        }
+
+        fn flatten(&self, _op: BooleanOp) -> bool {
+            self.flatten
+        }
+    }
+
+    impl SortTranslator for Prefix {
+        type SortKey = (String, bool);
+
+        fn sort_key(&mut self, key: &St) -> Result<(String, bool), TranslationError> {
+            let mut res = false;
+            for m in key.modifiers() {
+                res = descending(m)?;
+            }
+            Ok((String::from(key.index()), res))
+        }
+    }
+
+    fn translate(query: &str, flatten: bool) -> Result<String, TranslationError> {
+        Prefix { flatten }.translate(&crate::parse(query).unwrap())
+    }
+
+    #[test]
+    fn walk() {
+        assert_eq!(translate("a and b", false).unwrap(), "(and a b)");
+        assert_eq!(
+            translate("a and b and (c or d or e)", false).unwrap(),
+            "(and (and a b) (or (or c d) e))"
+        );
+        assert_eq!(
+            translate("a and b and (c or d or e)", true).unwrap(),
+            "(and a b (or c d e))"
+        );
+        assert_eq!(
+            translate("a not (b or c not d)", false).unwrap(),
+            "(not a (not (or !b !c) !d))"
+        );
+        assert_eq!(
+            translate("a not (b or (c and d) or e)", true).unwrap(),
+            "(not a !b (and !c !d) !e)"
+        );
+        assert_eq!(
+            translate("a and/x b and c", true).unwrap_err(),
+            TranslationError::new(
+                TranslationErrorKind::UnsupportedBoolean,
+                "and",
+                crate::parse("a and/x b").unwrap().span()
+            )
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let e = translate("a or i x.foo b", false).unwrap_err();
+        assert_eq!(e.kind(), TranslationErrorKind::UnsupportedRelation);
+        assert_eq!(e.text(), "x.foo");
+        assert_eq!(e.span().source("a or i x.foo b"), Some("x.foo"));
+        let e = translate("a prox b", false).unwrap_err();
+        assert_eq!(e.kind(), TranslationErrorKind::UnsupportedBoolean);
+        assert_eq!(e.text(), "prox");
+        let e = translate("a =/stem b", false).unwrap_err();
+        assert_eq!(e.kind(), TranslationErrorKind::UnsupportedRelationModifier);
+        assert_eq!(e.span().source("a =/stem b"), Some("/stem"));
+        let node = crate::parser::Parser::new()
+            .parse_recovering(&mut "a and (".chars())
+            .0;
+        let e = Prefix::default().translate(&node).unwrap_err();
+        assert_eq!(e.kind(), TranslationErrorKind::InvalidQuery);
+        assert_eq!(e.to_string(), format!("query has errors: {}", e.text()));
+    }
+
+    #[test]
+    fn sort() {
+        let node = crate::parse("a sortby dc.date/sort.descending title").unwrap();
+        assert_eq!(
+            Prefix::default().translate_sort(&node).unwrap(),
+            [
+                (String::from("dc.date"), true),
+                (String::from("title"), false)
+            ]
+        );
+        let node = crate::parse("a sortby date/missingValue=omit").unwrap();
+        let e = Prefix::default().translate_sort(&node).unwrap_err();
+        assert_eq!(e.kind(), TranslationErrorKind::UnsupportedSortModifier);
+        assert_eq!(e.text(), "missingValue");
+    }
+
+    #[test]
+    fn missing_values() {
+        let node = crate::parse(
+            "a sortby x/sort.missingValue=highValue/missingValue=LowValue/missingValue=omit",
+        )
+        .unwrap();
+        let CqlNode::Root(root) = node else {
+            panic!("{:?}", node)
+        };
+        let values: Vec<Option<bool>> = root.sort()[0]
+            .modifiers()
+            .iter()
+            .map(missing_value)
+            .collect();
+        assert_eq!(values, [Some(true), Some(false), None]);
+    }
+
+    #[test]
+    fn mapping() {
+        let mut mapping = Mapping::new();
+        assert!(mapping.is_empty());
+        mapping.insert("title", 1);
+        mapping.insert("dc.title", 2);
+        mapping.insert("dc.title", 3);
+        assert_eq!(mapping.get("DC.Title"), Some(&2));
+        assert_eq!(mapping.get("bath.title"), Some(&1));
+        assert_eq!(mapping.get("Title"), Some(&1));
+        assert_eq!(mapping.get("creator"), None);
+        assert_eq!(mapping.resolve("title"), Some(vec![&1]));
+        assert_eq!(mapping.resolve("cql.serverChoice"), Some(vec![]));
+        mapping.server_choice(vec![4, 5]);
+        assert_eq!(mapping.resolve("CQL.serverchoice"), Some(vec![&4, &5]));
+        assert_eq!(mapping.resolve("creator"), None);
+        mapping.insert("cql.serverChoice", 6);
+        assert_eq!(mapping.resolve("cql.serverChoice"), Some(vec![&6]));
+    }
+
+    /// The relation `mapping` gives the clause of `query`.
+    fn relation(mapping: &Mapping<u8>, query: &str) -> Result<String, TranslationError> {
+        let node = crate::parse(query).unwrap();
+        let CqlNode::Root(root) = &node else {
+            unreachable!()
+        };
+        let CqlNode::St(st) = root.search() else {
+            unreachable!()
+        };
+        mapping.relation(&Clause::new(st, false))
+    }
+
+    #[test]
+    fn relation_mapping() {
+        let mut mapping = Mapping::new();
+        assert_eq!(relation(&mapping, "ti CQL.All a").unwrap(), "all");
+        assert_eq!(
+            relation(&mapping, "ti bib.contains a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "bib.contains")
+        );
+        mapping.insert_relation("bib.contains", "cql.any");
+        mapping.insert_relation("=", "All");
+        mapping.insert_relation("within", "<=");
+        mapping.insert_relation("x.within", "=");
+        mapping.insert_relation("=", "any");
+        assert!(mapping.is_empty());
+        assert_eq!(relation(&mapping, "ti BIB.Contains a").unwrap(), "any");
+        assert_eq!(relation(&mapping, "ti = a").unwrap(), "all");
+        assert_eq!(relation(&mapping, "a").unwrap(), "all");
+        assert_eq!(relation(&mapping, "ti y.within a").unwrap(), "<=");
+        assert_eq!(relation(&mapping, "ti x.within a").unwrap(), "=");
+        assert_eq!(relation(&mapping, "ti == a").unwrap(), "==");
+        assert_eq!(
+            relation(&mapping, "ti other.contains a")
+                .unwrap_err()
+                .parts(),
+            (TranslationErrorKind::UnsupportedRelation, "other.contains")
+        );
+    }
+}
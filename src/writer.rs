@@ -0,0 +1,533 @@
+//! Writing a [`CqlNode`] tree back as CQL text.
+//!
+//! The output is canonical: one space between index, relation and term,
+//! modifiers attached without spaces, lower-case keywords as given in the
+//! tree, and terms quoted only when needed. Parsing the output of a
+//! parsed tree gives a tree equal to it.
+//!
+//! Terms and modifier values are kept in the tree as written, with
+//! backslash escapes, so `\"` in a term is written unchanged and only
+//! unescaped quotes are escaped when the term has to be quoted.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::Prefix;
+use crate::node::St;
+use crate::parser::resolve;
+use crate::parser::resolves;
+use std::borrow::Cow;
+use std::fmt;
+
+/// How many parentheses to write around boolean operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Parentheses {
+    /// Only where needed to keep the tree shape.
+    #[default]
+    Minimal,
+    /// Around every boolean, including the outermost one.
+    Full,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    parentheses: Parentheses,
+}
+
+impl WriteOptions {
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    pub fn parentheses(mut self, parentheses: Parentheses) -> WriteOptions {
+        self.parentheses = parentheses;
+        self
+    }
+}
+
+fn is_keyword(s: &str) -> bool {
+    ["and", "or", "not", "prox", "sortby"]
+        .iter()
+        .any(|k| s.eq_ignore_ascii_case(k))
+}
+
+/// Quote `term` if it would not be read back as one term otherwise.
+///
+/// Quotes inside an unquoted word are part of the term, so they are only
+/// escaped when the term must be quoted anyway.
+pub(crate) fn quote_term(term: &str) -> Cow<'_, str> {
+    let plain = !term.is_empty()
+        && !is_keyword(term)
+        && !term.starts_with('"')
+        && !term
+            .chars()
+            .any(|ch| ch.is_whitespace() || "()=<>/".contains(ch));
+    if plain {
+        return Cow::Borrowed(term);
+    }
+    let mut res = String::from("\"");
+    let mut escaped = false;
+    for ch in term.chars() {
+        if ch == '"' && !escaped {
+            res.push('\\');
+        }
+        escaped = ch == '\\' && !escaped;
+        res.push(ch);
+    }
+    // a dangling backslash would escape the closing quote
+    if escaped {
+        res.push('\\');
+    }
+    res.push('"');
+    Cow::Owned(res)
+}
+
+/// The clause a bare term stands for at the top level, with the index
+/// resolved under the top-level prefix assignments as the parser does.
+fn server_choice(prefixes: &[Prefix]) -> St {
+    let rel = CqlNode::mk_sc("cql.serverChoice", "=", None, Vec::new());
+    resolve(prefixes, rel, "cql.serverChoice", "=")
+}
+
+/// Whether `st` is `rel` with some term, so that the term alone stands
+/// for it. Clauses built without URIs are compared by name only.
+fn same_clause(st: &St, rel: &St) -> bool {
+    let unresolved = st.index_uri().is_none() && st.relation_uri().is_none();
+    st.index() == rel.index()
+        && st.relation() == rel.relation()
+        && st.modifiers() == rel.modifiers()
+        && (unresolved
+            || st.index_uri() == rel.index_uri() && st.relation_uri() == rel.relation_uri())
+}
+
+/// The search clauses under `node`, left to right.
+fn clauses<'a>(node: &'a CqlNode, res: &mut Vec<&'a St>) {
+    match node {
+        CqlNode::St(st) => res.push(st),
+        CqlNode::Boolean(bo) => {
+            clauses(bo.left(), res);
+            clauses(bo.right(), res);
+        }
+        CqlNode::Root(root) => clauses(root.search(), res),
+        CqlNode::Error(_) => {}
+    }
+}
+
+/// Where to write the prefix assignments of `node`, read under `scope`
+/// with bare terms standing for `rel`, so that parsing gives it back.
+///
+/// Assignments in front of a clause also scope over its index and
+/// relation, so those the clause got its index outside of go in
+/// parentheses after the index instead, as in `ti = (>"http://d" x)`.
+/// Returns how many assignments go in front and, if not all, the clause
+/// whose index and relation go before the rest. `None` if the tree
+/// cannot be written so.
+fn placement<'a>(node: &'a CqlNode, scope: &[Prefix], rel: &St) -> Option<(usize, Option<&'a St>)> {
+    let prefixes = match node {
+        CqlNode::St(st) => st.prefixes(),
+        CqlNode::Boolean(bo) => bo.prefixes(),
+        CqlNode::Root(root) => return placement(root.search(), scope, rel),
+        CqlNode::Error(_) => return Some((0, None)),
+    };
+    let inner = [scope, prefixes].concat();
+    let body = |rel: &St| match node {
+        CqlNode::St(st) => same_clause(st, rel) || resolves(&inner, st),
+        CqlNode::Boolean(bo) => {
+            placement(bo.left(), &inner, rel).is_some()
+                && placement(bo.right(), &inner, rel).is_some()
+        }
+        _ => true,
+    };
+    if body(rel) {
+        return Some((prefixes.len(), None));
+    }
+    let mut heads = Vec::new();
+    clauses(node, &mut heads);
+    (0..prefixes.len()).rev().find_map(|front| {
+        let outer = [scope, &prefixes[..front]].concat();
+        heads
+            .iter()
+            .find(|st| resolves(&outer, st) && body(st))
+            .map(|st| (front, Some(*st)))
+    })
+}
+
+struct Writer<'a> {
+    options: &'a WriteOptions,
+    out: String,
+    /// Prefix assignments written around the current node.
+    scope: Vec<Prefix>,
+    /// The clause a bare term stands for at the current node.
+    rel: St,
+}
+
+impl Writer<'_> {
+    fn prefixes(&mut self, prefixes: &[Prefix]) {
+        for p in prefixes {
+            self.out.push('>');
+            if let Some(name) = p.name() {
+                self.out.push_str(&quote_term(name));
+                self.out.push('=');
+            }
+            self.out.push('"');
+            self.out.push_str(&p.uri().replace('"', "\\\""));
+            self.out.push_str("\" ");
+        }
+    }
+
+    fn modifiers(&mut self, modifiers: &[Modifier]) {
+        for m in modifiers {
+            self.out.push('/');
+            self.out.push_str(&quote_term(m.name()));
+            if let (Some(relation), Some(value)) = (m.relation(), m.value()) {
+                self.out.push_str(relation);
+                self.out.push_str(&quote_term(value));
+            }
+        }
+    }
+
+    /// Where to write the prefix assignments of `node`. Where no place
+    /// keeps the tree, they all go in front as written.
+    fn placement<'a>(&self, node: &'a CqlNode) -> (usize, Option<&'a St>) {
+        placement(node, &self.scope, &self.rel).unwrap_or_else(|| match node {
+            CqlNode::St(st) => (st.prefixes().len(), None),
+            CqlNode::Boolean(bo) => (bo.prefixes().len(), None),
+            _ => (0, None),
+        })
+    }
+
+    fn index(&mut self, st: &St) {
+        self.out.push_str(&quote_term(st.index()));
+        self.out.push(' ');
+        self.out.push_str(st.relation());
+        self.modifiers(st.modifiers());
+        self.out.push(' ');
+    }
+
+    fn st(&mut self, st: &St) {
+        // a bare term stands for the clause the parser fills in
+        if !same_clause(st, &self.rel) {
+            self.index(st);
+        }
+        self.out.push_str(&quote_term(st.term().unwrap_or("")));
+    }
+
+    fn boolean(&mut self, bo: &Boolean) {
+        let mark = self.scope.len();
+        self.scope.extend_from_slice(bo.prefixes());
+        self.operand(bo.left(), false);
+        self.out.push(' ');
+        self.out.push_str(bo.value());
+        self.modifiers(bo.modifiers());
+        self.out.push(' ');
+        self.operand(bo.right(), true);
+        self.scope.truncate(mark);
+    }
+
+    /// Write `node` as an operand of a boolean. Operands on the right
+    /// need parentheses around booleans, since booleans associate to the
+    /// left, and operands with prefix assignments in front always need
+    /// them.
+    fn operand(&mut self, node: &CqlNode, right: bool) {
+        let parens = match (node, self.placement(node)) {
+            (CqlNode::St(_), (front, _)) => front > 0,
+            (CqlNode::Boolean(_), (front, None)) => {
+                right || front > 0 || self.options.parentheses == Parentheses::Full
+            }
+            (CqlNode::Boolean(_), (front, Some(_))) => front > 0,
+            (CqlNode::Root(root), _) => return self.operand(root.search(), right),
+            (CqlNode::Error(_), _) => false,
+        };
+        if parens {
+            self.out.push('(');
+            self.search(node);
+            self.out.push(')');
+        } else {
+            self.search(node);
+        }
+    }
+
+    fn search(&mut self, node: &CqlNode) {
+        let (front, head) = self.placement(node);
+        match node {
+            CqlNode::St(st) => {
+                let (outer, inner) = st.prefixes().split_at(front);
+                self.prefixes(outer);
+                let Some(head) = head else {
+                    return self.st(st);
+                };
+                self.index(head);
+                self.out.push('(');
+                self.prefixes(inner);
+                self.out.push_str(&quote_term(st.term().unwrap_or("")));
+                self.out.push(')');
+            }
+            CqlNode::Boolean(bo) => {
+                let (outer, inner) = bo.prefixes().split_at(front);
+                self.prefixes(outer);
+                let Some(head) = head else {
+                    return self.boolean(bo);
+                };
+                self.index(head);
+                self.out.push('(');
+                self.prefixes(inner);
+                let rel = std::mem::replace(&mut self.rel, head.clone());
+                self.boolean(bo);
+                self.rel = rel;
+                self.out.push(')');
+            }
+            CqlNode::Root(root) => self.search(root.search()),
+            // keep the output valid; the error was reported when parsing
+            CqlNode::Error(_) => self.out.push_str("\"\""),
+        }
+    }
+
+    fn root(&mut self, node: &CqlNode) {
+        let CqlNode::Root(root) = node else {
+            return self.top(node);
+        };
+        // sort keys are read under the top-level prefix assignments, so
+        // assignments they were read outside of go in parentheses
+        let prefixes = match root.search() {
+            CqlNode::St(st) => st.prefixes(),
+            CqlNode::Boolean(bo) => bo.prefixes(),
+            _ => &[],
+        };
+        if root.sort().iter().all(|key| resolves(prefixes, key)) {
+            self.top(root.search());
+        } else {
+            self.rel = server_choice(&[]);
+            self.out.push('(');
+            self.search(root.search());
+            self.out.push(')');
+        }
+        if !root.sort().is_empty() {
+            self.out.push_str(" sortby");
+            for key in root.sort() {
+                self.out.push(' ');
+                self.out.push_str(&quote_term(key.index()));
+                self.modifiers(key.modifiers());
+            }
+        }
+    }
+
+    fn top(&mut self, node: &CqlNode) {
+        let prefixes = match node {
+            CqlNode::St(st) => st.prefixes(),
+            CqlNode::Boolean(bo) => bo.prefixes(),
+            _ => &[],
+        };
+        self.rel = server_choice(prefixes);
+        match node {
+            CqlNode::Boolean(bo)
+                if self.options.parentheses == Parentheses::Full
+                    && self.placement(node).1.is_none() =>
+            {
+                self.prefixes(bo.prefixes());
+                self.out.push('(');
+                self.boolean(bo);
+                self.out.push(')');
+            }
+            _ => self.search(node),
+        }
+    }
+}
+
+impl CqlNode {
+    /// Write the tree as CQL with minimal parentheses.
+    ///
+    /// Error nodes left by [`Parser::parse_recovering`](crate::parser::Parser::parse_recovering)
+    /// are written as the empty term `""`, so that the output is always
+    /// valid CQL.
+    pub fn to_cql(&self) -> String {
+        self.to_cql_with(&WriteOptions::default())
+    }
+
+    pub fn to_cql_with(&self, options: &WriteOptions) -> String {
+        let mut writer = Writer {
+            options,
+            out: String::new(),
+            scope: Vec::new(),
+            rel: server_choice(&[]),
+        };
+        writer.root(self);
+        writer.out
+    }
+}
+
+impl fmt::Display for CqlNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_cql())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use std::borrow::BorrowMut;
+
+    fn canonical(query: &str) -> String {
+        let node = crate::parse(query).unwrap();
+        let res = node.to_cql();
+        assert_eq!(crate::parse(&res), Ok(node), "{}", res);
+        res
+    }
+
+    fn full(query: &str) -> String {
+        let node = crate::parse(query).unwrap();
+        let options = WriteOptions::new().parentheses(Parentheses::Full);
+        let res = node.to_cql_with(&options);
+        assert_eq!(crate::parse(&res), Ok(node), "{}", res);
+        res
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(canonical("x"), "x");
+        assert_eq!(canonical("  ti=x "), "ti = x");
+        assert_eq!(canonical("cql.serverChoice = x"), "x");
+        assert_eq!(canonical("CQL.SERVERCHOICE = x"), "CQL.SERVERCHOICE = x");
+        assert_eq!(
+            canonical("cql.serverChoice =/cql.word x"),
+            "cql.serverChoice =/cql.word x"
+        );
+        assert_eq!(canonical("dc.title ADJ \"a b\""), "dc.title ADJ \"a b\"");
+        assert_eq!(canonical("ti<>\"\""), "ti <> \"\"");
+        assert_eq!(
+            canonical("ti =/a=1/b/c<\"x y\" z"),
+            "ti =/a=1/b/c<\"x y\" z"
+        );
+        assert_eq!(canonical("ti = (a)"), "ti = a");
+        assert_eq!(canonical("ti = \"and\""), "ti = \"and\"");
+        assert_eq!(canonical("\"a \\\"b\\\" c\""), "\"a \\\"b\\\" c\"");
+        assert_eq!(canonical("a\"b"), "a\"b");
+        assert_eq!(canonical("\"a=b\""), "\"a=b\"");
+        assert_eq!(canonical("é\\*"), "é\\*");
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(canonical("a AND b"), "a AND b");
+        assert_eq!(canonical("(a and b) or c"), "a and b or c");
+        assert_eq!(canonical("a and (b or c)"), "a and (b or c)");
+        assert_eq!(canonical("((a) not (b))"), "a not b");
+        assert_eq!(
+            canonical("a prox/unit=word/distance<=2 b"),
+            "a prox/unit=word/distance<=2 b"
+        );
+        assert_eq!(canonical("ti = (a or b)"), "ti = a or ti = b");
+        assert_eq!(full("a"), "a");
+        assert_eq!(full("a and b or c"), "((a and b) or c)");
+        assert_eq!(full("a and (b or c)"), "(a and (b or c))");
+    }
+
+    #[test]
+    fn prefixes_and_sort() {
+        assert_eq!(
+            canonical(">dc=\"http://dc\" dc.ti = x sortby dc.date/sort.descending ti"),
+            ">dc=\"http://dc\" dc.ti = x sortby dc.date/sort.descending ti"
+        );
+        assert_eq!(
+            canonical(">\"http://d\" a and (>dc=\"http://dc\" b or c) and (>x=y d)"),
+            ">\"http://d\" a and (>dc=\"http://dc\" b or c) and (>x=\"y\" d)"
+        );
+        assert_eq!(
+            canonical("(>dc=\"http://dc\" a or b) and c"),
+            "(>dc=\"http://dc\" a or b) and c"
+        );
+        assert_eq!(
+            full(">dc=\"http://dc\" a and b sortby x"),
+            ">dc=\"http://dc\" (a and b) sortby x"
+        );
+        assert_eq!(
+            canonical("(>\"http://d\" a) sortby ti"),
+            "(>\"http://d\" a) sortby ti"
+        );
+        assert_eq!(
+            full("(>sort=\"http://s\" a or b) sortby ti/ascending"),
+            "(>sort=\"http://s\" a or b) sortby ti/ascending"
+        );
+    }
+
+    #[test]
+    fn inner_prefixes() {
+        // the index is read outside the assignments, so it stays there
+        assert_eq!(
+            canonical("ti = (>\"http://d\" x)"),
+            "ti = (>\"http://d\" x)"
+        );
+        assert_eq!(canonical("dc.ti = (>dc=\"u\" x)"), "dc.ti = (>dc=\"u\" x)");
+        assert_eq!(
+            canonical("a and dc.ti =/dc.m=1 (>dc=\"u\" x)"),
+            "a and dc.ti =/dc.m=1 (>dc=\"u\" x)"
+        );
+        assert_eq!(
+            canonical("dc.ti = (>dc=\"u\" x or y) and z"),
+            "dc.ti = (>dc=\"u\" x or y) and z"
+        );
+        assert_eq!(
+            full("dc.ti = (>dc=\"u\" x or y)"),
+            "dc.ti = (>dc=\"u\" x or y)"
+        );
+        assert_eq!(
+            canonical("a or (>cql=\"u\" cql.serverChoice = x)"),
+            "a or (>cql=\"u\" cql.serverChoice = x)"
+        );
+        assert_eq!(
+            canonical(">\"http://d\" ti = (>\"http://e\" x or y)"),
+            ">\"http://d\" ti = (>\"http://e\" x or y)"
+        );
+        assert_eq!(
+            canonical("ti = (>\"http://d\" x or dc.ti = y) not z"),
+            "ti = (>\"http://d\" x or dc.ti = y) not z"
+        );
+        assert_eq!(
+            canonical("(>cql=\"u\" x)"),
+            "cql.serverChoice = (>cql=\"u\" x)"
+        );
+        // assignments that do not touch the index still go in front
+        assert_eq!(canonical("ti = (>dc=\"u\" x)"), ">dc=\"u\" ti = x");
+        assert_eq!(
+            canonical(">dc=\"u\" dc.ti = (>dc=\"u\" x)"),
+            ">dc=\"u\" >dc=\"u\" dc.ti = x"
+        );
+    }
+
+    #[test]
+    fn constructed() {
+        let st = CqlNode::mk_sc("title", "=", Some("say \"hi\""), Vec::new());
+        assert_eq!(CqlNode::St(st).to_string(), "title = \"say \\\"hi\\\"\"");
+
+        // a trailing backslash is a literal one, like an escaped one
+        let st = CqlNode::mk_sc("title", "=", Some("a b\\"), Vec::new());
+        let cql = CqlNode::St(st).to_string();
+        assert_eq!(cql, "title = \"a b\\\\\"");
+        let node = crate::parse(&cql).unwrap();
+        let CqlNode::Root(root) = &node else { panic!() };
+        let CqlNode::St(st) = root.search() else {
+            panic!()
+        };
+        assert_eq!(crate::term::Term::new(st.term().unwrap()).text(), "a b\\");
+        assert_eq!(node.to_cql(), cql);
+
+        let left = CqlNode::mk_boolean(
+            "or",
+            Box::new(CqlNode::St(CqlNode::mk_sc("a", "=", Some("1"), Vec::new()))),
+            Box::new(CqlNode::St(CqlNode::mk_sc("b", "=", Some("2"), Vec::new()))),
+            Vec::new(),
+        );
+        let right = left.clone();
+        let bo = CqlNode::mk_boolean("and", Box::new(left), Box::new(right), Vec::new());
+        let root = CqlNode::mk_root(Box::new(bo), Vec::new());
+        assert_eq!(root.to_string(), "a = 1 or b = 2 and (a = 1 or b = 2)");
+    }
+
+    #[test]
+    fn error_nodes() {
+        let mut my = Parser::new();
+        let (node, diagnostics) = my.parse_recovering("a and".chars().borrow_mut());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(node.to_cql(), "a and \"\"");
+    }
+}
@@ -0,0 +1,728 @@
+//! XCQL, the XML form of CQL used by SRU.
+//!
+//! The elements follow the SRU XCQL schema in the
+//! `http://www.loc.gov/zing/cql/xcql/` namespace, as written by YAZ's
+//! `cql2xcql`: a `<searchClause>` or a `<triple>` at the top, with
+//! `<prefixes>` first and `<sortKeys>` last.
+//!
+//! Reading XCQL gives the same tree as parsing the equivalent CQL text,
+//! with context sets resolved from the prefixes in scope. Namespaces are
+//! not checked, so XCQL in the OASIS `searchRetrieve` namespace is read
+//! as well.
+//!
+//! The prefixes of a `<searchClause>` also scope over its index, and
+//! those of the outermost element over the sort keys. A clause whose
+//! index was read outside of its prefix assignments, as in
+//! `ti = (>"http://d" x)`, or sort keys read outside of the top-level
+//! ones, as in `(>"http://d" x) sortby ti`, cannot be written as XCQL
+//! that reads back the same, so writing them fails.
+
+use crate::node::CqlNode;
+use crate::node::Modifier;
+use crate::node::Prefix;
+use crate::node::St;
+use crate::parser::modifier_uri;
+use crate::parser::resolve;
+use crate::parser::resolves;
+use roxmltree::Node;
+use std::fmt;
+
+pub const XCQL_NAMESPACE: &str = "http://www.loc.gov/zing/cql/xcql/";
+
+#[derive(Debug, Clone, Default)]
+pub struct XcqlOptions {
+    pretty: bool,
+}
+
+impl XcqlOptions {
+    pub fn new() -> XcqlOptions {
+        XcqlOptions::default()
+    }
+
+    /// Put each element on a line of its own, indented by two spaces
+    /// per level.
+    pub fn pretty(mut self, pretty: bool) -> XcqlOptions {
+        self.pretty = pretty;
+        self
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+struct Writer<'a> {
+    options: &'a XcqlOptions,
+    level: usize,
+    out: String,
+    /// Prefix assignments of the enclosing elements.
+    prefixes: Vec<Prefix>,
+}
+
+impl Writer<'_> {
+    fn indent(&mut self) {
+        if self.options.pretty {
+            for _ in 0..self.level {
+                self.out.push_str("  ");
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        if self.options.pretty {
+            self.out.push('\n');
+        }
+    }
+
+    fn open(&mut self, name: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(name);
+        if self.level == 0 {
+            self.out.push_str(" xmlns=\"");
+            self.out.push_str(XCQL_NAMESPACE);
+            self.out.push('"');
+        }
+        self.out.push('>');
+        self.newline();
+        self.level += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.level -= 1;
+        self.indent();
+        self.out.push_str("</");
+        self.out.push_str(name);
+        self.out.push('>');
+        self.newline();
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(name);
+        self.out.push('>');
+        escape(value, &mut self.out);
+        self.out.push_str("</");
+        self.out.push_str(name);
+        self.out.push('>');
+        self.newline();
+    }
+
+    fn prefixes(&mut self, prefixes: &[Prefix]) {
+        if prefixes.is_empty() {
+            return;
+        }
+        self.open("prefixes");
+        for p in prefixes {
+            self.open("prefix");
+            if let Some(name) = p.name() {
+                self.text("name", name);
+            }
+            self.text("identifier", p.uri());
+            self.close("prefix");
+        }
+        self.close("prefixes");
+    }
+
+    fn modifiers(&mut self, modifiers: &[Modifier]) {
+        if modifiers.is_empty() {
+            return;
+        }
+        self.open("modifiers");
+        for m in modifiers {
+            self.open("modifier");
+            self.text("type", m.name());
+            if let (Some(relation), Some(value)) = (m.relation(), m.value()) {
+                self.text("comparison", relation);
+                self.text("value", value);
+            }
+            self.close("modifier");
+        }
+        self.close("modifiers");
+    }
+
+    fn sort_keys(&mut self, sort: &[St], path: &str) -> Result<(), XcqlError> {
+        if sort.is_empty() {
+            return Ok(());
+        }
+        if let Some(i) = sort.iter().position(|key| !resolves(&self.prefixes, key)) {
+            let message = "sort key read outside the top-level prefixes";
+            return Err(XcqlError::new(
+                message,
+                &format!("{}/sortKeys/key[{}]", path, i + 1),
+            ));
+        }
+        self.open("sortKeys");
+        for key in sort {
+            self.open("key");
+            self.text("index", key.index());
+            self.modifiers(key.modifiers());
+            self.close("key");
+        }
+        self.close("sortKeys");
+        Ok(())
+    }
+
+    /// Write `node` at `path`, with the sort keys of the query if it is
+    /// the outermost one.
+    fn node(&mut self, node: &CqlNode, sort: &[St], path: &str) -> Result<(), XcqlError> {
+        let mark = self.prefixes.len();
+        match node {
+            CqlNode::St(st) => {
+                let path = format!("{}/searchClause", path);
+                self.prefixes.extend_from_slice(st.prefixes());
+                // clauses built without context sets have none to keep
+                let unresolved = st.index_uri().is_none() && st.relation_uri().is_none();
+                if !unresolved && !resolves(&self.prefixes, st) {
+                    let message = "index read outside the prefixes of its clause";
+                    return Err(XcqlError::new(message, &path));
+                }
+                self.open("searchClause");
+                self.prefixes(st.prefixes());
+                self.text("index", st.index());
+                self.open("relation");
+                self.text("value", st.relation());
+                self.modifiers(st.modifiers());
+                self.close("relation");
+                self.text("term", st.term().unwrap_or(""));
+                self.sort_keys(sort, &path)?;
+                self.close("searchClause");
+            }
+            CqlNode::Boolean(bo) => {
+                let path = format!("{}/triple", path);
+                self.prefixes.extend_from_slice(bo.prefixes());
+                self.open("triple");
+                self.prefixes(bo.prefixes());
+                self.open("boolean");
+                self.text("value", bo.value());
+                self.modifiers(bo.modifiers());
+                self.close("boolean");
+                self.open("leftOperand");
+                self.node(bo.left(), &[], &format!("{}/leftOperand", path))?;
+                self.close("leftOperand");
+                self.open("rightOperand");
+                self.node(bo.right(), &[], &format!("{}/rightOperand", path))?;
+                self.close("rightOperand");
+                self.sort_keys(sort, &path)?;
+                self.close("triple");
+            }
+            CqlNode::Root(root) => self.node(root.search(), root.sort(), path)?,
+            // same as the empty term written by to_cql
+            CqlNode::Error(_) => {
+                let st = CqlNode::mk_sc("cql.serverChoice", "=", Some(""), Vec::new());
+                self.node(&CqlNode::St(st), sort, path)?;
+            }
+        }
+        self.prefixes.truncate(mark);
+        Ok(())
+    }
+}
+
+impl CqlNode {
+    /// Write the tree as compact XCQL. Fails for prefix assignments XCQL
+    /// cannot express, with the path of the element they would go in.
+    pub fn to_xcql(&self) -> Result<String, XcqlError> {
+        self.to_xcql_with(&XcqlOptions::default())
+    }
+
+    pub fn to_xcql_with(&self, options: &XcqlOptions) -> Result<String, XcqlError> {
+        let mut writer = Writer {
+            options,
+            level: 0,
+            out: String::new(),
+            prefixes: Vec::new(),
+        };
+        writer.node(self, &[], "")?;
+        Ok(writer.out)
+    }
+}
+
+/// Error from reading XCQL, with the path of the offending element.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct XcqlError {
+    message: String,
+    path: String,
+}
+
+impl XcqlError {
+    fn new(message: &str, path: &str) -> XcqlError {
+        XcqlError {
+            message: String::from(message),
+            path: String::from(path),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Path of the element in error, such as
+    /// `/triple/leftOperand/searchClause/relation/modifiers/modifier[2]`;
+    /// empty if the text is not well-formed XML.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for XcqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{} at {}", self.message, self.path)
+        }
+    }
+}
+
+impl std::error::Error for XcqlError {}
+
+/// The element children of an XCQL element, taken in schema order.
+struct Children<'a, 'input> {
+    elements: Vec<Node<'a, 'input>>,
+    next: usize,
+    path: &'a str,
+}
+
+impl<'a, 'input> Children<'a, 'input> {
+    fn new(node: Node<'a, 'input>, path: &'a str) -> Result<Children<'a, 'input>, XcqlError> {
+        let mut elements = Vec::new();
+        for child in node.children() {
+            if child.is_element() {
+                elements.push(child);
+            } else if child.is_text() && !child.text().unwrap_or("").trim().is_empty() {
+                return Err(XcqlError::new("unexpected text", path));
+            }
+        }
+        Ok(Children {
+            elements,
+            next: 0,
+            path,
+        })
+    }
+
+    fn take(&mut self, name: &str) -> Option<Node<'a, 'input>> {
+        let node = self.elements.get(self.next)?;
+        if node.tag_name().name() != name {
+            return None;
+        }
+        self.next += 1;
+        Some(*node)
+    }
+
+    fn expect(&mut self, name: &str) -> Result<Node<'a, 'input>, XcqlError> {
+        self.take(name)
+            .ok_or_else(|| XcqlError::new(&format!("missing <{}>", name), self.path))
+    }
+
+    fn finish(self) -> Result<(), XcqlError> {
+        match self.elements.get(self.next) {
+            Some(node) => {
+                let name = node.tag_name().name();
+                let message = format!("unexpected element <{}>", name);
+                Err(XcqlError::new(&message, &format!("{}/{}", self.path, name)))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+struct Reader {
+    prefixes: Vec<Prefix>,
+}
+
+impl Reader {
+    fn text(&self, node: Node, path: &str) -> Result<String, XcqlError> {
+        if let Some(child) = node.children().find(|n| n.is_element()) {
+            let name = child.tag_name().name();
+            let message = format!("unexpected element <{}>", name);
+            return Err(XcqlError::new(&message, &format!("{}/{}", path, name)));
+        }
+        let text: String = node.children().filter_map(|n| n.text()).collect();
+        Ok(text)
+    }
+
+    fn text_child(&self, children: &mut Children, name: &str) -> Result<String, XcqlError> {
+        let node = children.expect(name)?;
+        self.text(node, &format!("{}/{}", children.path, name))
+    }
+
+    fn prefixes(&self, children: &mut Children) -> Result<Vec<Prefix>, XcqlError> {
+        let mut res = Vec::new();
+        let Some(node) = children.take("prefixes") else {
+            return Ok(res);
+        };
+        let path = format!("{}/prefixes", children.path);
+        let mut list = Children::new(node, &path)?;
+        while let Some(node) = list.take("prefix") {
+            let path = format!("{}/prefix[{}]", path, res.len() + 1);
+            let mut prefix = Children::new(node, &path)?;
+            let name = match prefix.take("name") {
+                Some(node) => Some(self.text(node, &format!("{}/name", path))?),
+                None => None,
+            };
+            let uri = self.text_child(&mut prefix, "identifier")?;
+            prefix.finish()?;
+            res.push(Prefix::new(name.as_deref(), &uri));
+        }
+        list.finish()?;
+        Ok(res)
+    }
+
+    fn modifiers(
+        &self,
+        children: &mut Children,
+        default_set: &str,
+    ) -> Result<Vec<Modifier>, XcqlError> {
+        let mut res = Vec::new();
+        let Some(node) = children.take("modifiers") else {
+            return Ok(res);
+        };
+        let path = format!("{}/modifiers", children.path);
+        let mut list = Children::new(node, &path)?;
+        while let Some(node) = list.take("modifier") {
+            let path = format!("{}/modifier[{}]", path, res.len() + 1);
+            let mut modifier = Children::new(node, &path)?;
+            let name = self.text_child(&mut modifier, "type")?;
+            let (relation, value) = match modifier.take("comparison") {
+                Some(node) => {
+                    let relation = self.text(node, &format!("{}/comparison", path))?;
+                    let value = self.text_child(&mut modifier, "value")?;
+                    (Some(relation), Some(value))
+                }
+                None => (None, None),
+            };
+            modifier.finish()?;
+            let m = Modifier::new(&name, relation.as_deref(), value.as_deref())
+                .with_uri(modifier_uri(&self.prefixes, &name, default_set));
+            res.push(m);
+        }
+        list.finish()?;
+        Ok(res)
+    }
+
+    fn sort_keys(&self, children: &mut Children) -> Result<Vec<St>, XcqlError> {
+        let mut res = Vec::new();
+        let Some(node) = children.take("sortKeys") else {
+            return Ok(res);
+        };
+        let path = format!("{}/sortKeys", children.path);
+        let mut list = Children::new(node, &path)?;
+        while let Some(node) = list.take("key") {
+            let path = format!("{}/key[{}]", path, res.len() + 1);
+            let mut key = Children::new(node, &path)?;
+            let index = self.text_child(&mut key, "index")?;
+            let modifiers = self.modifiers(&mut key, "sort")?;
+            key.finish()?;
+            let st = CqlNode::mk_sc(&index, "", None, modifiers);
+            res.push(resolve(&self.prefixes, st, &index, ""));
+        }
+        list.finish()?;
+        Ok(res)
+    }
+
+    /// Read the searchClause or triple `node`. Sort keys are only read
+    /// for the outermost one, where they are returned in `sort`.
+    fn node(
+        &mut self,
+        node: Node,
+        path: &str,
+        sort: Option<&mut Vec<St>>,
+    ) -> Result<CqlNode, XcqlError> {
+        let mut children = Children::new(node, path)?;
+        let prefixes = self.prefixes(&mut children)?;
+        let mark = self.prefixes.len();
+        self.prefixes.extend(prefixes.iter().cloned());
+        let mut res = match node.tag_name().name() {
+            "searchClause" => {
+                let index = self.text_child(&mut children, "index")?;
+                let node = children.expect("relation")?;
+                let relation_path = format!("{}/relation", path);
+                let mut relation = Children::new(node, &relation_path)?;
+                let value = self.text_child(&mut relation, "value")?;
+                let modifiers = self.modifiers(&mut relation, "cql")?;
+                relation.finish()?;
+                let term = self.text_child(&mut children, "term")?;
+                let st = CqlNode::mk_sc(&index, &value, Some(&term), modifiers);
+                CqlNode::St(resolve(&self.prefixes, st, &index, &value))
+            }
+            "triple" => {
+                let node = children.expect("boolean")?;
+                let boolean_path = format!("{}/boolean", path);
+                let mut boolean = Children::new(node, &boolean_path)?;
+                let value = self.text_child(&mut boolean, "value")?;
+                let modifiers = self.modifiers(&mut boolean, "cql")?;
+                boolean.finish()?;
+                let left = self.operand(&mut children, "leftOperand")?;
+                let right = self.operand(&mut children, "rightOperand")?;
+                CqlNode::mk_boolean(&value, Box::new(left), Box::new(right), modifiers)
+            }
+            name => {
+                let message = format!("expected <searchClause> or <triple>, found <{}>", name);
+                return Err(XcqlError::new(&message, path));
+            }
+        };
+        if let Some(sort) = sort {
+            *sort = self.sort_keys(&mut children)?;
+        }
+        children.finish()?;
+        self.prefixes.truncate(mark);
+        res.add_prefixes(prefixes);
+        Ok(res)
+    }
+
+    fn operand(&mut self, children: &mut Children, name: &str) -> Result<CqlNode, XcqlError> {
+        let node = children.expect(name)?;
+        let path = format!("{}/{}", children.path, name);
+        let mut operand = Children::new(node, &path)?;
+        let Some(&inner) = operand.elements.first() else {
+            let message = "expected <searchClause> or <triple>";
+            return Err(XcqlError::new(message, &path));
+        };
+        operand.next = 1;
+        operand.finish()?;
+        let inner_path = format!("{}/{}", path, inner.tag_name().name());
+        self.node(inner, &inner_path, None)
+    }
+}
+
+impl CqlNode {
+    /// Read a query written as XCQL, with a `<searchClause>` or
+    /// `<triple>` as the document element.
+    pub fn from_xcql(xml: &str) -> Result<CqlNode, XcqlError> {
+        let doc = roxmltree::Document::parse(xml)
+            .map_err(|err| XcqlError::new(&format!("invalid XML: {}", err), ""))?;
+        let root = doc.root_element();
+        let path = format!("/{}", root.tag_name().name());
+        let mut reader = Reader {
+            prefixes: Vec::new(),
+        };
+        let mut sort = Vec::new();
+        let search = reader.node(root, &path, Some(&mut sort))?;
+        Ok(CqlNode::mk_root(Box::new(search), sort))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pretty(query: &str) -> String {
+        let node = crate::parse(query).unwrap();
+        node.to_xcql_with(&XcqlOptions::new().pretty(true)).unwrap()
+    }
+
+    #[test]
+    fn search_clause() {
+        assert_eq!(
+            crate::parse("ti = \"a & b\"").unwrap().to_xcql().unwrap(),
+            "<searchClause xmlns=\"http://www.loc.gov/zing/cql/xcql/\">\
+             <index>ti</index><relation><value>=</value></relation>\
+             <term>a &amp; b</term></searchClause>"
+        );
+        assert_eq!(
+            pretty(">dc=\"http://dc\" dc.ti any/cql.word/x<3 \"<y>\""),
+            r#"<searchClause xmlns="http://www.loc.gov/zing/cql/xcql/">
+  <prefixes>
+    <prefix>
+      <name>dc</name>
+      <identifier>http://dc</identifier>
+    </prefix>
+  </prefixes>
+  <index>dc.ti</index>
+  <relation>
+    <value>any</value>
+    <modifiers>
+      <modifier>
+        <type>cql.word</type>
+      </modifier>
+      <modifier>
+        <type>x</type>
+        <comparison>&lt;</comparison>
+        <value>3</value>
+      </modifier>
+    </modifiers>
+  </relation>
+  <term>&lt;y&gt;</term>
+</searchClause>
+"#
+        );
+    }
+
+    fn round_trip(query: &str) {
+        let node = crate::parse(query).unwrap();
+        assert_eq!(
+            CqlNode::from_xcql(&node.to_xcql().unwrap()),
+            Ok(node.clone())
+        );
+        let xml = node.to_xcql_with(&XcqlOptions::new().pretty(true)).unwrap();
+        assert_eq!(CqlNode::from_xcql(&xml), Ok(node));
+    }
+
+    fn write_error(query: &str) -> XcqlError {
+        crate::parse(query).unwrap().to_xcql().unwrap_err()
+    }
+
+    fn read_error(xml: &str) -> XcqlError {
+        CqlNode::from_xcql(xml).unwrap_err()
+    }
+
+    #[test]
+    fn read() {
+        round_trip("a");
+        round_trip("ti = \"a & <b>\"");
+        round_trip("ti any/cql.word/x<3 \"\"");
+        round_trip("a and b or (c prox/unit=word/distance<=2 d) sortby ti/sort.descending au");
+        round_trip(">dc=\"http://dc\" dc.ti = x and (>\"http://d\" y or dc.au = z) sortby dc.date");
+        round_trip(">sort=\"http://s\" a sortby ti/ascending");
+        // inner prefixes that leave the index as read outside them
+        round_trip("ti = (>dc=\"http://dc\" x) and (>\"http://d\" y or dc.ti = z)");
+        round_trip("(>\"http://d\" a) sortby dc.ti/sort.descending");
+
+        let xml = r#"<searchClause xmlns="http://docs.oasis-open.org/ns/search-ws/xcql">
+            <index>dc.title</index>
+            <relation><value>=</value></relation>
+            <term> x </term>
+        </searchClause>"#;
+        let node = CqlNode::from_xcql(xml).unwrap();
+        assert_eq!(node, crate::parse("dc.title = \" x \"").unwrap());
+    }
+
+    #[test]
+    fn read_errors() {
+        let e = read_error("<searchClause>");
+        assert!(e.message().starts_with("invalid XML"));
+        assert_eq!(e.path(), "");
+
+        let e = read_error("<query/>");
+        assert_eq!(
+            e.message(),
+            "expected <searchClause> or <triple>, found <query>"
+        );
+        assert_eq!(e.path(), "/query");
+
+        let e = read_error("<searchClause><index>ti</index><term>x</term></searchClause>");
+        assert_eq!(e.to_string(), "missing <relation> at /searchClause");
+
+        let e = read_error(
+            "<triple><boolean><value>and</value></boolean>\
+             <leftOperand><searchClause><index>a</index><relation><value>=</value>\
+             <modifiers><modifier><type>x</type></modifier>\
+             <modifier><type>y</type><comparison>=</comparison></modifier>\
+             </modifiers></relation><term>b</term></searchClause></leftOperand>\
+             <rightOperand/></triple>",
+        );
+        assert_eq!(e.message(), "missing <value>");
+        assert_eq!(
+            e.path(),
+            "/triple/leftOperand/searchClause/relation/modifiers/modifier[2]"
+        );
+
+        let e = read_error(
+            "<triple><boolean><value>and</value></boolean>\
+             <leftOperand><searchClause><index>a</index><relation><value>=</value>\
+             </relation><term>b</term><sortKeys/></searchClause></leftOperand>\
+             <rightOperand/></triple>",
+        );
+        assert_eq!(e.message(), "unexpected element <sortKeys>");
+        assert_eq!(e.path(), "/triple/leftOperand/searchClause/sortKeys");
+
+        let e = read_error(
+            "<triple><boolean><value>and</value></boolean>\
+             <leftOperand><searchClause><index>a</index><relation><value>=</value>\
+             </relation><term>b<x/></term></searchClause></leftOperand>\
+             <rightOperand/></triple>",
+        );
+        assert_eq!(e.path(), "/triple/leftOperand/searchClause/term/x");
+
+        let e = read_error(
+            "<triple><boolean><value>and</value></boolean>\
+             <leftOperand><searchClause><index>a</index><relation><value>=</value>\
+             </relation><term>b</term></searchClause></leftOperand>\
+             <rightOperand>c</rightOperand></triple>",
+        );
+        assert_eq!(e.to_string(), "unexpected text at /triple/rightOperand");
+    }
+
+    #[test]
+    fn write_errors() {
+        let e = write_error("ti = (>\"http://d\" x)");
+        assert_eq!(
+            e.to_string(),
+            "index read outside the prefixes of its clause at /searchClause"
+        );
+        let e = write_error("a or dc.ti = (>dc=\"http://dc\" x or y)");
+        assert_eq!(
+            e.path(),
+            "/triple/rightOperand/triple/leftOperand/searchClause"
+        );
+        let e = write_error("(>\"http://d\" a) sortby dc.x ti");
+        assert_eq!(e.message(), "sort key read outside the top-level prefixes");
+        assert_eq!(e.path(), "/searchClause/sortKeys/key[2]");
+
+        // built without context sets, so there are none to keep
+        let mut node = CqlNode::St(CqlNode::mk_sc("dc.ti", "=", Some("x"), Vec::new()));
+        node.add_prefixes(vec![Prefix::new(Some("dc"), "http://dc")]);
+        assert!(node.to_xcql().is_ok());
+    }
+
+    #[test]
+    fn triple() {
+        assert_eq!(
+            pretty("a prox/unit=word (>\"http://d\" b) sortby ti/sort.descending"),
+            r#"<triple xmlns="http://www.loc.gov/zing/cql/xcql/">
+  <boolean>
+    <value>prox</value>
+    <modifiers>
+      <modifier>
+        <type>unit</type>
+        <comparison>=</comparison>
+        <value>word</value>
+      </modifier>
+    </modifiers>
+  </boolean>
+  <leftOperand>
+    <searchClause>
+      <index>cql.serverChoice</index>
+      <relation>
+        <value>=</value>
+      </relation>
+      <term>a</term>
+    </searchClause>
+  </leftOperand>
+  <rightOperand>
+    <searchClause>
+      <prefixes>
+        <prefix>
+          <identifier>http://d</identifier>
+        </prefix>
+      </prefixes>
+      <index>cql.serverChoice</index>
+      <relation>
+        <value>=</value>
+      </relation>
+      <term>b</term>
+    </searchClause>
+  </rightOperand>
+  <sortKeys>
+    <key>
+      <index>ti</index>
+      <modifiers>
+        <modifier>
+          <type>sort.descending</type>
+        </modifier>
+      </modifiers>
+    </key>
+  </sortKeys>
+</triple>
+"#
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Translation of CQL to XPath predicates.
+//!
+//! Indexes are mapped to relative location paths with
+//! [`XPathTransform::path`], such as `dc:title` or
+//! `marc:datafield[@tag='245']/marc:subfield`, and each search clause
+//! tests whether any node on the path matches:
+//!
+//! | CQL             | XPath                                   |
+//! |-----------------|-----------------------------------------|
+//! | `ti = a`        | `dc:title[contains(LC(.), 'a')]`        |
+//! | `ti = ^a*`      | `dc:title[starts-with(LC(.), 'a')]`     |
+//! | `ti == a`       | `dc:title[. = 'a']`                     |
+//! | `ti == *a*`     | `dc:title[contains(., 'a')]`            |
+//! | `ti all "a b"`  | `dc:title[contains(..) and ...]`        |
+//! | `year < 2000`   | `year[number(.) < 2000]`                |
+//! | `ti <> a`       | `dc:title[. != 'a']`                    |
+//! | `a not b`       | `a and not(b)`                          |
+//!
+//! `LC(.)` is `translate()` from ASCII upper case in XPath 1.0, and
+//! `lower-case(.)` in XPath 2.0. Masking is limited to `*` at the start
+//! or end of a term in XPath 1.0; XPath 2.0 uses `matches()` for other
+//! masking. Ordering relations compare numbers. Sort keys are ignored.
+
+use crate::node::Boolean;
+use crate::node::CqlNode;
+use crate::term::is_masked;
+use crate::term::regex;
+use crate::term::text;
+use crate::term::trim_stars;
+use crate::term::TermChar;
+use crate::translate::infix;
+use crate::translate::BooleanOp;
+use crate::translate::Clause;
+use crate::translate::Mapping;
+use crate::translate::QueryTranslator;
+use crate::translate::TranslationError;
+use crate::translate::TranslationErrorKind;
+
+/// XPath version of the generated expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum XPathVersion {
+    #[default]
+    V1,
+    /// XPath 2.0 and later, with `lower-case()`, `ends-with()` and
+    /// `matches()`.
+    V2,
+}
+
+/// Whether `s` is an XPath 1.0 number, such as `-12.5`.
+fn is_number(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = digits.splitn(2, '.');
+    let int = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("");
+    (!int.is_empty() || !frac.is_empty())
+        && int.chars().all(|ch| ch.is_ascii_digit())
+        && frac.chars().all(|ch| ch.is_ascii_digit())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct XPathTransform {
+    version: XPathVersion,
+    paths: Mapping<String>,
+}
+
+impl XPathTransform {
+    pub fn new(version: XPathVersion) -> XPathTransform {
+        XPathTransform {
+            version,
+            ..XPathTransform::default()
+        }
+    }
+
+    /// Search `index` in the nodes selected by the relative location path
+    /// `path`. `index` is matched as by [`Mapping`].
+    pub fn path(&mut self, index: &str, path: &str) {
+        self.paths.insert(index, String::from(path));
+    }
+
+    /// Search relation `name` as `relation`, as by
+    /// [`Mapping::insert_relation`].
+    pub fn relation(&mut self, name: &str, relation: &str) {
+        self.paths.insert_relation(name, relation);
+    }
+
+    /// `s` as a string literal. XPath 1.0 literals have no escapes, so
+    /// strings with both kinds of quote are built with `concat()`.
+    fn literal(&self, s: &str) -> String {
+        if self.version == XPathVersion::V2 {
+            return format!("'{}'", s.replace('\'', "''"));
+        }
+        if !s.contains('\'') {
+            return format!("'{}'", s);
+        }
+        if !s.contains('"') {
+            return format!("\"{}\"", s);
+        }
+        let parts: Vec<String> = s.split('\'').map(|part| format!("'{}'", part)).collect();
+        format!("concat({})", parts.join(", \"'\", "))
+    }
+
+    /// The context node, in lower case if `ignore_case`.
+    fn value(&self, ignore_case: bool) -> &'static str {
+        match (ignore_case, self.version) {
+            (false, _) => ".",
+            (true, XPathVersion::V1) => {
+                "translate(., 'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz')"
+            }
+            (true, XPathVersion::V2) => "lower-case(.)",
+        }
+    }
+
+    /// Test of the context node matching `chars` of the term of
+    /// `clause`, anchored at the start and end as given by [`trim_stars`].
+    fn matches(
+        &self,
+        clause: &Clause<'_>,
+        (chars, start, end): (&[TermChar], bool, bool),
+        ignore_case: bool,
+    ) -> Result<String, TranslationError> {
+        if is_masked(chars) {
+            if self.version == XPathVersion::V1 {
+                return Err(clause.error(TranslationErrorKind::UnsupportedMasking));
+            }
+            let pattern = regex(chars, start, end);
+            let flags = if ignore_case { ", 'i'" } else { "" };
+            return Ok(format!("matches(., {}{})", self.literal(&pattern), flags));
+        }
+        let mut s = text(chars);
+        if ignore_case {
+            s = match self.version {
+                XPathVersion::V1 => s.to_ascii_lowercase(),
+                XPathVersion::V2 => s.to_lowercase(),
+            };
+        }
+        let value = self.value(ignore_case);
+        let literal = self.literal(&s);
+        Ok(match (start, end, self.version) {
+            (true, true, _) => format!("{} = {}", value, literal),
+            (true, false, _) => format!("starts-with({}, {})", value, literal),
+            (false, true, XPathVersion::V2) => format!("ends-with({}, {})", value, literal),
+            (false, true, XPathVersion::V1) => format!(
+                "substring({}, string-length(.) - {}) = {}",
+                value,
+                s.chars().count().saturating_sub(1),
+                literal
+            ),
+            (false, false, _) => format!("contains({}, {})", value, literal),
+        })
+    }
+
+    /// Test of the context node for the relation and term of `clause`.
+    fn test(&self, relation: &str, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        let term = clause.term();
+        match relation {
+            "=" | "adj" | "scr" => self.matches(clause, term.trim_stars(), true),
+            "all" | "any" => {
+                clause.unanchored()?;
+                let words: Result<Vec<String>, TranslationError> = clause
+                    .words()?
+                    .iter()
+                    .map(|w| self.matches(clause, trim_stars(w, false, false), true))
+                    .collect();
+                let words = words?;
+                let op = if relation == "all" { " and " } else { " or " };
+                Ok(words.join(op))
+            }
+            "==" | "exact" => {
+                clause.unanchored()?;
+                self.matches(clause, trim_stars(&term.chars, true, true), false)
+            }
+            "<>" => {
+                clause.unanchored()?;
+                clause.unmasked()?;
+                Ok(format!(". != {}", self.literal(&term.text())))
+            }
+            "<" | "<=" | ">" | ">=" => {
+                clause.unanchored()?;
+                clause.unmasked()?;
+                let number = term.text();
+                if !is_number(&number) {
+                    return Err(clause.error(TranslationErrorKind::InvalidNumber));
+                }
+                Ok(format!("number(.) {} {}", relation, number))
+            }
+            _ => Err(clause.error(TranslationErrorKind::UnsupportedRelation)),
+        }
+    }
+
+    fn search_clause(&self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        clause.no_modifiers()?;
+        let relation = self.paths.relation(clause)?;
+        let path = self
+            .paths
+            .get(clause.index())
+            .ok_or_else(|| clause.error(TranslationErrorKind::UnsupportedIndex))?;
+        Ok(format!("{}[{}]", path, self.test(&relation, clause)?))
+    }
+
+    /// Translate the search part of a query to a predicate expression,
+    /// relative to the record element, such as `record[...]`.
+    pub fn to_xpath(&self, node: &CqlNode) -> Result<String, TranslationError> {
+        Translator { transform: self }.translate(node)
+    }
+}
+
+struct Translator<'a> {
+    transform: &'a XPathTransform,
+}
+
+impl QueryTranslator for Translator<'_> {
+    type Output = String;
+    type Error = TranslationError;
+
+    fn clause(&mut self, clause: &Clause<'_>) -> Result<String, TranslationError> {
+        self.transform.search_clause(clause)
+    }
+
+    fn boolean(
+        &mut self,
+        bo: &Boolean,
+        op: BooleanOp,
+        mut operands: Vec<(&CqlNode, String)>,
+    ) -> Result<String, TranslationError> {
+        match op {
+            BooleanOp::And => Ok(infix(operands, " and ")),
+            BooleanOp::Or => Ok(infix(operands, " or ")),
+            BooleanOp::Not => {
+                let right = operands.pop().map(|(_, s)| s).unwrap_or_default();
+                Ok(format!("{} and not({})", infix(operands, ""), right))
+            }
+            BooleanOp::Prox => Err(TranslationError::boolean(bo)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LC: &str = "translate(., 'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz')";
+
+    fn transform(version: XPathVersion) -> XPathTransform {
+        let mut transform = XPathTransform::new(version);
+        transform.path("dc.title", "dc:title");
+        transform.path(
+            "creator",
+            "marc:datafield[@tag='100']/marc:subfield[@code='a']",
+        );
+        transform.path("year", "dc:date");
+        transform
+    }
+
+    fn xpath(version: XPathVersion, query: &str) -> Result<String, TranslationError> {
+        transform(version).to_xpath(&crate::parse(query).unwrap())
+    }
+
+    fn v1(query: &str) -> Result<String, TranslationError> {
+        xpath(XPathVersion::V1, query)
+    }
+
+    fn v2(query: &str) -> Result<String, TranslationError> {
+        xpath(XPathVersion::V2, query)
+    }
+
+    #[test]
+    fn search_clauses() {
+        assert_eq!(
+            v1("dc.title = Hobbit").unwrap(),
+            format!("dc:title[contains({}, 'hobbit')]", LC)
+        );
+        assert_eq!(
+            v1("dc.title = ^the*").unwrap(),
+            format!("dc:title[starts-with({}, 'the')]", LC)
+        );
+        assert_eq!(
+            v1("dc.title = ring^").unwrap(),
+            format!("dc:title[substring({}, string-length(.) - 3) = 'ring']", LC)
+        );
+        assert_eq!(
+            v1("creator == Tolkien").unwrap(),
+            "marc:datafield[@tag='100']/marc:subfield[@code='a'][. = 'Tolkien']"
+        );
+        assert_eq!(
+            v1("dc.title == *ring*").unwrap(),
+            "dc:title[contains(., 'ring')]"
+        );
+        assert_eq!(
+            v1("dc.title all \"lord ring\"").unwrap(),
+            format!(
+                "dc:title[contains({}, 'lord') and contains({}, 'ring')]",
+                LC, LC
+            )
+        );
+        assert_eq!(v1("year < 1950").unwrap(), "dc:date[number(.) < 1950]");
+        assert_eq!(v1("year >= -1.5").unwrap(), "dc:date[number(.) >= -1.5]");
+        assert_eq!(v1("dc.title <> a").unwrap(), "dc:title[. != 'a']");
+    }
+
+    #[test]
+    fn version_2() {
+        assert_eq!(
+            v2("dc.title = Hobbit").unwrap(),
+            "dc:title[contains(lower-case(.), 'hobbit')]"
+        );
+        assert_eq!(
+            v2("dc.title = ring^").unwrap(),
+            "dc:title[ends-with(lower-case(.), 'ring')]"
+        );
+        assert_eq!(
+            v2("dc.title = ^lo?d*ring").unwrap(),
+            "dc:title[matches(., '^lo.d.*ring', 'i')]"
+        );
+        assert_eq!(
+            v2("dc.title == \"a.b?\"").unwrap(),
+            "dc:title[matches(., '^a\\.b.$')]"
+        );
+        assert_eq!(
+            v2("dc.title == \"O'Brien\"").unwrap(),
+            "dc:title[. = 'O''Brien']"
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            v1("dc.title == \"O'Brien\"").unwrap(),
+            "dc:title[. = \"O'Brien\"]"
+        );
+        assert_eq!(
+            v1("dc.title == \"say \\\"hi\\\"\"").unwrap(),
+            "dc:title[. = 'say \"hi\"']"
+        );
+        assert_eq!(
+            v1("dc.title == \"it's \\\"x\\\"\"").unwrap(),
+            "dc:title[. = concat('it', \"'\", 's \"x\"')]"
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(
+            v1("dc.title == a or dc.title == b and year > 1").unwrap(),
+            "(dc:title[. = 'a'] or dc:title[. = 'b']) and dc:date[number(.) > 1]"
+        );
+        assert_eq!(
+            v1("dc.title == a not (dc.title == b or dc.title == c)").unwrap(),
+            "dc:title[. = 'a'] and not(dc:title[. = 'b'] or dc:title[. = 'c'])"
+        );
+        assert_eq!(
+            v1("dc.title == a prox dc.title == b").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedBoolean, "prox")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            v1("dc.title any \" \"").unwrap_err().parts(),
+            (TranslationErrorKind::EmptyTerm, " ")
+        );
+        assert_eq!(
+            v1("dc.title = a?c").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "a?c")
+        );
+        assert_eq!(
+            v1("year < 19*").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedMasking, "19*")
+        );
+        assert_eq!(
+            v1("year < soon").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidNumber, "soon")
+        );
+        assert_eq!(
+            v1("year < 1e3").unwrap_err().parts(),
+            (TranslationErrorKind::InvalidNumber, "1e3")
+        );
+        assert_eq!(
+            v1("dc.title == ^a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedAnchoring, "^a")
+        );
+        assert_eq!(
+            v1("dc.subject = a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedIndex, "dc.subject")
+        );
+        assert_eq!(
+            v1("dc.title =/stem a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelationModifier, "stem")
+        );
+        assert_eq!(
+            v1("dc.title cql.within a").unwrap_err().parts(),
+            (TranslationErrorKind::UnsupportedRelation, "cql.within")
+        );
+    }
+}